@@ -1,11 +1,14 @@
+use std::cell::RefCell;
+
 use glib::{ExitCode, clone};
 use log::{debug, error, info};
 
 use gettextrs::gettext;
 use gtk::{gio, glib, prelude::*, subclass::prelude::*};
 
+use crate::cli::{self, CliOptions};
 use crate::config::{APP_ID, PKGDATADIR, PROFILE, VERSION};
-use crate::input_file::InputFile;
+use crate::service::ConversionService;
 use crate::window::AppWindow;
 
 mod imp {
@@ -18,6 +21,7 @@ mod imp {
     #[derive(Debug)]
     pub struct App {
         pub settings: gio::Settings,
+        pub service: RefCell<Option<ConversionService>>,
     }
 
     #[glib::object_subclass]
@@ -29,6 +33,7 @@ mod imp {
         fn new() -> Self {
             Self {
                 settings: gio::Settings::new(APP_ID),
+                service: RefCell::default(),
             }
         }
     }
@@ -47,6 +52,180 @@ mod imp {
                 &gettext("Open a new window"),
                 None,
             );
+            obj.add_main_option(
+                "format",
+                glib::Char::from(b'f'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext("Convert the given files to this format without opening a window"),
+                Some("FORMAT"),
+            );
+            obj.add_main_option(
+                "output",
+                glib::Char::from(b'o'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::Filename,
+                &gettext("Directory or archive (.zip, .tar.gz, .tar.zst, .7z) to save converted files in"),
+                Some("PATH"),
+            );
+            obj.add_main_option(
+                "watch",
+                glib::Char::from(b'W'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::Filename,
+                &gettext("Keep converting images added to this folder into the output folder"),
+                Some("FOLDER"),
+            );
+            obj.add_main_option(
+                "preset",
+                glib::Char::from(b'P'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext("Convert with a saved preset, other options override its values"),
+                Some("NAME"),
+            );
+            obj.add_main_option(
+                "quality",
+                glib::Char::from(b'q'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::Int,
+                &gettext("Quality of lossy formats, from 1 to 100"),
+                Some("QUALITY"),
+            );
+            obj.add_main_option(
+                "resize",
+                glib::Char::from(b'r'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext("Resize by a percentage (50%) or to exact pixels (1920x1080)"),
+                Some("SIZE"),
+            );
+            obj.add_main_option(
+                "pixel-art",
+                glib::Char::from(b'p'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::None,
+                &gettext("Use the pixel art resize algorithm"),
+                None,
+            );
+            obj.add_main_option(
+                "background",
+                glib::Char::from(b'b'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext("Color replacing the transparency layer"),
+                Some("#RRGGBB[AA]"),
+            );
+            obj.add_main_option(
+                "dpi",
+                glib::Char::from(b'd'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::Int,
+                &gettext("Pixel density per inch of PDF pages"),
+                Some("DPI"),
+            );
+            obj.add_main_option(
+                "metadata",
+                glib::Char::from(b'M'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext(
+                    "Keep all metadata (keep), remove it (strip), keep the copyright only (copyright) or keep the EXIF data without the location and device (exif)",
+                ),
+                Some("POLICY"),
+            );
+            obj.add_main_option(
+                "name-template",
+                glib::Char::from(b't'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext("Output file names, e.g. \"{stem}-{width}x{height}-{index:03}.{ext}\""),
+                Some("TEMPLATE"),
+            );
+            obj.add_main_option(
+                "on-conflict",
+                glib::Char::from(b'c'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext("What to do with existing files: overwrite, skip or rename"),
+                Some("POLICY"),
+            );
+            obj.add_main_option(
+                "compression-level",
+                glib::Char::from(b'z'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::Int,
+                &gettext("Compression level of archives, from 0 (fastest) to 9 (smallest)"),
+                Some("LEVEL"),
+            );
+            obj.add_main_option(
+                "mirror",
+                glib::Char::from(b'm'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::String,
+                &gettext(
+                    "Skip images whose output is up to date, compared by modification time (mtime) or content (hash), or never (off)",
+                ),
+                Some("MODE"),
+            );
+            obj.add_main_option(
+                "delete-orphans",
+                glib::Char::from(b'D'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::None,
+                &gettext(
+                    "Delete outputs whose image no longer exists when skipping up to date images",
+                ),
+                None,
+            );
+            obj.add_main_option(
+                "keep-folders",
+                glib::Char::from(b'k'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::None,
+                &gettext("Recreate the folders images were imported from in the output"),
+                None,
+            );
+            obj.add_main_option(
+                "include",
+                glib::Char::from(b'i'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::StringArray,
+                &gettext("Only import files matching this pattern from folders"),
+                Some("PATTERN"),
+            );
+            obj.add_main_option(
+                "exclude",
+                glib::Char::from(b'x'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::StringArray,
+                &gettext("Skip files and folders matching this pattern in folders"),
+                Some("PATTERN"),
+            );
+            obj.add_main_option(
+                "hidden",
+                glib::Char::from(b'H'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::None,
+                &gettext("Import hidden files from folders"),
+                None,
+            );
+            obj.add_main_option(
+                "jobs",
+                glib::Char::from(b'j'),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::Int,
+                &gettext("Number of images converted at the same time, one per CPU by default"),
+                Some("JOBS"),
+            );
+            obj.add_main_option(
+                glib::OPTION_REMAINING,
+                glib::Char::from(0),
+                glib::OptionFlags::NONE,
+                glib::OptionArg::FilenameArray,
+                "",
+                Some(&gettext("[FILES…]")),
+            );
 
             obj.setup_gactions();
             obj.setup_accels();
@@ -54,6 +233,24 @@ mod imp {
     }
 
     impl ApplicationImpl for App {
+        fn dbus_register(
+            &self,
+            connection: &gio::DBusConnection,
+            object_path: &str,
+        ) -> Result<(), glib::Error> {
+            self.parent_dbus_register(connection, object_path)?;
+
+            let service =
+                ConversionService::register(&*self.obj(), connection, object_path, &self.settings)?;
+            self.service.replace(Some(service));
+            Ok(())
+        }
+
+        fn dbus_unregister(&self, connection: &gio::DBusConnection, object_path: &str) {
+            self.service.take();
+            self.parent_dbus_unregister(connection, object_path);
+        }
+
         fn activate(&self) {
             debug!("Application::activate");
             self.parent_activate();
@@ -72,24 +269,28 @@ mod imp {
 
             // Set icons for shell
             gtk::Window::set_default_icon_name(APP_ID);
+
+            // Started through D-Bus activation, give callers time to queue
+            // conversions before exiting for lack of windows.
+            let application = self.obj();
+            if application
+                .flags()
+                .contains(gio::ApplicationFlags::IS_SERVICE)
+            {
+                application.set_inactivity_timeout(10_000);
+            }
         }
 
         fn open(&self, files: &[gio::File], _hint: &str) {
             debug!("Application::open");
 
-            let files: Vec<Option<InputFile>> = files
-                .iter()
-                .filter(|file| file.query_exists(gio::Cancellable::NONE))
-                .map(InputFile::new)
-                .collect();
-
             let application = self.obj();
             application.present_main_window();
             if let Some(window) = application.active_window() {
                 window
                     .downcast_ref::<AppWindow>()
                     .unwrap()
-                    .open_files(files);
+                    .import_files(files.to_vec());
             }
         }
 
@@ -100,6 +301,38 @@ mod imp {
             debug!("Application::handle_local_options");
 
             let application = self.obj();
+
+            match CliOptions::from_options(options, &self.settings) {
+                Ok(Some(cli_options)) => {
+                    return std::ops::ControlFlow::Break(cli::run(cli_options));
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    eprintln!("{err}");
+                    return std::ops::ControlFlow::Break(cli::EXIT_USAGE);
+                }
+            }
+
+            // Registering the remaining arguments means GIO no longer opens
+            // them on its own.
+            let files = options
+                .lookup::<Vec<std::path::PathBuf>>(glib::OPTION_REMAINING)
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            if !files.is_empty() {
+                if let Err(err) = application.register(None::<&gio::Cancellable>) {
+                    error!("Failed to register the application: {err}");
+                }
+
+                let files = files.iter().map(gio::File::for_path).collect::<Vec<_>>();
+                application.open(&files, "");
+
+                if application.is_remote() {
+                    return std::ops::ControlFlow::Break(glib::ExitCode::SUCCESS);
+                }
+            }
+
             if options.contains("new-window") {
                 if let Err(err) = application.register(None::<&gio::Cancellable>) {
                     error!("Failed to register the application: {err}");
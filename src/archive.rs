@@ -0,0 +1,349 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+use crate::filetypes::{CompressionType, FileType};
+
+/// How much may be extracted from an archive, so that a small archive
+/// cannot fill the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractLimits {
+    /// Most bytes of a single image.
+    pub entry_size: u64,
+    /// Most bytes of every image together.
+    pub total_size: u64,
+    /// Most images.
+    pub entries: usize,
+}
+
+/// The limit an archive went over, carried by the [`io::Error`] stopping
+/// the extraction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// An image, or every image together, holds `size` bytes, more than
+    /// `limit`.
+    Size { size: u64, limit: u64 },
+    /// The archive holds more than `limit` images.
+    Entries { limit: usize },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Size { size, limit } => write!(f, "{size} bytes, over {limit}"),
+            LimitExceeded::Entries { limit } => write!(f, "more than {limit} images"),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Extracts every supported image of the archive at `archive` into
+/// `destination`, keeping their paths inside it. Returns those paths,
+/// relative to `destination`. Extracting stops with a [`LimitExceeded`]
+/// error once the images go over `limits`.
+pub fn extract_images(
+    archive: &Path,
+    kind: CompressionType,
+    destination: &Path,
+    limits: ExtractLimits,
+) -> io::Result<Vec<PathBuf>> {
+    let extraction = Extraction {
+        destination,
+        limits,
+        extracted: vec![],
+        total_size: 0,
+    };
+    match kind {
+        CompressionType::Zip => extract_zip(archive, extraction),
+        CompressionType::TarGz => extract_tar(GzDecoder::new(File::open(archive)?), extraction),
+        CompressionType::TarZst => {
+            extract_tar(zstd::Decoder::new(File::open(archive)?)?, extraction)
+        }
+        CompressionType::SevenZ => extract_7z(archive, extraction),
+        CompressionType::Directory => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+    }
+}
+
+fn extract_zip(archive: &Path, mut extraction: Extraction) -> io::Result<Vec<PathBuf>> {
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let Some(path) = entry
+            .enclosed_name()
+            .filter(|p| entry.is_file() && is_image(p))
+        else {
+            continue;
+        };
+        let size = entry.size();
+        extraction.write(&mut entry, path, size)?;
+    }
+    Ok(extraction.extracted)
+}
+
+fn extract_tar<R: Read>(reader: R, mut extraction: Extraction) -> io::Result<Vec<PathBuf>> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(path) = enclosed_path(&entry.path()?).filter(|p| is_image(p)) else {
+            continue;
+        };
+        let size = entry.size();
+        extraction.write(&mut entry, path, size)?;
+    }
+    Ok(extraction.extracted)
+}
+
+fn extract_7z(archive: &Path, mut extraction: Extraction) -> io::Result<Vec<PathBuf>> {
+    let mut reader = SevenZReader::open(archive, Password::empty()).map_err(io::Error::other)?;
+    // Kept apart from the errors of the reader, which lose what went over
+    // the limits.
+    let mut failure = None;
+    reader
+        .for_each_entries(|entry, data| {
+            let path = enclosed_path(Path::new(entry.name()))
+                .filter(|p| !entry.is_directory() && is_image(p));
+            match path {
+                Some(path) => {
+                    if let Err(e) = extraction.write(data, path, entry.size()) {
+                        failure = Some(e);
+                        return Ok(false);
+                    }
+                }
+                // Entries share one stream, skipping one still means reading it.
+                None => {
+                    io::copy(data, &mut io::sink())?;
+                }
+            }
+            Ok(true)
+        })
+        .map_err(io::Error::other)?;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(extraction.extracted),
+    }
+}
+
+/// The images extracted so far from an archive.
+struct Extraction<'a> {
+    destination: &'a Path,
+    limits: ExtractLimits,
+    extracted: Vec<PathBuf>,
+    total_size: u64,
+}
+
+impl Extraction<'_> {
+    /// Extracts `entry` to `path` inside the destination. `size` is what the
+    /// archive claims it holds, which is checked again while writing.
+    fn write<R: Read + ?Sized>(
+        &mut self,
+        entry: &mut R,
+        path: PathBuf,
+        size: u64,
+    ) -> io::Result<()> {
+        if self.extracted.len() >= self.limits.entries {
+            let limit = self.limits.entries;
+            return Err(io::Error::other(LimitExceeded::Entries { limit }));
+        }
+        self.check(size)?;
+
+        let destination = self.destination.join(&path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // One byte over the limit is enough to tell it is crossed.
+        let left = self.limits.total_size - self.total_size;
+        let mut entry = Read::take(entry, self.limits.entry_size.min(left) + 1);
+        let written = io::copy(&mut entry, &mut File::create(&destination)?)?;
+        self.check(written)?;
+
+        self.total_size += written;
+        self.extracted.push(path);
+        Ok(())
+    }
+
+    fn check(&self, size: u64) -> io::Result<()> {
+        let total_size = self.total_size.saturating_add(size);
+        let exceeded = if size > self.limits.entry_size {
+            LimitExceeded::Size {
+                size,
+                limit: self.limits.entry_size,
+            }
+        } else if total_size > self.limits.total_size {
+            LimitExceeded::Size {
+                size: total_size,
+                limit: self.limits.total_size,
+            }
+        } else {
+            return Ok(());
+        };
+        Err(io::Error::other(exceeded))
+    }
+}
+
+/// `path` when it stays inside the directory it gets extracted to.
+fn enclosed_path(path: &Path) -> Option<PathBuf> {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| path.components().collect::<PathBuf>())
+        .filter(|p| p.file_name().is_some())
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .and_then(|e| FileType::from_string(&e.to_lowercase()))
+        .is_some_and(|f| f.is_input())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collect::{write_7z, write_tar_gz, write_zip};
+
+    const LIMITS: ExtractLimits = ExtractLimits {
+        entry_size: 100,
+        total_size: 250,
+        entries: 3,
+    };
+
+    /// Packs files of `sizes` bytes under their names into an archive of
+    /// `kind`, in a new folder also holding the extracted files.
+    fn archive(kind: CompressionType, files: &[(&str, usize)]) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = files
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, size))| {
+                let path = dir.path().join(i.to_string());
+                fs::write(&path, vec![b'x'; size]).unwrap();
+                (path, PathBuf::from(name))
+            })
+            .collect::<Vec<_>>();
+        let path = dir.path().join("archive");
+        match kind {
+            CompressionType::Zip => write_zip(&entries, &path, 6),
+            CompressionType::TarGz => write_tar_gz(&entries, &path, 6),
+            CompressionType::SevenZ => write_7z(&entries, &path, 6),
+            _ => unreachable!(),
+        }
+        .unwrap();
+        (dir, path)
+    }
+
+    fn extract(kind: CompressionType, files: &[(&str, usize)]) -> io::Result<Vec<PathBuf>> {
+        let (dir, path) = archive(kind, files);
+        extract_images(&path, kind, &dir.path().join("out"), LIMITS)
+    }
+
+    fn exceeded(result: io::Result<Vec<PathBuf>>) -> LimitExceeded {
+        let error = result.unwrap_err();
+        *error.get_ref().unwrap().downcast_ref().unwrap()
+    }
+
+    const KINDS: [CompressionType; 3] = [
+        CompressionType::Zip,
+        CompressionType::TarGz,
+        CompressionType::SevenZ,
+    ];
+
+    #[test]
+    fn extracts_images_only() {
+        for kind in KINDS {
+            let (dir, path) = archive(kind, &[("a.png", 10), ("notes.txt", 10), ("b/c.JPG", 10)]);
+            let destination = dir.path().join("out");
+            let extracted = extract_images(&path, kind, &destination, LIMITS).unwrap();
+            assert_eq!(
+                extracted,
+                ["a.png", "b/c.JPG"].map(PathBuf::from),
+                "{kind:?}"
+            );
+            assert_eq!(fs::read(destination.join("b/c.JPG")).unwrap(), [b'x'; 10]);
+        }
+    }
+
+    #[test]
+    fn keeps_entries_inside_the_destination() {
+        let extracted = extract(CompressionType::Zip, &[("../a.png", 10), ("b.png", 10)]);
+        assert_eq!(extracted.unwrap(), [PathBuf::from("b.png")]);
+    }
+
+    #[test]
+    fn limits_the_size_of_each_image() {
+        for kind in KINDS {
+            assert_eq!(
+                exceeded(extract(kind, &[("a.png", 101)])),
+                LimitExceeded::Size {
+                    size: 101,
+                    limit: 100
+                },
+                "{kind:?}"
+            );
+            assert!(extract(kind, &[("a.png", 100)]).is_ok(), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn limits_the_size_of_every_image() {
+        for kind in KINDS {
+            let files = [("a.png", 100), ("b.png", 100), ("c.png", 51)];
+            assert_eq!(
+                exceeded(extract(kind, &files)),
+                LimitExceeded::Size {
+                    size: 251,
+                    limit: 250
+                },
+                "{kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_the_number_of_images() {
+        for kind in KINDS {
+            let files = [("a.png", 1), ("b.png", 1), ("c.png", 1), ("notes.txt", 1)];
+            assert_eq!(extract(kind, &files).unwrap().len(), 3, "{kind:?}");
+
+            let files = [("a.png", 1), ("b.png", 1), ("c.png", 1), ("d.png", 1)];
+            assert_eq!(
+                exceeded(extract(kind, &files)),
+                LimitExceeded::Entries { limit: 3 },
+                "{kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn does_not_trust_declared_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut extraction = Extraction {
+            destination: dir.path(),
+            limits: LIMITS,
+            extracted: vec![],
+            total_size: 0,
+        };
+        let mut entry: &[u8] = &[0; 200];
+        let error = extraction
+            .write(&mut entry, PathBuf::from("a.png"), 10)
+            .unwrap_err();
+        assert_eq!(
+            *error
+                .get_ref()
+                .unwrap()
+                .downcast_ref::<LimitExceeded>()
+                .unwrap(),
+            LimitExceeded::Size {
+                size: 101,
+                limit: 100
+            }
+        );
+    }
+}
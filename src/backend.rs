@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::filetypes::FileType;
+use crate::image_info::ImageInfo;
+use crate::magick::{ImageMagick, MagickConvertJob};
+use crate::metadata;
+use crate::native::Native;
+use crate::scheduler::Step;
+
+/// Something able to read, convert and write images.
+pub trait ConversionBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether [`ConversionBackend::probe`] works on images of `kind`.
+    fn can_probe(&self, kind: FileType) -> bool;
+
+    /// Whether the backend reads images of `kind`.
+    fn can_decode(&self, kind: FileType) -> bool;
+
+    /// Whether the backend writes images of `kind`.
+    fn can_encode(&self, kind: FileType) -> bool;
+
+    /// Whether the backend handles every option of `job`, not only its
+    /// formats. It may read the input, so it is only called on the worker
+    /// thread running the job.
+    fn supports(&self, job: &MagickConvertJob) -> bool {
+        self.can_decode(job.input_type) && self.can_encode(job.output_type)
+    }
+
+    /// Describes the image at `path`, leaving out what the backend cannot
+    /// tell.
+    fn probe(&self, path: &Path, kind: FileType) -> Result<ImageInfo, ()>;
+
+    /// The step decoding the input of `job` and encoding its output.
+    fn convert(&self, job: &MagickConvertJob) -> Step;
+}
+
+/// Every backend, by order of preference. ImageMagick comes last as it
+/// handles everything, at the cost of starting a process for each step.
+static BACKENDS: [&dyn ConversionBackend; 2] = [&Native, &ImageMagick];
+
+/// Whether any backend reads images of `kind`.
+pub fn can_read(kind: FileType) -> bool {
+    BACKENDS.into_iter().any(|b| b.can_decode(kind))
+}
+
+/// Whether any backend writes images of `kind`.
+pub fn can_write(kind: FileType) -> bool {
+    BACKENDS.into_iter().any(|b| b.can_encode(kind))
+}
+
+fn backend_for(job: &MagickConvertJob) -> &'static dyn ConversionBackend {
+    BACKENDS
+        .into_iter()
+        .find(|b| b.supports(job))
+        .unwrap_or(&ImageMagick)
+}
+
+/// The steps running `jobs`, each by the first backend supporting it,
+/// followed by filtering the metadata of its output when needed. The
+/// backends are chosen when the steps run.
+pub fn steps(jobs: &[MagickConvertJob]) -> Vec<Step> {
+    jobs.iter()
+        .flat_map(|job| {
+            let convert = {
+                let job = job.clone();
+                Step::Deferred(Box::new(move || {
+                    let backend = backend_for(&job);
+                    debug!(
+                        "Converting {} with {}",
+                        job.input_file.display(),
+                        backend.name()
+                    );
+                    backend.convert(&job)
+                }))
+            };
+            let filter = job.metadata.filters_exif(job.output_type).then(|| {
+                let (output, kind, policy) =
+                    (job.output_file.clone(), job.output_type, job.metadata);
+                Step::Task(Box::new(move || {
+                    metadata::filter_file(&output, kind, policy)
+                }))
+            });
+            std::iter::once(convert).chain(filter)
+        })
+        .collect()
+}
+
+/// Probes the image at `path` with the first backend able to, off the
+/// calling thread.
+pub async fn probe(path: PathBuf, kind: FileType) -> Result<ImageInfo, ()> {
+    let backend = BACKENDS
+        .into_iter()
+        .find(|b| b.can_probe(kind))
+        .unwrap_or(&ImageMagick);
+    tokio::task::spawn_blocking(move || backend.probe(&path, kind))
+        .await
+        .unwrap_or(Err(()))
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use gtk::glib;
+use log::{debug, warn};
+
+use crate::filetypes::FileType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Mode {
+    read: bool,
+    write: bool,
+}
+
+impl Mode {
+    /// Parses the mode column of `magick -list format`, like `rw+`.
+    fn parse(mode: &str) -> Option<Self> {
+        let mut chars = mode.chars();
+        let read = match chars.next()? {
+            'r' => true,
+            '-' => false,
+            _ => return None,
+        };
+        let write = match chars.next()? {
+            'w' => true,
+            '-' => false,
+            _ => return None,
+        };
+        matches!((chars.next()?, chars.next()), ('+' | '-', None)).then_some(Self { read, write })
+    }
+}
+
+/// What the installed ImageMagick reads and writes, which depends on the
+/// libraries it was built with.
+#[derive(Debug, Clone, Default)]
+pub struct MagickCapabilities {
+    /// First line of `magick -version`, `None` when it could not run.
+    version: Option<String>,
+    /// Libraries listed as built-in delegates by `magick -version`.
+    delegates: Option<HashSet<String>>,
+    /// Modes of the coders, by upper case name.
+    coders: HashMap<String, Mode>,
+    /// Whether Ghostscript, which ImageMagick reads PDF with, is on the path.
+    ghostscript: bool,
+}
+
+impl MagickCapabilities {
+    /// Asks `magick` what it supports, reusing the list of formats in
+    /// `cache` when it was written by the same version.
+    fn probe(cache: &Path) -> Self {
+        let ghostscript = find_program("gs");
+        let Some(version_output) = magick_output(&["-version"]) else {
+            warn!("Could not run ImageMagick, only the built-in formats are available");
+            return Self {
+                ghostscript,
+                ..Default::default()
+            };
+        };
+        let version = version_output.lines().next().unwrap_or_default().to_owned();
+        let delegates = version_output
+            .lines()
+            .find_map(|l| l.strip_prefix("Delegates (built-in):"))
+            .map(|l| l.split_whitespace().map(str::to_owned).collect());
+
+        let coders = match read_cache(cache, &version) {
+            Some(coders) => coders,
+            None => {
+                let coders = magick_output(&["-list", "format"])
+                    .map(|list| parse_formats(&list))
+                    .unwrap_or_default();
+                if !coders.is_empty() {
+                    write_cache(cache, &version, &coders);
+                }
+                coders
+            }
+        };
+
+        debug!(
+            "{version}: {} formats, Ghostscript: {ghostscript}",
+            coders.len()
+        );
+        Self {
+            version: Some(version),
+            delegates,
+            coders,
+            ghostscript,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.version.is_some()
+    }
+
+    pub fn can_read(&self, kind: FileType) -> bool {
+        match kind {
+            FileType::Pdf if !self.ghostscript => false,
+            _ => self.mode(kind).read,
+        }
+    }
+
+    pub fn can_write(&self, kind: FileType) -> bool {
+        self.mode(kind).write
+    }
+
+    fn mode(&self, kind: FileType) -> Mode {
+        if !self.is_installed() || kind == FileType::Unknown {
+            return Mode::default();
+        }
+        if let (Some(delegates), Some(delegate)) = (&self.delegates, delegate(kind))
+            && !delegates.contains(delegate)
+        {
+            return Mode::default();
+        }
+        // A list that could not be read is no reason to refuse everything.
+        if self.coders.is_empty() {
+            return Mode {
+                read: true,
+                write: true,
+            };
+        }
+        self.coders
+            .get(&kind.as_coder().to_uppercase())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Capabilities of the installed ImageMagick, probed on first use.
+pub fn magick_capabilities() -> &'static MagickCapabilities {
+    static CAPABILITIES: OnceLock<MagickCapabilities> = OnceLock::new();
+    CAPABILITIES.get_or_init(|| {
+        MagickCapabilities::probe(
+            &glib::user_cache_dir()
+                .join("switcheroo")
+                .join("magick-formats"),
+        )
+    })
+}
+
+/// The delegate library ImageMagick needs for images of `kind`, for those
+/// it does not handle by itself.
+fn delegate(kind: FileType) -> Option<&'static str> {
+    match kind {
+        FileType::Png => Some("png"),
+        FileType::Jpg | FileType::Jpeg => Some("jpeg"),
+        FileType::Webp => Some("webp"),
+        FileType::Heif | FileType::Heic | FileType::Avif => Some("heic"),
+        FileType::Jxl => Some("jxl"),
+        FileType::Tiff => Some("tiff"),
+        _ => None,
+    }
+}
+
+fn magick_output(args: &[&str]) -> Option<String> {
+    let output = Command::new("magick")
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the output of `magick -list format`, whose lines start with the
+/// coder name, followed by its module and mode.
+fn parse_formats(list: &str) -> HashMap<String, Mode> {
+    list.lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.next()?.trim_end_matches('*');
+            let mode = columns.take(2).find_map(Mode::parse)?;
+            Some((name.to_uppercase(), mode))
+        })
+        .collect()
+}
+
+fn read_cache(cache: &Path, version: &str) -> Option<HashMap<String, Mode>> {
+    let contents = std::fs::read_to_string(cache).ok()?;
+    let (cached_version, list) = contents.split_once('\n')?;
+    (cached_version == version)
+        .then(|| parse_formats(list))
+        .filter(|c| !c.is_empty())
+}
+
+fn write_cache(cache: &Path, version: &str, coders: &HashMap<String, Mode>) {
+    let mut contents = format!("{version}\n");
+    for (name, mode) in coders {
+        contents.push_str(&format!(
+            "{name} {}{}+\n",
+            if mode.read { 'r' } else { '-' },
+            if mode.write { 'w' } else { '-' }
+        ));
+    }
+    if let Err(e) = cache
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(cache, contents))
+    {
+        warn!("Could not cache the ImageMagick formats: {e}");
+    }
+}
+
+fn find_program(name: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .any(|path| path.is_file())
+    })
+}
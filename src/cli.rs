@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use futures::future::join_all;
+use gettextrs::gettext;
+use glib::{ExitCode, clone};
+use gtk::{gio, glib, prelude::*};
+use itertools::Itertools;
+use tempfile::TempDir;
+
+use crate::backend;
+use crate::color::Color;
+use crate::conversion::{
+    ConflictPolicy, ConversionInput, ConversionOptions, ConversionPlan, Converter, PlannedJob,
+};
+use crate::filetypes::{CompressionType, FileType, OutputType};
+use crate::folder::FolderFilter;
+use crate::input_file::InputFile;
+use crate::magick::{ResizeArgument, ResizeFilter};
+use crate::metadata::MetadataPolicy;
+use crate::mirror::MirrorMode;
+use crate::preset::Preset;
+use crate::runtime;
+use crate::scheduler::{JobEvent, JobId, Priority, Scheduler};
+use crate::temp::create_temporary_dir;
+use crate::template::NameTemplate;
+use crate::watch::{FolderWatcher, WatchEvent, WatchOptions};
+
+/// Returned when the command line itself could not be understood.
+pub const EXIT_USAGE: ExitCode = ExitCode::new(2);
+
+/// Sent by Ctrl+C, which stops watching a folder.
+const SIGINT: i32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct CliOptions {
+    pub inputs: Vec<PathBuf>,
+    /// A directory, or an archive to collect the outputs in.
+    pub output: PathBuf,
+    /// Maximum number of images converted at the same time, zero for one
+    /// per CPU.
+    pub jobs: usize,
+    pub conversion: ConversionOptions,
+    pub template: NameTemplate,
+    /// What to do with outputs already in the output directory, as given by
+    /// `--on-conflict` or else saved in the window. Asking is not possible
+    /// here, so outputs are renamed rather than asked about.
+    pub conflict_policy: ConflictPolicy,
+    /// Compression level of archive outputs, from 0, the fastest, to 9.
+    pub compression_level: u32,
+    /// Which files are imported from input folders.
+    pub folder_filter: FolderFilter,
+    /// Whether outputs go in the same folders as their inputs.
+    pub keep_folders: bool,
+    /// How up to date outputs are found in a directory output.
+    pub mirror_mode: MirrorMode,
+    /// Whether outputs whose input is gone are deleted when mirroring.
+    pub delete_orphans: bool,
+    /// A folder whose new images are converted until interrupted, instead
+    /// of converting the inputs.
+    pub watch: Option<PathBuf>,
+}
+
+impl CliOptions {
+    /// Returns `Ok(None)` when no headless option was passed, so the
+    /// application should start normally.
+    pub fn from_options(
+        options: &glib::VariantDict,
+        settings: &gio::Settings,
+    ) -> Result<Option<Self>, String> {
+        if !["format", "output", "preset", "watch"]
+            .iter()
+            .any(|o| options.contains(o))
+        {
+            return Ok(None);
+        }
+
+        let inputs = lookup::<Vec<PathBuf>>(options, glib::OPTION_REMAINING)?.unwrap_or_default();
+        let watch = lookup::<PathBuf>(options, "watch")?;
+        match (&watch, inputs.is_empty()) {
+            (None, true) => return Err(gettext("No input files given")),
+            (Some(_), false) => return Err(gettext("Input files cannot be given with --watch")),
+            _ => {}
+        }
+
+        let preset = match lookup::<String>(options, "preset")? {
+            Some(name) => Some(
+                Preset::find(settings, &name)
+                    .ok_or_else(|| gettext("No preset named {}").replace("{}", &name))?,
+            ),
+            None => None,
+        };
+
+        let output_type = match (lookup::<String>(options, "format")?, &preset) {
+            (Some(format), _) => FileType::from_string(&format.to_lowercase())
+                .filter(|f| f.is_output())
+                .ok_or_else(|| gettext("Unsupported output format: {}").replace("{}", &format))?,
+            (None, Some(preset)) => preset.output_type,
+            (None, None) => return Err(gettext("Missing --format")),
+        };
+        if !backend::can_write(output_type) {
+            return Err(gettext("The installed ImageMagick cannot write {} images")
+                .replace("{}", &output_type.as_display_string()));
+        }
+
+        let Some(output) = lookup::<PathBuf>(options, "output")? else {
+            return Err(gettext("Missing --output"));
+        };
+
+        let quality = match lookup::<i32>(options, "quality")? {
+            Some(q) if (1..=100).contains(&q) => q as usize,
+            Some(q) => {
+                return Err(gettext("Quality must be between 1 and 100, got {}")
+                    .replace("{}", &q.to_string()));
+            }
+            None => preset
+                .as_ref()
+                .map_or_else(|| settings.int("quality") as usize, |p| p.quality),
+        };
+
+        let dpi = match lookup::<i32>(options, "dpi")? {
+            Some(d) if d > 0 => d as usize,
+            Some(d) => {
+                return Err(gettext("Invalid pixel density: {}").replace("{}", &d.to_string()));
+            }
+            None => preset
+                .as_ref()
+                .map_or_else(|| settings.int("dpi") as usize, |p| p.dpi),
+        };
+
+        let background = match lookup::<String>(options, "background")? {
+            Some(b) => Color::from_hex_string(&b)
+                .ok_or_else(|| gettext("Invalid background color: {}").replace("{}", &b))?,
+            None => match &preset {
+                Some(preset) => preset.background,
+                None if output_type.supports_alpha() => Color::rgba(0, 0, 0, 0),
+                None => Color::rgb(255, 255, 255),
+            },
+        };
+
+        let resize_arg = match lookup::<String>(options, "resize")? {
+            Some(r) => ResizeArgument::from_string(&r)
+                .ok_or_else(|| gettext("Invalid resize value: {}").replace("{}", &r))?,
+            None => preset.as_ref().map(|p| p.resize_arg).unwrap_or_default(),
+        };
+
+        let jobs = match lookup::<i32>(options, "jobs")? {
+            Some(j) if j >= 0 => j as usize,
+            Some(j) => {
+                return Err(gettext("Invalid number of jobs: {}").replace("{}", &j.to_string()));
+            }
+            None => settings.int("max-parallel-jobs") as usize,
+        };
+
+        let template = match lookup::<String>(options, "name-template")? {
+            Some(t) => NameTemplate::parse(&t)?,
+            None => NameTemplate::parse(&settings.string("filename-template")).unwrap_or_default(),
+        };
+
+        let compression_level = match lookup::<i32>(options, "compression-level")? {
+            Some(l) if (0..=9).contains(&l) => l as u32,
+            Some(l) => {
+                return Err(gettext("Compression level must be between 0 and 9, got {}")
+                    .replace("{}", &l.to_string()));
+            }
+            None => settings.int("archive-compression-level") as u32,
+        };
+
+        let conflict_policy = match lookup::<String>(options, "on-conflict")? {
+            Some(p) => ConflictPolicy::from_string(&p)
+                .filter(|p| *p != ConflictPolicy::Ask)
+                .ok_or_else(|| gettext("Invalid conflict policy: {}").replace("{}", &p))?,
+            None => ConflictPolicy::from_index(settings.enum_("conflict-policy") as usize)
+                .unwrap_or_default(),
+        };
+
+        let mut folder_filter = FolderFilter::load(settings);
+        if let Some(include) = lookup::<Vec<String>>(options, "include")? {
+            folder_filter.include = include;
+        }
+        if let Some(exclude) = lookup::<Vec<String>>(options, "exclude")? {
+            folder_filter.exclude = exclude;
+        }
+        if options.contains("hidden") {
+            folder_filter.include_hidden = true;
+        }
+
+        let keep_folders = options.contains("keep-folders") || settings.boolean("keep-folders");
+
+        let mirror_mode = match lookup::<String>(options, "mirror")? {
+            Some(m) => MirrorMode::from_string(&m)
+                .ok_or_else(|| gettext("Invalid mirror mode: {}").replace("{}", &m))?,
+            None => {
+                MirrorMode::from_index(settings.enum_("mirror-mode") as usize).unwrap_or_default()
+            }
+        };
+        let delete_orphans =
+            options.contains("delete-orphans") || settings.boolean("mirror-delete-orphans");
+
+        let metadata = match lookup::<String>(options, "metadata")? {
+            Some(m) => MetadataPolicy::from_string(&m)
+                .ok_or_else(|| gettext("Invalid metadata policy: {}").replace("{}", &m))?,
+            None => match &preset {
+                Some(preset) => preset.metadata,
+                None => MetadataPolicy::from_index(settings.enum_("metadata-policy") as usize)
+                    .unwrap_or_default(),
+            },
+        };
+
+        let filter = match (options.contains("pixel-art"), &preset) {
+            (true, _) => ResizeFilter::Point,
+            (false, Some(preset)) => preset.filter,
+            (false, None) => ResizeFilter::Default,
+        };
+
+        Ok(Some(Self {
+            inputs,
+            output,
+            jobs,
+            template,
+            conflict_policy,
+            compression_level,
+            folder_filter,
+            keep_folders,
+            mirror_mode,
+            delete_orphans,
+            watch,
+            conversion: ConversionOptions {
+                output_type,
+                background,
+                quality,
+                filter: Some(filter),
+                resize_arg,
+                dpi,
+                metadata,
+            },
+        }))
+    }
+}
+
+/// Value of the option `name`, which is an error when it has another type
+/// than `T`.
+fn lookup<T: glib::FromVariant>(
+    options: &glib::VariantDict,
+    name: &str,
+) -> Result<Option<T>, String> {
+    options
+        .lookup::<T>(name)
+        .map_err(|_| gettext("Invalid value for {}").replace("{}", name))
+}
+
+/// A headless conversion whose inputs are loaded and whose outputs are
+/// planned, ready to be handed to a [`Scheduler`].
+pub struct PreparedConversion {
+    /// Kept around so images extracted from archives are not deleted yet.
+    _files: Vec<InputFile>,
+    pub inputs: Vec<ConversionInput>,
+    pub plan: ConversionPlan,
+    /// Where the jobs of the plan write their outputs.
+    pub dir: TempDir,
+    pub save_format: OutputType,
+    /// One line per input that could not be imported.
+    pub rejected: Vec<String>,
+    /// One line per output that is not converted or saved under another
+    /// name.
+    pub notes: Vec<String>,
+}
+
+impl PreparedConversion {
+    /// The plan of the jobs in `succeeded`, whose outputs are collected.
+    pub fn succeeded(&self, succeeded: Vec<PlannedJob>) -> ConversionPlan {
+        ConversionPlan {
+            jobs: succeeded,
+            mirror: self.plan.mirror.clone(),
+        }
+    }
+
+    /// Gathers the outputs of the jobs in `succeeded` where they belong.
+    pub fn collect(&self, succeeded: Vec<PlannedJob>, options: &CliOptions) -> Result<(), String> {
+        if succeeded.is_empty() {
+            return Ok(());
+        }
+        let plan = self.succeeded(succeeded);
+        plan.collect(self.save_format, &options.output, options.compression_level)
+    }
+}
+
+/// Loads the inputs of `options` and plans their conversion, skipping
+/// outputs that are up to date or already exist as configured. Loading,
+/// probing and planning run on other threads.
+pub async fn prepare(options: &CliOptions) -> Result<PreparedConversion, String> {
+    let archive = options
+        .output
+        .file_name()
+        .and_then(|n| CompressionType::from_file_name(&n.to_string_lossy()));
+    let save_format = OutputType::Compression(archive.unwrap_or(CompressionType::Directory));
+
+    let output_dir = match save_format {
+        OutputType::Compression(CompressionType::Directory) => Some(options.output.as_path()),
+        _ => options.output.parent(),
+    };
+    if let Some(output_dir) = output_dir.filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(output_dir)
+            .map_err(|e| format!("{}: {e}", output_dir.display()))?;
+    }
+
+    let mut rejected = vec![];
+    let mut files = vec![];
+    for path in options.inputs.iter() {
+        let (loaded, failed) = InputFile::load_all(
+            vec![gio::File::for_commandline_arg(path)],
+            options.folder_filter.clone(),
+            |_| {},
+        )
+        .await;
+        if loaded.is_empty() && failed.is_empty() {
+            rejected.push(format!(
+                "{}: {}",
+                path.display(),
+                gettext("No supported images found")
+            ));
+        }
+        rejected.extend(failed.iter().map(|f| format!("{}: {}", f.path, f.error)));
+        files.extend(loaded);
+    }
+
+    let probes = files.iter().map(|f| (f.path(), f.kind())).collect_vec();
+    let infos = gio::spawn_blocking(move || {
+        runtime().block_on(join_all(
+            probes
+                .into_iter()
+                .map(|(path, kind)| backend::probe(path, kind)),
+        ))
+    })
+    .await
+    .map_err(|_| gettext("Could not read the input images"))?;
+    let inputs = files
+        .iter()
+        .zip(infos)
+        .map(|(f, info)| {
+            f.set_info(info.unwrap_or_default());
+            ConversionInput {
+                path: f.path(),
+                kind: f.kind(),
+                frames: f.frames(),
+                dimensions: f.dimensions(),
+                has_alpha: f.has_alpha(),
+                relative_dir: f.relative_dir(),
+                temporary: f.is_temporary(),
+            }
+        })
+        .collect_vec();
+
+    let planning = options.clone();
+    let (inputs, (plan, dir, notes)) = gio::spawn_blocking(move || {
+        plan_outputs(&planning, &inputs, save_format).map(|planned| (inputs, planned))
+    })
+    .await
+    .map_err(|_| gettext("Could not plan the outputs"))??;
+
+    Ok(PreparedConversion {
+        _files: files,
+        inputs,
+        plan,
+        dir,
+        save_format,
+        rejected,
+        notes,
+    })
+}
+
+/// Plans the outputs of `inputs` in a new temporary folder, along with one
+/// note per output that is not converted or saved under another name.
+fn plan_outputs(
+    options: &CliOptions,
+    inputs: &[ConversionInput],
+    save_format: OutputType,
+) -> Result<(ConversionPlan, TempDir, Vec<String>), String> {
+    let dir = runtime()
+        .block_on(create_temporary_dir())
+        .map_err(|e| e.to_string())?;
+
+    let mut plan = Converter::new(
+        options.conversion,
+        options.template.clone(),
+        options.keep_folders,
+    )
+    .plan(inputs, &dir)?;
+
+    let mut notes = vec![];
+
+    let mirror_mode = match save_format {
+        OutputType::Compression(CompressionType::Directory) => options.mirror_mode,
+        _ => MirrorMode::Off,
+    };
+    let report = plan.mirror(&options.output, mirror_mode, options.delete_orphans)?;
+    for name in report.up_to_date.iter() {
+        notes.push(format!(
+            "{}: {}",
+            name.display(),
+            gettext("up to date, skipped")
+        ));
+    }
+    for name in report.removed.iter() {
+        notes.push(format!(
+            "{}: {}",
+            name.display(),
+            gettext("image no longer exists, removed")
+        ));
+    }
+
+    if save_format == OutputType::Compression(CompressionType::Directory)
+        && mirror_mode == MirrorMode::Off
+    {
+        let resolution = options.conflict_policy.resolution().unwrap_or_default();
+        let resolutions = plan
+            .conflicts(&options.output)
+            .into_iter()
+            .map(|i| (i, resolution.clone()))
+            .collect_vec();
+        let report = plan.resolve(&options.output, &resolutions);
+        for name in report.skipped.iter() {
+            notes.push(format!(
+                "{}: {}",
+                name.display(),
+                gettext("already exists, skipped")
+            ));
+        }
+        for (name, new_name) in report.renamed.iter() {
+            notes.push(format!(
+                "{}: {}",
+                name.display(),
+                gettext("already exists, saved as {}")
+                    .replace("{}", &new_name.display().to_string())
+            ));
+        }
+    }
+
+    Ok((plan, dir, notes))
+}
+
+/// Converts every input without creating any window, reporting progress on
+/// stdout and errors on stderr.
+pub fn run(options: CliOptions) -> ExitCode {
+    if let Some(source) = options.watch.clone() {
+        return watch(options, source);
+    }
+
+    let prepared = match futures::executor::block_on(prepare(&options)) {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    for line in prepared.rejected.iter() {
+        eprintln!("{line}");
+    }
+    for line in prepared.notes.iter() {
+        println!("{line}");
+    }
+
+    let mut failed = prepared.rejected.len();
+    let (inputs, plan) = (&prepared.inputs, &prepared.plan);
+
+    let total = plan.command_count();
+    let mut completed = 0;
+
+    let scheduler = Scheduler::new(options.jobs);
+    let jobs: HashMap<JobId, &PlannedJob> = plan
+        .jobs
+        .iter()
+        .map(|job| {
+            let handle = scheduler.submit(Priority::Normal, backend::steps(&job.commands));
+            (handle.id, job)
+        })
+        .collect();
+    let events = scheduler.events();
+
+    let mut succeeded = vec![];
+    let mut remaining = jobs.len();
+    while remaining > 0
+        && let Ok(event) = events.recv_blocking()
+    {
+        match event {
+            JobEvent::Progress(id) => {
+                completed += 1;
+                let job = jobs[&id];
+                println!(
+                    "[{completed}/{total}] {} → {}",
+                    inputs[job.input].path.display(),
+                    job.output_name.display()
+                );
+            }
+            JobEvent::Done(id, result) => {
+                remaining -= 1;
+                let job = jobs[&id];
+                match result {
+                    Ok(()) => succeeded.push(job.clone()),
+                    Err(e) => {
+                        eprintln!("{}: {}", inputs[job.input].path.display(), e.trim());
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if let Err(e) = prepared.collect(succeeded, &options) {
+        eprintln!("{e}");
+        failed += 1;
+    }
+
+    match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+/// Converts every image landing in `source` into the output folder until
+/// interrupted, printing a line for each of them.
+fn watch(options: CliOptions, source: PathBuf) -> ExitCode {
+    if CompressionType::from_file_name(&options.output.to_string_lossy()).is_some() {
+        eprintln!("{}", gettext("Watching a folder needs an output folder"));
+        return EXIT_USAGE;
+    }
+    if let Err(e) = std::fs::create_dir_all(&options.output) {
+        eprintln!("{}: {e}", options.output.display());
+        return ExitCode::FAILURE;
+    }
+
+    let watch_options = WatchOptions {
+        source: source.clone(),
+        destination: options.output,
+        converter: Converter::new(options.conversion, options.template, false),
+        conflict: options.conflict_policy.resolution().unwrap_or_default(),
+        jobs: options.jobs,
+    };
+    let watcher = match FolderWatcher::start(watch_options, print_watch_event) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "{}",
+        gettext("Watching {}, press Ctrl+C to stop").replace("{}", &source.to_string_lossy())
+    );
+
+    // Stopping through the main loop lets the watcher clean up its
+    // temporary files and running conversions.
+    let main_loop = glib::MainLoop::new(None, false);
+    glib::unix_signal_add_local_once(
+        SIGINT,
+        clone!(
+            #[strong]
+            main_loop,
+            move || main_loop.quit()
+        ),
+    );
+    main_loop.run();
+    drop(watcher);
+
+    ExitCode::SUCCESS
+}
+
+fn print_watch_event(event: WatchEvent) {
+    let time = glib::DateTime::now_local()
+        .and_then(|d| d.format("%H:%M:%S"))
+        .map(String::from)
+        .unwrap_or_default();
+    match event {
+        WatchEvent::Converted { input, outputs } if outputs.is_empty() => println!(
+            "[{time}] {}: {}",
+            input.display(),
+            gettext("already exists, skipped")
+        ),
+        WatchEvent::Converted { input, outputs } => {
+            println!(
+                "[{time}] {} → {}",
+                input.display(),
+                outputs.iter().map(|o| o.display()).join(", ")
+            )
+        }
+        WatchEvent::Failed { input, error } => eprintln!("[{time}] {}: {error}", input.display()),
+    }
+}
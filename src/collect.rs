@@ -0,0 +1,151 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use sevenz_rust::lzma::LZMA2Options;
+use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+/// Moves `source` to `destination`, copying it when they are on different
+/// filesystems.
+pub fn move_file(source: &Path, destination: &Path) -> io::Result<()> {
+    match fs::rename(source, destination) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            write_atomically(destination, |file| {
+                io::copy(&mut File::open(source)?, file)?;
+                Ok(())
+            })?;
+            fs::remove_file(source)
+        }
+        result => result,
+    }
+}
+
+/// Writes every file of `entries` under its name into a new ZIP archive at
+/// `destination`, streaming their contents. A `level` of zero stores them
+/// without compression, otherwise it goes up to 9. Entries are named with
+/// UTF-8 text, so the parts of names that are not valid UTF-8 are replaced.
+pub fn write_zip(entries: &[(PathBuf, PathBuf)], destination: &Path, level: u32) -> io::Result<()> {
+    let options = match level {
+        0 => SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        l => SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(l.min(9) as i64)),
+    }
+    .large_file(true);
+
+    write_atomically(destination, |file| {
+        let mut zip = ZipWriter::new(file);
+        for (path, name) in entries {
+            zip.start_file(name.to_string_lossy(), options)?;
+            io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+        zip.finish()?;
+        Ok(())
+    })?;
+
+    remove_files(entries)
+}
+
+/// Writes every file of `entries` under its name into a new tarball at
+/// `destination`, compressed with gzip at `level`, from 0 to 9.
+pub fn write_tar_gz(
+    entries: &[(PathBuf, PathBuf)],
+    destination: &Path,
+    level: u32,
+) -> io::Result<()> {
+    write_atomically(destination, |file| {
+        let encoder = GzEncoder::new(file, flate2::Compression::new(level.min(9)));
+        write_tar(entries, encoder)?.finish()?;
+        Ok(())
+    })?;
+
+    remove_files(entries)
+}
+
+/// Writes every file of `entries` under its name into a new tarball at
+/// `destination`, compressed with Zstandard. `level` goes from 0 to 9 like
+/// the other formats and is spread over Zstandard's wider range.
+pub fn write_tar_zst(
+    entries: &[(PathBuf, PathBuf)],
+    destination: &Path,
+    level: u32,
+) -> io::Result<()> {
+    let level = (level.min(9) as i32 * 2).max(1);
+    write_atomically(destination, |file| {
+        let encoder = zstd::Encoder::new(file, level)?;
+        write_tar(entries, encoder)?.finish()?;
+        Ok(())
+    })?;
+
+    remove_files(entries)
+}
+
+/// Writes every file of `entries` under its name into a new 7z archive at
+/// `destination`, compressed with the LZMA2 preset `level`, from 0 to 9.
+/// Like in ZIP archives, names that are not valid UTF-8 are altered.
+pub fn write_7z(entries: &[(PathBuf, PathBuf)], destination: &Path, level: u32) -> io::Result<()> {
+    write_atomically(destination, |file| {
+        let mut archive = SevenZWriter::new(file).map_err(io::Error::other)?;
+        archive.set_content_methods(vec![LZMA2Options::with_preset(level.min(9)).into()]);
+        for (path, name) in entries {
+            archive
+                .push_archive_entry(
+                    SevenZArchiveEntry::from_path(path, name.to_string_lossy().into_owned()),
+                    Some(File::open(path)?),
+                )
+                .map_err(io::Error::other)?;
+        }
+        archive.finish()?;
+        Ok(())
+    })?;
+
+    remove_files(entries)
+}
+
+/// Streams `entries` into a tar archive written to `writer`, returning it
+/// once the archive is complete.
+fn write_tar<W: Write>(entries: &[(PathBuf, PathBuf)], writer: W) -> io::Result<W> {
+    let mut tar = tar::Builder::new(writer);
+    for (path, name) in entries {
+        tar.append_path_with_name(path, name)?;
+    }
+    tar.into_inner()
+}
+
+fn remove_files(entries: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    entries
+        .iter()
+        .try_for_each(|(path, _)| fs::remove_file(path))
+}
+
+/// Creates `destination` through a hidden file next to it, which only
+/// replaces it once `write` succeeded and everything reached the disk.
+pub fn write_atomically<F>(destination: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let name = destination
+        .file_name()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut partial_name = std::ffi::OsString::from(".");
+    partial_name.push(name);
+    partial_name.push(".partial");
+    let partial = destination.with_file_name(partial_name);
+
+    let result = File::create(&partial).and_then(|mut file| {
+        write(&mut file)?;
+        file.flush()?;
+        file.sync_all()
+    });
+
+    match result.and_then(|()| fs::rename(&partial, destination)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            fs::remove_file(&partial).ok();
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,772 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use itertools::Itertools;
+use tempfile::TempDir;
+
+use crate::collect::{move_file, write_7z, write_tar_gz, write_tar_zst, write_zip};
+use crate::color::Color;
+use crate::filetypes::{CompressionType, FileType, OutputType};
+use crate::magick::{
+    JobFile, MagickConvertJob, ResizeArgument, ResizeFilter, file_argument, generate_job,
+    output_argument,
+};
+use crate::metadata::MetadataPolicy;
+use crate::mirror::{Mirror, MirrorMode, MirrorReport};
+use crate::temp::get_temp_file_path;
+use crate::template::{NameFields, NameTemplate};
+
+/// An image to convert, with everything the planner needs to know about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionInput {
+    pub path: PathBuf,
+    pub kind: FileType,
+    pub frames: usize,
+    /// Size of the first frame, if known.
+    pub dimensions: Option<(usize, usize)>,
+    /// Whether the image has transparent pixels. The background is only
+    /// applied to those that do, others are left as they are.
+    pub has_alpha: bool,
+    /// Folder of the input relative to the folder or archive it was imported
+    /// from, recreated in the output when keeping folders.
+    pub relative_dir: Option<PathBuf>,
+    /// Whether `path` is a temporary copy of an image extracted from an
+    /// archive or downloaded, which does not outlive the conversion.
+    pub temporary: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversionOptions {
+    pub output_type: FileType,
+    pub background: Color,
+    pub quality: usize,
+    pub filter: Option<ResizeFilter>,
+    pub resize_arg: ResizeArgument,
+    pub dpi: usize,
+    pub metadata: MetadataPolicy,
+}
+
+impl ConversionOptions {
+    /// Size of the output after resizing an image of the given size.
+    fn output_dimensions(&self, dimensions: Option<(usize, usize)>) -> Option<(usize, usize)> {
+        match self.resize_arg {
+            ResizeArgument::ExactPixels { width, height } => Some((width, height)),
+            ResizeArgument::Percentage { width, height } => {
+                let (w, h) = dimensions?;
+                Some((
+                    (w * width).div_ceil(100).max(1),
+                    (h * height).div_ceil(100).max(1),
+                ))
+            }
+        }
+    }
+
+    fn default_job(&self) -> MagickConvertJob {
+        MagickConvertJob {
+            input_file: PathBuf::new(),
+            input_type: FileType::Unknown,
+            input_frame: None,
+            output_file: PathBuf::new(),
+            output_type: self.output_type,
+            background: self.background,
+            quality: self.quality,
+            filter: self.filter,
+            resize_arg: self.resize_arg,
+            density: None,
+            first_frame: false,
+            remove_alpha: false,
+            metadata: self.metadata,
+        }
+    }
+}
+
+/// What to do when an output would replace a file already in the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    Rename,
+    Ask,
+}
+
+impl ConflictPolicy {
+    pub fn iterator() -> std::slice::Iter<'static, Self> {
+        use ConflictPolicy::*;
+        static POLICIES: [ConflictPolicy; 4] = [Overwrite, Skip, Rename, Ask];
+        POLICIES.iter()
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::iterator().nth(index).copied()
+    }
+
+    /// The resolution applied to every conflict, `None` if the user decides.
+    pub fn resolution(&self) -> Option<ConflictResolution> {
+        match self {
+            ConflictPolicy::Overwrite => Some(ConflictResolution::Overwrite),
+            ConflictPolicy::Skip => Some(ConflictResolution::Skip),
+            ConflictPolicy::Rename => Some(ConflictResolution::Rename),
+            ConflictPolicy::Ask => None,
+        }
+    }
+
+    pub fn from_string(policy: &str) -> Option<Self> {
+        match policy {
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            "skip" => Some(ConflictPolicy::Skip),
+            "rename" => Some(ConflictPolicy::Rename),
+            "ask" => Some(ConflictPolicy::Ask),
+            _ => None,
+        }
+    }
+}
+
+/// How a single conflicting output was dealt with.
+///
+/// Where nobody can be asked, conflicts are renamed by default so neither
+/// the existing file nor the new output is lost.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ConflictResolution {
+    Overwrite,
+    Skip,
+    #[default]
+    Rename,
+}
+
+/// Outputs that did not end up under their planned name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConflictReport {
+    pub skipped: Vec<PathBuf>,
+    /// Planned names and the names actually used.
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl ConflictReport {
+    pub fn is_empty(&self) -> bool {
+        self.skipped.is_empty() && self.renamed.is_empty()
+    }
+}
+
+/// A single output file and the `magick` invocations producing it.
+#[derive(Debug, Clone)]
+pub struct PlannedJob {
+    /// Index of the input this job was planned from.
+    pub input: usize,
+    /// Path of that input, `None` for temporary copies. Their outputs are
+    /// always converted and left out of the mirror, as the copy is gone by
+    /// the next conversion.
+    pub source: Option<PathBuf>,
+    /// Path of the output relative to the destination, only holding
+    /// folders when keeping them. It keeps the input's name as it is, even
+    /// when that is not valid UTF-8.
+    pub output_name: PathBuf,
+    /// Where the output is written inside the temporary directory.
+    pub output_file: PathBuf,
+    pub commands: Vec<MagickConvertJob>,
+}
+
+impl PlannedJob {
+    /// Changes the output name, writing the output next to the old one.
+    fn rename(&mut self, name: PathBuf) {
+        let output_file = self
+            .output_file
+            .with_file_name(name.file_name().unwrap_or_default());
+        for command in self.commands.iter_mut() {
+            if command.output_file == self.output_file {
+                command.output_file = output_file.clone();
+            }
+        }
+        self.output_name = name;
+        self.output_file = output_file;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConversionPlan {
+    pub jobs: Vec<PlannedJob>,
+    /// Set when mirroring into a directory, to remember the outputs once
+    /// collected.
+    pub mirror: Option<Mirror>,
+}
+
+impl ConversionPlan {
+    pub fn output_files(&self) -> Vec<PathBuf> {
+        self.jobs.iter().map(|j| j.output_file.clone()).collect()
+    }
+
+    /// Every output file, along with its path relative to the destination.
+    fn output_entries(&self) -> Vec<(PathBuf, PathBuf)> {
+        self.jobs
+            .iter()
+            .map(|j| (j.output_file.clone(), j.output_name.clone()))
+            .collect()
+    }
+
+    /// Number of steps running the jobs, each reporting its progress once.
+    /// Filtering the metadata of an output takes a step of its own.
+    pub fn command_count(&self) -> usize {
+        self.jobs
+            .iter()
+            .flat_map(|j| j.commands.iter())
+            .map(|c| 1 + usize::from(c.metadata.filters_exif(c.output_type)))
+            .sum()
+    }
+
+    /// Indices of the jobs whose output already exists in `destination`.
+    pub fn conflicts(&self, destination: &Path) -> Vec<usize> {
+        self.jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| destination.join(&job.output_name).exists())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Applies one resolution per conflicting job, as returned by
+    /// [`ConversionPlan::conflicts`]. Renamed outputs get the first free
+    /// `_N` suffix in `destination`.
+    pub fn resolve(
+        &mut self,
+        destination: &Path,
+        resolutions: &[(usize, ConflictResolution)],
+    ) -> ConflictReport {
+        let mut report = ConflictReport::default();
+        let mut taken: HashSet<PathBuf> = self.jobs.iter().map(|j| j.output_name.clone()).collect();
+        let mut skipped = HashSet::new();
+
+        for (i, resolution) in resolutions {
+            let job = &mut self.jobs[*i];
+            match resolution {
+                ConflictResolution::Overwrite => {}
+                ConflictResolution::Skip => {
+                    skipped.insert(*i);
+                    report.skipped.push(job.output_name.clone());
+                }
+                ConflictResolution::Rename => {
+                    let name = (1..)
+                        .map(|n| with_suffix(&job.output_name, n))
+                        .find(|name| !taken.contains(name) && !destination.join(name).exists())
+                        .unwrap();
+                    taken.insert(name.clone());
+                    report.renamed.push((job.output_name.clone(), name.clone()));
+                    job.rename(name);
+                }
+            }
+        }
+
+        self.jobs = std::mem::take(&mut self.jobs)
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !skipped.contains(i))
+            .map(|(_, job)| job)
+            .collect();
+
+        report
+    }
+
+    /// Drops the jobs whose output in `destination` is up to date according
+    /// to `mode`. With `delete_orphans`, also deletes the outputs of earlier
+    /// runs whose input is gone.
+    pub fn mirror(
+        &mut self,
+        destination: &Path,
+        mode: MirrorMode,
+        delete_orphans: bool,
+    ) -> Result<MirrorReport, String> {
+        let mut report = MirrorReport::default();
+        if mode == MirrorMode::Off {
+            return Ok(report);
+        }
+
+        let mut mirror = Mirror::new(mode, destination);
+        if delete_orphans {
+            let planned = self.jobs.iter().map(|j| j.output_name.as_path()).collect();
+            report.removed = mirror.remove_orphans(destination, &planned);
+        }
+
+        self.jobs.retain(|job| {
+            let Some(source) = &job.source else {
+                return true;
+            };
+            if !mirror.is_up_to_date(source, &job.output_name, destination) {
+                return true;
+            }
+            mirror.record(source, &job.output_name);
+            report.up_to_date.push(job.output_name.clone());
+            false
+        });
+        mirror
+            .save(destination)
+            .map_err(|e| format!("{}: {e}", destination.display()))?;
+        self.mirror = Some(mirror);
+
+        Ok(report)
+    }
+
+    /// The `magick` command merging every page into the PDF at `destination`,
+    /// when the outputs need merging rather than [`ConversionPlan::collect`].
+    pub fn merge_command(&self, save_format: OutputType, destination: &Path) -> Option<Command> {
+        let output_files = self.output_files();
+
+        match save_format {
+            OutputType::File(FileType::Pdf) if output_files.len() > 1 => {
+                let mut command = Command::new("magick");
+                command
+                    .args(
+                        output_files
+                            .iter()
+                            .map(|f| file_argument(FileType::Pdf, f, None)),
+                    )
+                    .arg(output_argument(FileType::Pdf, destination));
+                command.stdout(Stdio::piped()).stderr(Stdio::piped());
+                Some(command)
+            }
+            _ => None,
+        }
+    }
+
+    /// Gathers every output into `destination` once all jobs are done, by
+    /// moving them or writing them into an archive compressed at `level`.
+    pub fn collect(
+        &self,
+        save_format: OutputType,
+        destination: &Path,
+        level: u32,
+    ) -> Result<(), String> {
+        let entries = self.output_entries();
+        let error = |path: &Path, e: std::io::Error| format!("{}: {e}", path.display());
+
+        match save_format {
+            OutputType::File(_) => match entries.first() {
+                Some((file, _)) => move_file(file, destination).map_err(|e| error(destination, e)),
+                None => Ok(()),
+            },
+            OutputType::Compression(CompressionType::Directory) => {
+                entries.iter().try_for_each(|(file, name)| {
+                    let target = destination.join(name);
+                    target
+                        .parent()
+                        .map_or(Ok(()), std::fs::create_dir_all)
+                        .and_then(|()| move_file(file, &target))
+                        .map_err(|e| error(&target, e))
+                })?;
+
+                match &self.mirror {
+                    Some(mirror) => {
+                        let mut mirror = mirror.clone();
+                        for job in self.jobs.iter() {
+                            if let Some(source) = &job.source {
+                                mirror.record(source, &job.output_name);
+                            }
+                        }
+                        mirror.save(destination).map_err(|e| error(destination, e))
+                    }
+                    None => Ok(()),
+                }
+            }
+            OutputType::Compression(archive) => match archive {
+                CompressionType::Zip => write_zip(&entries, destination, level),
+                CompressionType::TarGz => write_tar_gz(&entries, destination, level),
+                CompressionType::TarZst => write_tar_zst(&entries, destination, level),
+                CompressionType::SevenZ => write_7z(&entries, destination, level),
+                CompressionType::Directory => unreachable!(),
+            }
+            .map_err(|e| error(destination, e)),
+        }
+    }
+}
+
+/// Turns input images into `magick` jobs writing into a temporary directory.
+#[derive(Debug, Clone)]
+pub struct Converter {
+    options: ConversionOptions,
+    template: NameTemplate,
+    /// Whether outputs go in the same folders as their inputs.
+    keep_folders: bool,
+}
+
+impl Converter {
+    pub fn new(options: ConversionOptions, template: NameTemplate, keep_folders: bool) -> Self {
+        Self {
+            options,
+            template,
+            keep_folders,
+        }
+    }
+
+    pub fn plan(
+        &self,
+        inputs: &[ConversionInput],
+        dir: &TempDir,
+    ) -> Result<ConversionPlan, String> {
+        use FileType::*;
+
+        let output_type = self.options.output_type;
+        let extension = output_type.as_extension();
+        let default_job = self.options.default_job();
+
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+        let jobs = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let stem = input.path.file_stem().unwrap_or_default().to_owned();
+                (i, input, stem)
+            })
+            .sorted_by_key(|(_, input, s)| (input.relative_dir.clone(), s.to_owned()))
+            .flat_map(|(i, input, stem)| {
+                // Images whose frames could not be counted still have one.
+                let (input_filetype, frames) = (input.kind, input.frames.max(1));
+                // The frame read from the input, and the one named in the output.
+                let frames = match (input_filetype, output_type, frames) {
+                    (Pdf, _, c) => (0..c).map(|f| (Some(f), Some(f))).collect_vec(),
+                    (_, _, 1) => vec![(Some(0), None)],
+                    (input, output, _)
+                        if input.supports_animation() && output.supports_animation() =>
+                    {
+                        vec![(None, None)]
+                    }
+                    (input, _, count) if input.supports_animation() => {
+                        (0..count).map(|f| (Some(f), Some(f))).collect_vec()
+                    }
+                    _ => vec![(Some(0), None)],
+                };
+
+                frames
+                    .into_iter()
+                    .map(move |(input_frame, frame)| (i, input, stem.clone(), input_frame, frame))
+            })
+            .enumerate()
+            .scan(
+                HashSet::new(),
+                |names, (counter, (i, input, stem, input_frame, frame))| {
+                    // Frames need telling apart even if the template ignores them.
+                    let mut stem = stem;
+                    if let Some(f) = frame.filter(|_| !self.template.has_index()) {
+                        stem.push(format!("-{f}"));
+                    }
+                    let name = self.template.render(&NameFields {
+                        stem: &stem,
+                        index: frame.unwrap_or_default(),
+                        dimensions: self.options.output_dimensions(input.dimensions),
+                        extension,
+                        date: &date,
+                        counter: counter + 1,
+                    });
+                    let name = match name {
+                        Ok(name) => name,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    let name = match &input.relative_dir {
+                        Some(folder) if self.keep_folders => folder.join(name),
+                        _ => PathBuf::from(name),
+                    };
+                    let name = unique_name(names, name);
+                    names.insert(name.clone());
+                    Some(Ok((i, input, input_frame, name)))
+                },
+            )
+            .map(|planned| {
+                let (i, input, input_frame, output_name) = planned?;
+                let output_file =
+                    get_temp_file_path(dir, JobFile::new(output_type, Some(output_name.clone())));
+                if let Some(parent) = output_file.parent() {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| format!("{}: {e}", parent.display()))?;
+                }
+                let input_arguments = MagickConvertJob {
+                    remove_alpha: !input.has_alpha,
+                    ..default_job.clone()
+                };
+                Ok(PlannedJob {
+                    input: i,
+                    source: Some(input.path.clone()).filter(|_| !input.temporary),
+                    commands: generate_job(
+                        &input.path,
+                        input_frame,
+                        &input.kind,
+                        &output_file,
+                        &output_type,
+                        self.options.dpi,
+                        &input_arguments,
+                    ),
+                    output_name,
+                    output_file,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(ConversionPlan { jobs, mirror: None })
+    }
+}
+
+/// Adds `_1`, `_2`, … before the extension of `name` until it is not in `taken`.
+fn unique_name(taken: &HashSet<PathBuf>, name: PathBuf) -> PathBuf {
+    if !taken.contains(&name) {
+        return name;
+    }
+
+    (1..)
+        .map(|n| with_suffix(&name, n))
+        .find(|n| !taken.contains(n))
+        .unwrap()
+}
+
+fn with_suffix(name: &Path, n: usize) -> PathBuf {
+    let file_name = name.file_name().unwrap_or_default().as_bytes();
+    let renamed = match file_name.iter().rposition(|&b| b == b'.') {
+        Some(dot) if dot > 0 => {
+            let mut renamed = file_name[..dot].to_vec();
+            renamed.extend_from_slice(format!("_{n}").as_bytes());
+            renamed.extend_from_slice(&file_name[dot..]);
+            renamed
+        }
+        _ => [file_name, format!("_{n}").as_bytes()].concat(),
+    };
+    name.with_file_name(OsStr::from_bytes(&renamed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(output_type: FileType) -> ConversionOptions {
+        ConversionOptions {
+            output_type,
+            background: Color::rgba(0, 0, 0, 0),
+            quality: 92,
+            filter: None,
+            resize_arg: ResizeArgument::Percentage {
+                width: 50,
+                height: 50,
+            },
+            dpi: 300,
+            metadata: MetadataPolicy::KeepAll,
+        }
+    }
+
+    fn input(path: &str, kind: FileType, frames: usize) -> ConversionInput {
+        ConversionInput {
+            path: PathBuf::from(path),
+            kind,
+            frames,
+            dimensions: Some((200, 100)),
+            has_alpha: false,
+            relative_dir: None,
+            temporary: false,
+        }
+    }
+
+    fn plan_all(converter: &Converter, inputs: &[ConversionInput]) -> (ConversionPlan, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        (converter.plan(inputs, &dir).unwrap(), dir)
+    }
+
+    fn names(plan: &ConversionPlan) -> Vec<&str> {
+        plan.jobs
+            .iter()
+            .map(|j| j.output_name.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn plans_one_job_per_still_image() {
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), false);
+        let inputs = [
+            input("b.jpg", FileType::Jpg, 1),
+            input("a.webp", FileType::Webp, 1),
+        ];
+        let (plan, dir) = plan_all(&converter, &inputs);
+
+        assert_eq!(names(&plan), ["a.png", "b.png"]);
+        assert_eq!(plan.jobs[0].input, 1);
+        assert_eq!(plan.jobs[0].source, Some(PathBuf::from("a.webp")));
+        assert!(plan.jobs[0].output_file.starts_with(dir.path()));
+        assert_eq!(plan.command_count(), 2);
+    }
+
+    #[test]
+    fn counts_metadata_filtering_steps() {
+        let inputs = [input("a.webp", FileType::Webp, 1)];
+        for (metadata, count) in [
+            (MetadataPolicy::KeepAll, 1),
+            (MetadataPolicy::StripAll, 1),
+            (MetadataPolicy::CopyrightOnly, 2),
+            (MetadataPolicy::ExifOnly, 2),
+        ] {
+            let options = ConversionOptions {
+                metadata,
+                ..options(FileType::Jpg)
+            };
+            let converter = Converter::new(options, NameTemplate::default(), false);
+            let (plan, _dir) = plan_all(&converter, &inputs);
+            assert_eq!(plan.command_count(), count, "{metadata:?}");
+        }
+    }
+
+    #[test]
+    fn treats_zero_frames_as_one() {
+        for kind in [FileType::Png, FileType::Gif, FileType::Pdf] {
+            let converter = Converter::new(options(FileType::Png), NameTemplate::default(), false);
+            let (plan, _dir) = plan_all(&converter, &[input("image", kind, 0)]);
+            assert_eq!(plan.jobs.len(), 1, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn splits_pages_and_frames() {
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), false);
+        let (plan, _dir) = plan_all(&converter, &[input("doc.pdf", FileType::Pdf, 3)]);
+        assert_eq!(names(&plan), ["doc-0.png", "doc-1.png", "doc-2.png"]);
+        assert_eq!(plan.jobs[2].commands[0].input_frame, Some(2));
+        assert_eq!(plan.jobs[2].commands[0].density, Some(300));
+
+        let (plan, _dir) = plan_all(&converter, &[input("anim.gif", FileType::Gif, 2)]);
+        assert_eq!(names(&plan), ["anim-0.png", "anim-1.png"]);
+        assert!(plan.jobs[1].commands[0].first_frame);
+    }
+
+    #[test]
+    fn keeps_animations_whole() {
+        let converter = Converter::new(options(FileType::Webp), NameTemplate::default(), false);
+        let (plan, _dir) = plan_all(&converter, &[input("anim.gif", FileType::Gif, 5)]);
+        assert_eq!(names(&plan), ["anim.webp"]);
+        assert_eq!(plan.jobs[0].commands[0].input_frame, None);
+        assert!(!plan.jobs[0].commands[0].first_frame);
+    }
+
+    #[test]
+    fn renders_the_template() {
+        let template = NameTemplate::parse("{counter:02}-{stem}-{width}x{height}.{ext}").unwrap();
+        let converter = Converter::new(options(FileType::Jpg), template, false);
+        let (plan, _dir) = plan_all(&converter, &[input("photo.png", FileType::Png, 1)]);
+        assert_eq!(names(&plan), ["01-photo-100x50.jpg"]);
+    }
+
+    #[test]
+    fn tells_identical_names_apart() {
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), false);
+        let inputs = [
+            input("one/photo.jpg", FileType::Jpg, 1),
+            input("two/photo.webp", FileType::Webp, 1),
+        ];
+        let (plan, _dir) = plan_all(&converter, &inputs);
+        assert_eq!(names(&plan), ["photo.png", "photo_1.png"]);
+        assert_ne!(plan.jobs[0].output_file, plan.jobs[1].output_file);
+    }
+
+    #[test]
+    fn keeps_folders() {
+        let inputs = [ConversionInput {
+            relative_dir: Some(PathBuf::from("holiday/day 1")),
+            temporary: false,
+            ..input("photo.jpg", FileType::Jpg, 1)
+        }];
+
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), true);
+        let (plan, dir) = plan_all(&converter, &inputs);
+        assert_eq!(names(&plan), ["holiday/day 1/photo.png"]);
+        assert!(dir.path().join("holiday/day 1").is_dir());
+
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), false);
+        let (plan, _dir) = plan_all(&converter, &inputs);
+        assert_eq!(names(&plan), ["photo.png"]);
+    }
+
+    #[test]
+    fn reports_folders_that_cannot_be_created() {
+        let inputs = [ConversionInput {
+            relative_dir: Some(PathBuf::from("taken")),
+            temporary: false,
+            ..input("photo.jpg", FileType::Jpg, 1)
+        }];
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), true);
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("taken"), b"").unwrap();
+
+        let error = converter.plan(&inputs, &dir).unwrap_err();
+        assert!(error.contains("taken"), "{error}");
+    }
+
+    #[test]
+    fn removes_alpha_only_from_opaque_images() {
+        let converter = Converter::new(options(FileType::Jpg), NameTemplate::default(), false);
+        let inputs = [
+            input("opaque.png", FileType::Png, 1),
+            ConversionInput {
+                has_alpha: true,
+                ..input("transparent.png", FileType::Png, 1)
+            },
+        ];
+        let (plan, _dir) = plan_all(&converter, &inputs);
+        assert!(plan.jobs[0].commands[0].remove_alpha);
+        assert!(!plan.jobs[1].commands[0].remove_alpha);
+    }
+
+    #[test]
+    fn resolves_conflicts() {
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), false);
+        let inputs = [
+            input("a.jpg", FileType::Jpg, 1),
+            input("b.jpg", FileType::Jpg, 1),
+            input("c.jpg", FileType::Jpg, 1),
+        ];
+        let (mut plan, _dir) = plan_all(&converter, &inputs);
+        let destination = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.png", "b_1.png"] {
+            std::fs::write(destination.path().join(name), b"").unwrap();
+        }
+
+        assert_eq!(plan.conflicts(destination.path()), [0, 1]);
+        let report = plan.resolve(
+            destination.path(),
+            &[
+                (0, ConflictResolution::Skip),
+                (1, ConflictResolution::Rename),
+            ],
+        );
+        assert_eq!(report.skipped, [PathBuf::from("a.png")]);
+        assert_eq!(
+            report.renamed,
+            [(PathBuf::from("b.png"), PathBuf::from("b_2.png"))]
+        );
+        assert_eq!(names(&plan), ["b_2.png", "c.png"]);
+        assert!(plan.jobs[0].output_file.ends_with("b_2.png"));
+        assert_eq!(
+            plan.jobs[0].commands[0].output_file,
+            plan.jobs[0].output_file
+        );
+    }
+
+    #[test]
+    fn adds_suffixes_before_the_extension() {
+        let suffixed = |name: &str, n| with_suffix(Path::new(name), n);
+        assert_eq!(suffixed("photo.png", 1), Path::new("photo_1.png"));
+        assert_eq!(suffixed("a.b/photo", 2), Path::new("a.b/photo_2"));
+        assert_eq!(suffixed(".hidden", 3), Path::new(".hidden_3"));
+    }
+
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        let converter = Converter::new(options(FileType::Png), NameTemplate::default(), false);
+        let inputs = [ConversionInput {
+            path: PathBuf::from(OsStr::from_bytes(b"caf\xe9.jpg")),
+            ..input("", FileType::Jpg, 1)
+        }];
+        let (plan, _dir) = plan_all(&converter, &inputs);
+        assert_eq!(
+            plan.jobs[0].output_name.as_os_str().as_bytes(),
+            b"caf\xe9.png"
+        );
+        assert!(
+            plan.jobs[0]
+                .output_file
+                .ends_with(&plan.jobs[0].output_name)
+        );
+    }
+}
@@ -4,14 +4,16 @@ use gettextrs::gettext;
 use glib::clone;
 use gtk::{gio, prelude::*};
 
+use crate::backend;
 use crate::filetypes::{CompressionType, FileType, OutputType};
 use crate::input_file::InputFile;
+use crate::remote::RemoteOutput;
 use crate::window::AppWindow;
 
 pub struct FileChooser;
 
 impl FileChooser {
-    pub fn load_files<A, B, C>(
+    pub async fn load_files<A, B, C>(
         files: Vec<gio::File>,
         parent: &AppWindow,
         callback_start: A,
@@ -23,16 +25,9 @@ impl FileChooser {
         C: Fn(&AppWindow, Option<&str>),
     {
         callback_start(parent);
-        let gfiles: Vec<Option<InputFile>> = files
-            .into_iter()
-            .map(|file| InputFile::new(&file))
-            .collect();
-        let mut files = Vec::new();
-
-        for file in gfiles.into_iter().flatten() {
-            if file.kind().is_input() {
-                files.push(file);
-            }
+        let (files, rejected) = parent.load_inputs(files).await;
+        if !rejected.is_empty() {
+            parent.show_rejected_files(rejected);
         }
 
         if files.is_empty() {
@@ -82,9 +77,12 @@ impl FileChooser {
         C: Fn(&AppWindow, Option<&str>) + 'static,
     {
         let image_filter = gtk::FileFilter::new();
-        for filter in FileType::input_formats() {
+        for filter in FileType::input_formats().filter(|&&f| backend::can_read(f)) {
             image_filter.add_mime_type(filter.as_mime());
         }
+        for archive in CompressionType::compression_formats() {
+            image_filter.add_mime_type(archive.as_mime());
+        }
         image_filter.set_name(Some(&gettext("Images")));
 
         let dialog = gtk::FileDialog::builder()
@@ -105,7 +103,7 @@ impl FileChooser {
 
         if current_paths.len() == 1
             && files.len() == 1
-            && current_paths[0] == files[0].path().unwrap()
+            && files[0].path().as_ref() == Some(&current_paths[0])
         {
             return;
         }
@@ -116,18 +114,69 @@ impl FileChooser {
             &callback_start,
             &callback_success,
             &callback_error,
-        );
+        )
+        .await;
+    }
+
+    pub fn open_folder_wrapper<A, B, C>(
+        parent: &AppWindow,
+        callback_start: A,
+        callback_success: B,
+        callback_error: C,
+    ) where
+        A: Fn(&AppWindow) + 'static,
+        B: Fn(&AppWindow, Vec<InputFile>) + 'static,
+        C: Fn(&AppWindow, Option<&str>) + 'static,
+    {
+        glib::MainContext::default().spawn_local(clone!(
+            #[strong]
+            parent,
+            async move {
+                FileChooser::open_folder(&parent, callback_start, callback_success, callback_error)
+                    .await;
+            }
+        ));
+    }
+
+    pub async fn open_folder<A, B, C>(
+        parent: &AppWindow,
+        callback_start: A,
+        callback_success: B,
+        callback_error: C,
+    ) where
+        A: Fn(&AppWindow) + 'static,
+        B: Fn(&AppWindow, Vec<InputFile>) + 'static,
+        C: Fn(&AppWindow, Option<&str>) + 'static,
+    {
+        let dialog = gtk::FileDialog::builder()
+            .accept_label(gettext("_Select Folder"))
+            .modal(true)
+            .build();
+
+        let Ok(folder) = dialog.select_folder_future(Some(parent)).await else {
+            callback_error(parent, None);
+            return;
+        };
+
+        FileChooser::load_files(
+            vec![folder],
+            parent,
+            &callback_start,
+            &callback_success,
+            &callback_error,
+        )
+        .await;
     }
 
     pub fn choose_output_file_wrapper<A, B>(
         parent: &AppWindow,
         default_name: String,
         format: OutputType,
-        default_folder: String,
+        default_folder: PathBuf,
         callback_success: A,
         callback_error: B,
     ) where
-        A: Fn(&AppWindow, OutputType, String) + 'static,
+        A: Fn(&AppWindow, OutputType, PathBuf) + 'static,
         B: Fn(&AppWindow, Option<&str>) + 'static,
     {
         glib::MainContext::default().spawn_local(clone!(
@@ -151,11 +200,11 @@ impl FileChooser {
         parent: &AppWindow,
         default_name: String,
         format: OutputType,
-        default_folder: Option<String>,
+        default_folder: Option<PathBuf>,
         callback_success: A,
         callback_error: B,
     ) where
-        A: Fn(&AppWindow, OutputType, String) + 'static,
+        A: Fn(&AppWindow, OutputType, PathBuf) + 'static,
         B: Fn(&AppWindow, Option<&str>) + 'static,
     {
         let image_filter = gtk::FileFilter::new();
@@ -168,7 +217,7 @@ impl FileChooser {
 
         dialog.set_initial_name(Some(&default_name));
         if let Some(default_folder) = default_folder {
-            dialog.set_initial_folder(Some(&gio::File::for_path(default_folder)));
+            dialog.set_initial_folder(Some(&gio::File::for_commandline_arg(default_folder)));
         }
 
         let Ok(file) = dialog.save_future(Some(parent)).await else {
@@ -176,14 +225,32 @@ impl FileChooser {
             return;
         };
 
-        let file_path = file.path().unwrap();
+        // Outputs to other locations are written locally, then uploaded.
+        let file_path = match file.path() {
+            Some(path) => {
+                parent.set_remote_output(None);
+                path
+            }
+            None => match RemoteOutput::new(file) {
+                Ok(output) => {
+                    let path = output.local_path().to_owned();
+                    parent.set_remote_output(Some(output));
+                    path
+                }
+                Err(e) => {
+                    callback_error(parent, Some(&e.to_string()));
+                    return;
+                }
+            },
+        };
 
-        let Some(file_extension) = file_path.extension() else {
+        if file_path.extension().is_none() {
             callback_error(parent, Some(&gettext("Unspecified filetype")));
             return;
-        };
+        }
 
-        let Some(file_extension) = OutputType::from_string(file_extension.to_str().unwrap()) else {
+        let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+        let Some(file_extension) = OutputType::from_file_name(&file_name) else {
             callback_error(parent, Some(&gettext("Unknown filetype")));
             return;
         };
@@ -193,16 +260,16 @@ impl FileChooser {
             return;
         }
 
-        callback_success(parent, format, file_path.to_str().unwrap().to_owned());
+        callback_success(parent, format, file_path);
     }
 
     pub fn choose_output_folder_wrapper<A, B>(
         parent: &AppWindow,
-        default_folder: String,
+        default_folder: PathBuf,
         callback_success: A,
         callback_error: B,
     ) where
-        A: Fn(&AppWindow, OutputType, String) + 'static,
+        A: Fn(&AppWindow, OutputType, PathBuf) + 'static,
         B: Fn(&AppWindow, Option<&str>) + 'static,
     {
         glib::MainContext::default().spawn_local(clone!(
@@ -222,17 +289,17 @@ impl FileChooser {
 
     pub async fn choose_output_folder<A, B>(
         parent: &AppWindow,
-        default_folder: Option<String>,
+        default_folder: Option<PathBuf>,
         callback_success: A,
         callback_error: B,
     ) where
-        A: Fn(&AppWindow, OutputType, String) + 'static,
+        A: Fn(&AppWindow, OutputType, PathBuf) + 'static,
         B: Fn(&AppWindow, Option<&str>) + 'static,
     {
         let dialog = gtk::FileDialog::builder().build();
 
         if let Some(default_folder) = default_folder {
-            dialog.set_initial_folder(Some(&gio::File::for_path(default_folder)));
+            dialog.set_initial_folder(Some(&gio::File::for_commandline_arg(default_folder)));
         }
 
         let Ok(file) = dialog.select_folder_future(Some(parent)).await else {
@@ -240,12 +307,16 @@ impl FileChooser {
             return;
         };
 
-        let file_path = file.path().unwrap();
+        let Some(file_path) = file.path() else {
+            callback_error(parent, Some(&gettext("The folder is not on this computer")));
+            return;
+        };
+        parent.set_remote_output(None);
 
         callback_success(
             parent,
             OutputType::Compression(CompressionType::Directory),
-            file_path.to_str().unwrap().to_owned(),
+            file_path,
         );
     }
 }
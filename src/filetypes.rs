@@ -1,3 +1,6 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 use std::slice::Iter;
 
 #[derive(Clone, Copy, Debug, glib::Enum, PartialEq, Default, Eq, Hash)]
@@ -179,6 +182,14 @@ impl FileType {
         self.as_extension().to_uppercase()
     }
 
+    /// Name of the ImageMagick coder reading and writing the format.
+    pub fn as_coder(&self) -> &str {
+        match self {
+            Jpg | Jpeg => "jpeg",
+            _ => self.as_extension(),
+        }
+    }
+
     pub fn from_string(extension: &str) -> Option<Self> {
         match extension {
             "png" => Some(Png),
@@ -201,6 +212,147 @@ impl FileType {
     }
 }
 
+/// How many bytes are read to recognize a file, enough to get past the XML
+/// declaration and comments usually found before the root of an SVG.
+const SIGNATURE_LENGTH: u64 = 4096;
+
+/// Major and compatible brands of ISOBMFF files, checked in this order since
+/// AVIF files usually list the generic HEIF brands too.
+const ISOBMFF_BRANDS: [(&[u8; 4], FileType); 11] = [
+    (b"avif", Avif),
+    (b"avis", Avif),
+    (b"heic", Heic),
+    (b"heix", Heic),
+    (b"heim", Heic),
+    (b"heis", Heic),
+    (b"hevc", Heic),
+    (b"hevx", Heic),
+    (b"mif1", Heif),
+    (b"msf1", Heif),
+    (b"heif", Heif),
+];
+
+impl FileType {
+    /// The type of the file at `path`, recognized from its first bytes. The
+    /// `mimetype` guessed by GIO is only used when they match no known
+    /// format.
+    pub fn detect(path: &Path, mimetype: Option<&str>) -> Option<Self> {
+        let mut header = vec![];
+        let sniffed = File::open(path)
+            .and_then(|file| file.take(SIGNATURE_LENGTH).read_to_end(&mut header))
+            .ok()
+            .and_then(|_| Self::from_signature(&header));
+        sniffed.or_else(|| mimetype.and_then(Self::from_mimetype))
+    }
+
+    /// The type of a file starting with `header`, `None` when it matches no
+    /// supported format.
+    pub fn from_signature(header: &[u8]) -> Option<Self> {
+        const JXL_CONTAINER: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+        match header {
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(Png),
+            [0xff, 0xd8, 0xff, ..] => Some(Jpg),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Gif),
+            [
+                b'R',
+                b'I',
+                b'F',
+                b'F',
+                _,
+                _,
+                _,
+                _,
+                b'W',
+                b'E',
+                b'B',
+                b'P',
+                ..,
+            ] => Some(Webp),
+            [0xff, 0x0a, ..] => Some(Jxl),
+            _ if header.starts_with(JXL_CONTAINER) => Some(Jxl),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => isobmff_type(header),
+            [b'I', b'I', b'*' | b'+', 0, ..] | [b'M', b'M', 0, b'*' | b'+', ..] => Some(Tiff),
+            [b'D', b'D', b'S', b' ', ..] => Some(Dds),
+            // Icon directories: reserved, type 1 and a non-zero image count.
+            [0, 0, 1, 0, count_low, count_high, ..] if (*count_low, *count_high) != (0, 0) => {
+                Some(Ico)
+            }
+            [
+                b'B',
+                b'M',
+                _,
+                _,
+                _,
+                _,
+                _,
+                _,
+                _,
+                _,
+                _,
+                _,
+                _,
+                _,
+                size,
+                0,
+                0,
+                0,
+                ..,
+            ] if [12, 40, 52, 56, 64, 108, 124].contains(size) => Some(Bmp),
+            // Some writers put garbage before the PDF header, readers accept
+            // it within the first kilobyte.
+            _ if find(&header[..header.len().min(1024)], b"%PDF-").is_some() => Some(Pdf),
+            _ if is_svg(header) => Some(Svg),
+            _ => None,
+        }
+    }
+}
+
+/// The type of an ISOBMFF file from the brands of its leading `ftyp` box.
+fn isobmff_type(header: &[u8]) -> Option<FileType> {
+    let size = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+    let ftyp = header
+        .get(8..size.min(header.len()))
+        .filter(|ftyp| ftyp.len() >= 4)?;
+    // The major brand, then the compatible ones after the minor version.
+    let compatible = ftyp.get(8..).unwrap_or_default().chunks_exact(4);
+    let brands = std::iter::once(&ftyp[..4])
+        .chain(compatible)
+        .collect::<Vec<_>>();
+
+    ISOBMFF_BRANDS
+        .iter()
+        .find(|(brand, _)| brands.contains(&brand.as_slice()))
+        .map(|(_, kind)| *kind)
+}
+
+/// Whether `header` is the start of an XML document whose root is `<svg`.
+fn is_svg(header: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(header);
+    let mut rest = text.trim_start_matches('\u{feff}').trim_start();
+    loop {
+        // Skip the XML declaration, processing instructions, comments and
+        // the doctype, which all come before the root element.
+        let end = if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<!") {
+            ">"
+        } else {
+            return rest.starts_with("<svg") || rest.starts_with("<svg:svg");
+        };
+        match rest.find(end) {
+            Some(i) => rest = rest[i + end.len()..].trim_start(),
+            None => return false,
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
 #[derive(Clone, Copy, Debug, glib::Enum, PartialEq, Eq, Hash)]
 #[enum_type(name = "SwitcherooCompressionType")]
 pub enum CompressionType {
@@ -208,28 +360,34 @@ pub enum CompressionType {
     Zip,
     #[enum_value(name = "Directory")]
     Directory,
+    #[enum_value(name = "TarGz")]
+    TarGz,
+    #[enum_value(name = "TarZst")]
+    TarZst,
+    #[enum_value(name = "SevenZ")]
+    SevenZ,
 }
 
 use CompressionType::*;
 
 impl CompressionType {
     pub fn is_compression(&self) -> bool {
-        matches!(self, Zip)
+        !matches!(self, Directory)
     }
 
     pub fn iterator() -> Iter<'static, Self> {
-        static COMPRESSION_TYPES: [CompressionType; 2] = [Zip, Directory];
+        static COMPRESSION_TYPES: [CompressionType; 5] = [Zip, Directory, TarGz, TarZst, SevenZ];
         COMPRESSION_TYPES.iter()
     }
 
     pub fn compression_formats() -> Iter<'static, Self> {
-        static COMPRESSION_TYPES: [CompressionType; 1] = [Zip];
+        static COMPRESSION_TYPES: [CompressionType; 4] = [Zip, TarGz, TarZst, SevenZ];
         COMPRESSION_TYPES.iter()
     }
 
     pub fn possible_output(sandboxed: bool) -> Iter<'static, Self> {
-        static COMPRESSION_TYPES: [CompressionType; 1] = [Zip];
-        static ALL_TYPES: [CompressionType; 2] = [Zip, Directory];
+        static COMPRESSION_TYPES: [CompressionType; 4] = [Zip, TarGz, TarZst, SevenZ];
+        static ALL_TYPES: [CompressionType; 5] = [Zip, Directory, TarGz, TarZst, SevenZ];
         match sandboxed {
             true => COMPRESSION_TYPES.iter(),
             false => ALL_TYPES.iter(),
@@ -240,6 +398,9 @@ impl CompressionType {
         match self {
             Zip => "application/zip",
             Directory => "inode/directory",
+            TarGz => "application/x-compressed-tar",
+            TarZst => "application/x-zstd-compressed-tar",
+            SevenZ => "application/x-7z-compressed",
         }
     }
 
@@ -247,6 +408,9 @@ impl CompressionType {
         match self {
             Zip => "zip",
             Directory => "directory",
+            TarGz => "tar.gz",
+            TarZst => "tar.zst",
+            SevenZ => "7z",
         }
     }
 
@@ -261,9 +425,31 @@ impl CompressionType {
         match extension {
             "zip" => Some(Zip),
             "directory" => Some(Directory),
+            "tar.gz" | "tgz" => Some(TarGz),
+            "tar.zst" | "tzst" => Some(TarZst),
+            "7z" => Some(SevenZ),
             _ => None,
         }
     }
+
+    /// The archive type of an input file, from its mimetype.
+    pub fn from_mimetype(mimetype: &str) -> Option<Self> {
+        Self::compression_formats()
+            .find(|c| c.as_mime() == mimetype)
+            .copied()
+    }
+
+    /// The archive type a file name points to, from its extension.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if let Some(archive) =
+            Self::compression_formats().find(|c| name.ends_with(&format!(".{}", c.as_extension())))
+        {
+            return Some(*archive);
+        }
+        let (_, extension) = name.rsplit_once('.')?;
+        Self::from_string(extension).filter(|c| c.is_compression())
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -284,6 +470,9 @@ impl OutputType {
         match extension {
             "zip" => Some(OutputType::Compression(Zip)),
             "directory" => Some(OutputType::Compression(Directory)),
+            "tar.gz" => Some(OutputType::Compression(TarGz)),
+            "tar.zst" => Some(OutputType::Compression(TarZst)),
+            "7z" => Some(OutputType::Compression(SevenZ)),
             "png" => Some(OutputType::File(Png)),
             "jpg" => Some(OutputType::File(Jpg)),
             "jpeg" => Some(OutputType::File(Jpg)),
@@ -303,6 +492,17 @@ impl OutputType {
         }
     }
 
+    /// The output type a file name points to, taking double extensions such
+    /// as `.tar.gz` into account.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        CompressionType::from_file_name(name)
+            .map(OutputType::Compression)
+            .or_else(|| {
+                let (_, extension) = name.rsplit_once('.')?;
+                Self::from_string(&extension.to_lowercase())
+            })
+    }
+
     pub fn as_extension(&self) -> &str {
         match self {
             OutputType::File(f) => f.as_extension(),
@@ -310,3 +510,80 @@ impl OutputType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ISOBMFF `ftyp` box with `major` and `compatible` brands.
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let mut data = ((16 + 4 * compatible.len()) as u32).to_be_bytes().to_vec();
+        data.extend(b"ftyp");
+        data.extend(major);
+        data.extend(0u32.to_be_bytes());
+        compatible.iter().for_each(|brand| data.extend(*brand));
+        data
+    }
+
+    fn signatures() -> Vec<(FileType, Vec<u8>)> {
+        vec![
+            (Png, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec()),
+            (Jpg, b"\xff\xd8\xff\xe0\0\x10JFIF\0".to_vec()),
+            (Gif, b"GIF89a\x01\0\x01\0".to_vec()),
+            (Gif, b"GIF87a\x01\0\x01\0".to_vec()),
+            (Webp, b"RIFF\x24\0\0\0WEBPVP8 ".to_vec()),
+            (Tiff, b"II*\0\x08\0\0\0".to_vec()),
+            (Tiff, b"MM\0*\0\0\0\x08".to_vec()),
+            (Heic, ftyp(b"heic", &[b"mif1", b"heic"])),
+            (Heif, ftyp(b"mif1", &[b"mif1"])),
+            (Avif, ftyp(b"avif", &[b"mif1", b"miaf"])),
+            // The major brand is only one of the compatible ones.
+            (Avif, ftyp(b"mif1", &[b"miaf", b"avif"])),
+            (Pdf, b"%PDF-1.7\n".to_vec()),
+            (Pdf, b"\r\ngarbage%PDF-1.4\n".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn detects_files_by_their_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        for (i, (kind, data)) in signatures().into_iter().enumerate() {
+            // Without an extension, and with that of another format.
+            for name in [format!("{i}"), format!("{i}.png"), format!("{i}.txt")] {
+                let path = dir.path().join(&name);
+                std::fs::write(&path, &data).unwrap();
+                assert_eq!(
+                    FileType::detect(&path, Some("image/bmp")),
+                    Some(kind),
+                    "{name}"
+                );
+                assert_eq!(FileType::detect(&path, None), Some(kind), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_mimetype() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.jpg");
+        for data in [&b""[..], b"not an image", &ftyp(b"isom", &[b"mp41"])] {
+            std::fs::write(&path, data).unwrap();
+            assert_eq!(FileType::detect(&path, Some("image/png")), Some(Png));
+            assert_eq!(FileType::detect(&path, None), None);
+        }
+        assert_eq!(
+            FileType::detect(&dir.path().join("missing.png"), Some("image/png")),
+            Some(Png)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_signatures() {
+        for (kind, data) in signatures() {
+            if kind == Pdf {
+                continue;
+            }
+            assert_eq!(FileType::from_signature(&data[..2]), None, "{kind:?}");
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use gtk::{gio, glib, prelude::*};
+use log::warn;
+
+const INCLUDE_KEY: &str = "folder-include";
+const EXCLUDE_KEY: &str = "folder-exclude";
+const HIDDEN_KEY: &str = "folder-hidden";
+
+/// Which files are imported when walking a folder.
+///
+/// Patterns are shell-style globs: `*` and `?` stop at `/` while `**` crosses
+/// folders. A pattern containing `/` is matched against the path relative to
+/// the folder, any other against the file or folder name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FolderFilter {
+    /// Only files matching one of these are imported, every file when empty.
+    pub include: Vec<String>,
+    /// Files and folders matching one of these are skipped.
+    pub exclude: Vec<String>,
+    pub include_hidden: bool,
+}
+
+impl FolderFilter {
+    pub fn load(settings: &gio::Settings) -> Self {
+        let patterns = |key| settings.value(key).get::<Vec<String>>().unwrap_or_default();
+        Self {
+            include: patterns(INCLUDE_KEY),
+            exclude: patterns(EXCLUDE_KEY),
+            include_hidden: settings.boolean(HIDDEN_KEY),
+        }
+    }
+
+    pub fn save(&self, settings: &gio::Settings) -> Result<(), glib::BoolError> {
+        settings.set_value(INCLUDE_KEY, &self.include.to_variant())?;
+        settings.set_value(EXCLUDE_KEY, &self.exclude.to_variant())?;
+        settings.set_boolean(HIDDEN_KEY, self.include_hidden)
+    }
+
+    /// Splits a comma separated list of patterns, as typed by the user.
+    pub fn parse_patterns(text: &str) -> Vec<String> {
+        text.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn accepts_folder(&self, relative_path: &Path) -> bool {
+        self.accepts_hidden(relative_path) && !matches_any(&self.exclude, relative_path)
+    }
+
+    fn accepts_file(&self, relative_path: &Path) -> bool {
+        self.accepts_folder(relative_path)
+            && (self.include.is_empty() || matches_any(&self.include, relative_path))
+    }
+
+    fn accepts_hidden(&self, relative_path: &Path) -> bool {
+        self.include_hidden
+            || !relative_path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+    }
+}
+
+/// Every file under `root` accepted by `filter`, relative to `root` and
+/// sorted. Symbolic links to folders are not followed.
+pub fn walk(root: &Path, filter: &FolderFilter) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut folders = vec![PathBuf::new()];
+
+    while let Some(folder) = folders.pop() {
+        let entries = match fs::read_dir(root.join(&folder)) {
+            Ok(entries) => entries,
+            Err(e) if folder.as_os_str().is_empty() => return Err(e),
+            Err(e) => {
+                warn!("Could not read {}: {e}", root.join(&folder).display());
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let relative_path = folder.join(entry.file_name());
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if filter.accepts_folder(&relative_path) {
+                    folders.push(relative_path);
+                }
+            } else if (file_type.is_file() || entry.path().is_file())
+                && filter.accepts_file(&relative_path)
+            {
+                files.push(relative_path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn matches_any(patterns: &[String], relative_path: &Path) -> bool {
+    let path = relative_path.to_string_lossy();
+    let name = relative_path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    patterns.iter().any(|pattern| {
+        let text = match pattern.contains('/') {
+            true => &path,
+            false => &name,
+        };
+        glob_match(
+            &pattern.chars().collect::<Vec<_>>(),
+            &text.chars().collect::<Vec<_>>(),
+        )
+    })
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            glob_match(rest, text)
+                || (0..text.len()).any(|i| text[i] == '/' && glob_match(rest, &text[i + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        glob_match(
+            &pattern.chars().collect::<Vec<_>>(),
+            &text.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn touch(root: &Path, names: &[&str]) {
+        for name in names {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+    }
+
+    #[test]
+    fn stops_single_stars_at_folders() {
+        assert!(matches("*.png", "a.png"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b", "ab"));
+        assert!(!matches("*.png", "a/b.png"));
+        assert!(matches("*/*.png", "a/b.png"));
+        assert!(!matches("*/*.png", "a/b/c.png"));
+        assert!(!matches("*.png", "a.jpg"));
+    }
+
+    #[test]
+    fn crosses_any_number_of_folders_with_double_stars() {
+        assert!(matches("**/*.png", "a.png"));
+        assert!(matches("**/*.png", "a/a.png"));
+        assert!(matches("**/*.png", "a/b/c/a.png"));
+        assert!(matches("raw/**/*.png", "raw/a.png"));
+        assert!(matches("raw/**/*.png", "raw/a/b/a.png"));
+        assert!(!matches("raw/**/*.png", "other/raw/a.png"));
+        assert!(matches("raw/**", "raw/a/b.png"));
+        assert!(!matches("**/*.png", "a/b.jpg"));
+    }
+
+    #[test]
+    fn matches_single_characters() {
+        assert!(matches("a?.png", "ab.png"));
+        assert!(!matches("a?.png", "a.png"));
+        assert!(!matches("a?.png", "abc.png"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn matches_names_or_paths() {
+        let names = patterns(&["*.png"]);
+        assert!(matches_any(&names, Path::new("a/b/c.png")));
+        assert!(!matches_any(&names, Path::new("a/b/c.jpg")));
+
+        let paths = patterns(&["b/*.png"]);
+        assert!(matches_any(&paths, Path::new("b/c.png")));
+        assert!(!matches_any(&paths, Path::new("a/b/c.png")));
+        assert!(!matches_any(&patterns(&[]), Path::new("a.png")));
+    }
+
+    #[test]
+    fn skips_hidden_files_unless_asked() {
+        let root = tempfile::tempdir().unwrap();
+        touch(
+            root.path(),
+            &["a.png", ".b.png", ".cache/c.png", "d/.e.png"],
+        );
+
+        let filter = FolderFilter::default();
+        assert_eq!(walk(root.path(), &filter).unwrap(), [Path::new("a.png")]);
+
+        let filter = FolderFilter {
+            include_hidden: true,
+            ..FolderFilter::default()
+        };
+        assert_eq!(
+            walk(root.path(), &filter).unwrap(),
+            [".b.png", ".cache/c.png", "a.png", "d/.e.png"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn prunes_excluded_folders() {
+        let root = tempfile::tempdir().unwrap();
+        touch(
+            root.path(),
+            &[
+                "a.png",
+                "a.jpg",
+                "thumbs/b.png",
+                "raw/c.png",
+                "raw/thumbs/d.png",
+                "raw/e.tmp.png",
+            ],
+        );
+
+        let filter = FolderFilter {
+            include: patterns(&["*.png"]),
+            exclude: patterns(&["thumbs", "*.tmp.png"]),
+            include_hidden: false,
+        };
+        assert_eq!(
+            walk(root.path(), &filter).unwrap(),
+            ["a.png", "raw/c.png"].map(PathBuf::from)
+        );
+
+        let filter = FolderFilter {
+            exclude: patterns(&["raw/thumbs"]),
+            ..FolderFilter::default()
+        };
+        assert_eq!(
+            walk(root.path(), &filter).unwrap(),
+            [
+                "a.jpg",
+                "a.png",
+                "raw/c.png",
+                "raw/e.tmp.png",
+                "thumbs/b.png"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn splits_typed_patterns() {
+        assert_eq!(
+            FolderFilter::parse_patterns(" *.png, ,raw/**,"),
+            ["*.png", "raw/**"]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use gettextrs::{gettext, ngettext};
+
+use crate::filetypes::FileType;
+
+/// What is known of an image before converting it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageInfo {
+    /// Every frame of the image, in order.
+    pub frames: Vec<FrameInfo>,
+    /// Colorspace as ImageMagick names it, like `sRGB` or `CMYK`.
+    pub colorspace: Option<String>,
+    /// Bits per channel.
+    pub depth: Option<usize>,
+    /// Whether a pixel of the image is not fully opaque, which an unused
+    /// alpha channel does not count as.
+    pub has_alpha: bool,
+    /// Horizontal and vertical resolution, in dots per inch.
+    pub dpi: Option<(f64, f64)>,
+    /// Whether the image carries an ICC color profile.
+    pub has_icc: bool,
+    /// EXIF orientation, from 1 to 8.
+    pub orientation: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub width: usize,
+    pub height: usize,
+    /// How long the frame is shown, in hundredths of a second.
+    pub delay: usize,
+}
+
+/// Template given to `magick identify -format`, printing a line of tab
+/// separated fields for each frame. Parsed by [`ImageInfo::from_identify`].
+pub const IDENTIFY_FORMAT: &str = "%w\\t%h\\t%T\\t%[colorspace]\\t%z\\t%[opaque]\\t%x\\t%y\\t%U\\t%[profiles]\\t%[orientation]\\n";
+
+impl ImageInfo {
+    /// An image of a single frame of `width` by `height` pixels.
+    pub fn still(width: usize, height: usize) -> Self {
+        Self {
+            frames: vec![FrameInfo {
+                width,
+                height,
+                delay: 0,
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Reads the output of `magick identify -format` with
+    /// [`IDENTIFY_FORMAT`].
+    pub fn from_identify(output: &str) -> Option<Self> {
+        let mut info = Self::default();
+        for line in output.lines().filter(|l| !l.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [
+                width,
+                height,
+                delay,
+                colorspace,
+                depth,
+                opaque,
+                x,
+                y,
+                units,
+                profiles,
+                orientation,
+            ] = fields[..]
+            else {
+                return None;
+            };
+
+            info.frames.push(FrameInfo {
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+                delay: delay.parse().unwrap_or_default(),
+            });
+            info.has_alpha |= opaque.eq_ignore_ascii_case("false");
+            if info.frames.len() > 1 {
+                continue;
+            }
+
+            info.colorspace = Some(colorspace.to_owned()).filter(|c| !c.is_empty());
+            info.depth = depth.parse().ok();
+            info.dpi = dpi(x, y, units);
+            info.has_icc = profiles
+                .split(',')
+                .any(|p| p.eq_ignore_ascii_case("icc") || p.eq_ignore_ascii_case("icm"));
+            info.orientation = exif_orientation(orientation);
+        }
+        (!info.frames.is_empty()).then_some(info)
+    }
+
+    /// Number of frames, at least one.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len().max(1)
+    }
+
+    /// Whether the image has pixels that are not fully opaque. Images that
+    /// were not probed are assumed to when `kind` supports transparency.
+    pub fn is_transparent(&self, kind: FileType) -> bool {
+        match self.frames.is_empty() {
+            true => kind.supports_alpha(),
+            false => self.has_alpha,
+        }
+    }
+
+    /// Size of the first frame.
+    pub fn dimensions(&self) -> Option<(usize, usize)> {
+        self.frames.first().map(|f| (f.width, f.height))
+    }
+
+    /// What is known beyond the format and size, as shown to people.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![];
+        if self.frames.len() > 1 {
+            let count = self.frames.len();
+            parts.push(
+                ngettext("{} frame", "{} frames", count as u32).replace("{}", &count.to_string()),
+            );
+            let duration = self.frames.iter().map(|f| f.delay).sum::<usize>();
+            if duration > 0 {
+                parts.push(format!("{:.2} s", duration as f64 / 100.0));
+            }
+        }
+        parts.extend(self.colorspace.clone());
+        if let Some(depth) = self.depth {
+            parts.push(gettext("{} bits").replace("{}", &depth.to_string()));
+        }
+        if self.has_alpha {
+            parts.push(gettext("Transparent"));
+        }
+        match self.dpi {
+            Some((x, y)) if x.round() == y.round() => parts.push(format!("{x:.0} DPI")),
+            Some((x, y)) => parts.push(format!("{x:.0}×{y:.0} DPI")),
+            None => {}
+        }
+        if self.has_icc {
+            parts.push(gettext("ICC profile"));
+        }
+        if self.orientation.is_some_and(|o| o != 1) {
+            parts.push(gettext("Rotated"));
+        }
+        parts.join(" · ")
+    }
+}
+
+fn dpi(x: &str, y: &str, units: &str) -> Option<(f64, f64)> {
+    // Older versions follow the resolution with its units.
+    let parse = |r: &str| r.split_whitespace().next()?.parse::<f64>().ok();
+    let (x, y) = (parse(x)?, parse(y)?);
+    if x <= 0.0 || y <= 0.0 {
+        return None;
+    }
+    match units {
+        "PixelsPerCentimeter" => Some((x * 2.54, y * 2.54)),
+        _ => Some((x, y)),
+    }
+}
+
+fn exif_orientation(orientation: &str) -> Option<u8> {
+    match orientation {
+        "TopLeft" => Some(1),
+        "TopRight" => Some(2),
+        "BottomRight" => Some(3),
+        "BottomLeft" => Some(4),
+        "LeftTop" => Some(5),
+        "RightTop" => Some(6),
+        "RightBottom" => Some(7),
+        "LeftBottom" => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_field_of_a_still_image() {
+        let output = "640\t480\t0\tsRGB\t8\tTrue\t72\t72\tPixelsPerInch\texif,icc\tRightTop\n";
+        assert_eq!(
+            ImageInfo::from_identify(output),
+            Some(ImageInfo {
+                frames: vec![FrameInfo {
+                    width: 640,
+                    height: 480,
+                    delay: 0,
+                }],
+                colorspace: Some("sRGB".to_owned()),
+                depth: Some(8),
+                has_alpha: false,
+                dpi: Some((72.0, 72.0)),
+                has_icc: true,
+                orientation: Some(6),
+            })
+        );
+    }
+
+    #[test]
+    fn reads_every_frame() {
+        let output = concat!(
+            "10\t20\t5\tsRGB\t8\tTrue\t\t\tUndefined\t\tUndefined\n",
+            "30\t40\t15\tGray\t16\tFalse\t300\t300\tPixelsPerInch\ticc\tLeftTop\n",
+        );
+        let info = ImageInfo::from_identify(output).unwrap();
+
+        assert_eq!(info.frame_count(), 2);
+        assert_eq!(info.dimensions(), Some((10, 20)));
+        assert_eq!(info.frames[1].delay, 15);
+        // Only the first frame describes the image, but any transparent
+        // frame makes it transparent.
+        assert_eq!(info.colorspace.as_deref(), Some("sRGB"));
+        assert_eq!(info.dpi, None);
+        assert!(!info.has_icc);
+        assert_eq!(info.orientation, None);
+        assert!(info.has_alpha);
+        assert!(info.is_transparent(FileType::Jpg));
+        assert_eq!(
+            info.summary(),
+            "2 frames · 0.20 s · sRGB · 8 bits · Transparent"
+        );
+    }
+
+    #[test]
+    fn leaves_out_missing_resolutions() {
+        for (x, y, units) in [
+            ("", "", "Undefined"),
+            ("0", "0", "PixelsPerInch"),
+            ("72", "", "PixelsPerInch"),
+        ] {
+            let output = format!("1\t1\t0\tsRGB\t8\tTrue\t{x}\t{y}\t{units}\t\tUndefined\n");
+            let info = ImageInfo::from_identify(&output).unwrap();
+            assert_eq!(info.dpi, None, "{x:?} {y:?}");
+        }
+
+        let output = "1\t1\t0\tsRGB\t8\tTrue\t28.35 PixelsPerCentimeter\t28.35\tPixelsPerCentimeter\t\tUndefined\n";
+        let (x, y) = ImageInfo::from_identify(output).unwrap().dpi.unwrap();
+        assert_eq!((x.round(), y.round()), (72.0, 72.0));
+    }
+
+    #[test]
+    fn tells_transparency_apart_from_an_alpha_channel() {
+        let opaque = ImageInfo::from_identify("1\t1\t0\tsRGB\t8\tTrue\t\t\t\t\t\n").unwrap();
+        assert!(!opaque.is_transparent(FileType::Png));
+        let transparent = ImageInfo::from_identify("1\t1\t0\tsRGB\t8\tfalse\t\t\t\t\t\n").unwrap();
+        assert!(transparent.is_transparent(FileType::Png));
+
+        // Images that were not probed may be transparent if their format is.
+        assert!(ImageInfo::default().is_transparent(FileType::Png));
+        assert!(!ImageInfo::default().is_transparent(FileType::Jpg));
+    }
+
+    #[test]
+    fn rejects_output_that_is_not_the_format() {
+        // The default format of `identify` starts with the file name, which
+        // may itself look like a size.
+        for output in [
+            "",
+            "photo 10x10 .png PNG 640x480 640x480+0+0 8-bit sRGB 1.2KB\n",
+            "a\tb\t0\tsRGB\t8\tTrue\t\t\t\t\t\n",
+            "1\t1\t0\tsRGB\t8\tTrue\t\t\t\t\n",
+            "1\t1\t0\tsRGB\t8\tTrue\t\t\t\t\t\t\n",
+        ] {
+            assert_eq!(ImageInfo::from_identify(output), None, "{output:?}");
+        }
+    }
+}
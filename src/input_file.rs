@@ -1,14 +1,108 @@
-use glib::{ParamSpec, ParamSpecEnum, ParamSpecString, Value};
+use gettextrs::gettext;
+use glib::{ParamSpec, ParamSpecEnum, Value};
 use gtk::{
     gdk::{Texture, gdk_pixbuf::Pixbuf},
     gio, glib,
     prelude::*,
     subclass::prelude::*,
 };
+use log::warn;
 use once_cell::sync::Lazy;
 use std::cell::{Cell, Ref, RefCell};
+use std::fmt;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+use crate::archive::{ExtractLimits, LimitExceeded, extract_images};
+use crate::backend;
+use crate::filetypes::{CompressionType, FileType};
+use crate::folder::{FolderFilter, walk};
+use crate::image_info::ImageInfo;
+use crate::remote;
+use crate::runtime;
+use crate::temp::create_temporary_dir;
+
+/// Files above this size are refused, decoding them would likely run out of
+/// memory.
+const MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// What is extracted from a single archive at most. Each image is held to
+/// [`MAX_FILE_SIZE`] like other files, and so are all of them together.
+const ARCHIVE_LIMITS: ExtractLimits = ExtractLimits {
+    entry_size: MAX_FILE_SIZE,
+    total_size: MAX_FILE_SIZE,
+    entries: 10_000,
+};
+
+/// Why a file could not be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    /// The file is not on this computer and could not be downloaded, with
+    /// the reason why.
+    NotLocal(String),
+    /// The file could not be read, with the reason why.
+    Unreadable(String),
+    /// The file is not an image in a supported format.
+    UnknownFormat,
+    /// The image is in a format nothing installed can read.
+    Unavailable(FileType),
+    /// The file or archive is damaged, with what is wrong with it.
+    Corrupt(String),
+    /// The file is bigger than `limit`, both in bytes.
+    TooLarge { size: u64, limit: u64 },
+    /// The archive holds more than `limit` images.
+    TooManyFiles { limit: usize },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::NotLocal(e) => {
+                write!(f, "{}: {e}", gettext("Could not download the file"))
+            }
+            InputError::Unreadable(e) => write!(f, "{}: {e}", gettext("Could not read the file")),
+            InputError::UnknownFormat => write!(f, "{}", gettext("Unsupported filetype")),
+            InputError::Unavailable(kind) => write!(
+                f,
+                "{}",
+                gettext("The installed ImageMagick cannot read {} images")
+                    .replace("{}", &kind.as_display_string())
+            ),
+            InputError::Corrupt(e) => write!(f, "{}: {e}", gettext("The file is damaged")),
+            InputError::TooLarge { size, limit } => write!(
+                f,
+                "{}",
+                gettext("The file is too large: {size}, the limit is {limit}")
+                    .replace("{size}", &glib::format_size(*size))
+                    .replace("{limit}", &glib::format_size(*limit))
+            ),
+            InputError::TooManyFiles { limit } => write!(
+                f,
+                "{}",
+                gettext("The archive holds more than {} images").replace("{}", &limit.to_string())
+            ),
+        }
+    }
+}
+
+/// A file that could not be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedFile {
+    pub path: String,
+    pub error: InputError,
+}
 
-use crate::filetypes::FileType;
+impl RejectedFile {
+    fn new(path: &str, error: InputError) -> Self {
+        Self {
+            path: path.to_owned(),
+            error,
+        }
+    }
+}
 
 mod imp {
 
@@ -17,13 +111,20 @@ mod imp {
     use super::*;
 
     pub struct InputFile {
-        pub path: RefCell<String>,
+        pub path: RefCell<PathBuf>,
         pub kind: Cell<FileType>,
         pub pixbuf: RefCell<Option<Texture>>,
-        pub frames: Cell<usize>,
         pub is_behind_sandbox: Cell<bool>,
-        pub width: Cell<Option<usize>>,
-        pub height: Cell<Option<usize>>,
+        /// What probing the image found, empty until it is probed.
+        pub info: RefCell<ImageInfo>,
+        /// The archive, folder or remote location the file was imported
+        /// from.
+        pub root: RefCell<Option<PathBuf>>,
+        /// Path of the file inside `root`.
+        pub relative_path: RefCell<Option<PathBuf>>,
+        /// Keeps the extracted or downloaded files around as long as they are
+        /// used.
+        pub extraction_dir: RefCell<Option<Arc<TempDir>>>,
     }
 
     #[glib::object_subclass]
@@ -33,13 +134,14 @@ mod imp {
 
         fn new() -> Self {
             Self {
-                path: RefCell::new("/invalid-path".to_string()),
+                path: RefCell::new(PathBuf::from("/invalid-path")),
                 kind: Cell::new(FileType::Unknown),
                 pixbuf: RefCell::new(None),
-                frames: Cell::new(1),
                 is_behind_sandbox: Cell::new(true),
-                width: Cell::new(None),
-                height: Cell::new(None),
+                info: RefCell::new(ImageInfo::default()),
+                root: RefCell::new(None),
+                relative_path: RefCell::new(None),
+                extraction_dir: RefCell::new(None),
             }
         }
     }
@@ -48,7 +150,6 @@ mod imp {
         fn properties() -> &'static [ParamSpec] {
             static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
                 vec![
-                    ParamSpecString::builder("path").readwrite().build(),
                     ParamSpecEnum::builder::<FileType>("kind")
                         .readwrite()
                         .build(),
@@ -65,10 +166,6 @@ mod imp {
 
         fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
             match pspec.name() {
-                "path" => {
-                    let p = value.get::<String>().expect("Value must be a String");
-                    self.path.replace(p);
-                }
                 "kind" => {
                     let p = value.get::<FileType>().expect("Value must be a filetype");
                     self.kind.set(p);
@@ -87,7 +184,6 @@ mod imp {
 
         fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
             match pspec.name() {
-                "path" => self.path.borrow().to_value(),
                 "kind" => self.kind.get().to_value(),
                 "is-behind-sandbox" => self.is_behind_sandbox.get().to_value(),
                 _ => unimplemented!(),
@@ -106,30 +202,313 @@ impl Default for InputFile {
     }
 }
 
-impl InputFile {
-    pub fn new(file: &gio::File) -> Option<Self> {
-        let path = file.path().unwrap();
-        let is_behind_sandbox = !path.starts_with("/home");
+/// How far importing got, as reported by [`InputFile::load_all`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportProgress {
+    /// Number of images found so far.
+    Found(usize),
+    /// Bytes of a remote file downloaded so far, and its size if known.
+    Downloading(u64, Option<u64>),
+}
+
+/// Counts the images found while importing and passes the progress on.
+struct Progress<'a> {
+    found: usize,
+    report: &'a mut dyn FnMut(ImportProgress),
+}
 
-        let file_info = file
+impl<'a> Progress<'a> {
+    fn new(report: &'a mut dyn FnMut(ImportProgress)) -> Self {
+        Self { found: 0, report }
+    }
+
+    fn found(&mut self) {
+        self.found += 1;
+        (self.report)(ImportProgress::Found(self.found));
+    }
+
+    fn downloading(&mut self, done: u64, total: Option<u64>) {
+        (self.report)(ImportProgress::Downloading(done, total));
+    }
+}
+
+/// A file found while importing, before it becomes an [`InputFile`]. Unlike
+/// those, it can be sent across threads, so importing can run off the main
+/// thread.
+#[derive(Debug)]
+struct FoundFile {
+    path: PathBuf,
+    kind: FileType,
+    /// The archive, folder or remote location the file was found in.
+    root: Option<PathBuf>,
+    /// Path of the file inside `root`.
+    relative_path: Option<PathBuf>,
+    /// Folder holding the file when it was extracted or downloaded.
+    dir: Option<Arc<TempDir>>,
+}
+
+type Found = (Vec<FoundFile>, Vec<RejectedFile>);
+
+impl FoundFile {
+    /// Checks that `file` is an image that can be converted.
+    fn new(file: &gio::File) -> Result<Self, InputError> {
+        let path = file
+            .path()
+            .ok_or_else(|| InputError::NotLocal(gettext("The file has no local path")))?;
+
+        let unreadable = |e: std::io::Error| InputError::Unreadable(e.to_string());
+        let size = std::fs::metadata(&path).map_err(unreadable)?.len();
+        if size > MAX_FILE_SIZE {
+            return Err(InputError::TooLarge {
+                size,
+                limit: MAX_FILE_SIZE,
+            });
+        }
+        if size == 0 {
+            return Err(InputError::Corrupt(gettext("The file is empty")));
+        }
+        File::open(&path).map_err(unreadable)?;
+
+        let mimetype = file
             .query_info(
                 gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
                 gio::FileQueryInfoFlags::NONE,
                 gio::Cancellable::NONE,
             )
-            .unwrap();
+            .ok()
+            .and_then(|info| info.content_type());
+
+        let kind = FileType::detect(&path, mimetype.as_ref().map(|m| m.as_str()))
+            .filter(|f| f.is_input())
+            .ok_or(InputError::UnknownFormat)?;
+        if !backend::can_read(kind) {
+            return Err(InputError::Unavailable(kind));
+        }
+        Ok(Self {
+            path,
+            kind,
+            root: None,
+            relative_path: None,
+            dir: None,
+        })
+    }
 
-        let mimetype = file_info.content_type().unwrap().as_str().to_owned();
+    /// Every input `file` holds, see [`InputFile::load`], reporting to
+    /// `progress` along the way.
+    fn load(file: &gio::File, filter: &FolderFilter, progress: &mut Progress) -> Found {
+        let display_path = file
+            .path()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|| file.uri().into());
+        let reject = |error| (vec![], vec![RejectedFile::new(&display_path, error)]);
+
+        if file.path().is_none() {
+            return match Self::from_remote(file, filter, progress) {
+                Ok(loaded) => loaded,
+                Err(e) => reject(e),
+            };
+        }
 
-        let extension = FileType::from_mimetype(&mimetype);
+        if let Some(path) = file.path().filter(|p| p.is_dir()) {
+            return Self::from_folder(&path, filter, progress)
+                .unwrap_or_else(|e| reject(InputError::Unreadable(e.to_string())));
+        }
 
-        extension.map(|extension| {
-            glib::Object::builder::<Self>()
-                .property("path", path.to_str().unwrap())
-                .property("kind", extension)
-                .property("is-behind-sandbox", is_behind_sandbox)
-                .build()
-        })
+        let archive = file
+            .query_info(
+                gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
+                gio::FileQueryInfoFlags::NONE,
+                gio::Cancellable::NONE,
+            )
+            .ok()
+            .and_then(|info| info.content_type())
+            .and_then(|mimetype| CompressionType::from_mimetype(&mimetype));
+
+        match (archive, file.path()) {
+            (Some(kind), Some(path)) => match Self::from_archive(&path, kind, progress) {
+                Ok((files, rejected)) if files.is_empty() && rejected.is_empty() => {
+                    reject(InputError::UnknownFormat)
+                }
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    warn!("Could not extract {}: {e}", path.display());
+                    let exceeded = e.get_ref().and_then(|e| e.downcast_ref::<LimitExceeded>());
+                    reject(match (exceeded, e.kind()) {
+                        (Some(&LimitExceeded::Size { size, limit }), _) => {
+                            InputError::TooLarge { size, limit }
+                        }
+                        (Some(&LimitExceeded::Entries { limit }), _) => {
+                            InputError::TooManyFiles { limit }
+                        }
+                        (None, ErrorKind::NotFound | ErrorKind::PermissionDenied) => {
+                            InputError::Unreadable(e.to_string())
+                        }
+                        (None, _) => InputError::Corrupt(e.to_string()),
+                    })
+                }
+            },
+            _ => match Self::new(file) {
+                Ok(input) => {
+                    progress.found();
+                    (vec![input], vec![])
+                }
+                Err(e) => reject(e),
+            },
+        }
+    }
+
+    /// Downloads `file` into a temporary folder and loads it from there.
+    fn from_remote(
+        file: &gio::File,
+        filter: &FolderFilter,
+        progress: &mut Progress,
+    ) -> Result<Found, InputError> {
+        let size = remote::size(file);
+        if let Some(size) = size.filter(|s| *s > MAX_FILE_SIZE) {
+            return Err(InputError::TooLarge {
+                size,
+                limit: MAX_FILE_SIZE,
+            });
+        }
+        let (path, dir) =
+            remote::download(file, |done| progress.downloading(done, size)).map_err(|e| {
+                warn!("Could not download {}: {e}", file.uri());
+                InputError::NotLocal(e.to_string())
+            })?;
+
+        let uri = file.uri().to_string();
+        let local = path.to_string_lossy().into_owned();
+        let (mut files, mut rejected) = Self::load(&gio::File::for_path(&path), filter, progress);
+        let dir = Arc::new(dir);
+        for input in files.iter_mut() {
+            input.root = Some(PathBuf::from(&uri));
+            input.dir.get_or_insert_with(|| dir.clone());
+        }
+        for r in rejected.iter_mut() {
+            r.path = r.path.replacen(&local, &uri, 1);
+        }
+        Ok((files, rejected))
+    }
+
+    fn from_archive(
+        path: &Path,
+        kind: CompressionType,
+        progress: &mut Progress,
+    ) -> std::io::Result<Found> {
+        let dir = runtime().block_on(create_temporary_dir())?;
+        let images = extract_images(path, kind, dir.path(), ARCHIVE_LIMITS)?;
+        let dir = Arc::new(dir);
+
+        let mut loaded = (vec![], vec![]);
+        for relative_path in images {
+            match Self::new(&gio::File::for_path(dir.path().join(&relative_path))) {
+                Ok(file) => {
+                    progress.found();
+                    loaded.0.push(Self {
+                        root: Some(path.to_owned()),
+                        relative_path: Some(relative_path),
+                        dir: Some(dir.clone()),
+                        ..file
+                    });
+                }
+                Err(error) => {
+                    let display_path = path.join(&relative_path).to_string_lossy().into_owned();
+                    loaded.1.push(RejectedFile::new(&display_path, error));
+                }
+            }
+        }
+        Ok(loaded)
+    }
+
+    fn from_folder(
+        path: &Path,
+        filter: &FolderFilter,
+        progress: &mut Progress,
+    ) -> std::io::Result<Found> {
+        let mut loaded = (vec![], vec![]);
+        for relative_path in walk(path, filter)? {
+            let file_path = path.join(&relative_path);
+            match Self::new(&gio::File::for_path(&file_path)) {
+                Ok(file) => {
+                    progress.found();
+                    loaded.0.push(Self {
+                        root: Some(path.to_owned()),
+                        relative_path: Some(relative_path),
+                        ..file
+                    });
+                }
+                // Folders are expected to hold other files too.
+                Err(InputError::UnknownFormat) => {}
+                Err(error) => {
+                    let display_path = file_path.to_string_lossy().into_owned();
+                    loaded.1.push(RejectedFile::new(&display_path, error));
+                }
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+impl InputFile {
+    pub fn new(file: &gio::File) -> Result<Self, InputError> {
+        FoundFile::new(file).map(Self::from_found)
+    }
+
+    fn from_found(found: FoundFile) -> Self {
+        let is_behind_sandbox = !found.path.starts_with("/home");
+        let input = glib::Object::builder::<Self>()
+            .property("kind", found.kind)
+            .property("is-behind-sandbox", is_behind_sandbox)
+            .build();
+        let imp = input.imp();
+        // Not a property, paths need not be valid UTF-8.
+        imp.path.replace(found.path);
+        imp.root.replace(found.root);
+        imp.relative_path.replace(found.relative_path);
+        imp.extraction_dir.replace(found.dir);
+        input
+    }
+
+    /// Every input `file` holds: the image itself, the supported images
+    /// inside it when it is an archive, or those `filter` accepts when it is a
+    /// folder. Files that could not be imported are returned apart, except
+    /// for files of folders that simply are not images.
+    pub fn load(file: &gio::File, filter: &FolderFilter) -> (Vec<Self>, Vec<RejectedFile>) {
+        let (files, rejected) = FoundFile::load(file, filter, &mut Progress::new(&mut |_| {}));
+        (files.into_iter().map(Self::from_found).collect(), rejected)
+    }
+
+    /// Loads every file of `files` like [`InputFile::load`], on another
+    /// thread as walking folders, extracting archives and downloading can
+    /// take a while. `progress` is told how far it got.
+    pub async fn load_all(
+        files: Vec<gio::File>,
+        filter: FolderFilter,
+        progress: impl Fn(ImportProgress),
+    ) -> (Vec<Self>, Vec<RejectedFile>) {
+        let uris: Vec<String> = files.iter().map(|f| f.uri().into()).collect();
+        let (sender, receiver) = async_channel::unbounded();
+        let loading = gio::spawn_blocking(move || {
+            let mut report = |p| {
+                sender.send_blocking(p).ok();
+            };
+            let mut progress = Progress::new(&mut report);
+            let mut loaded: Found = (vec![], vec![]);
+            for uri in uris {
+                let (files, rejected) =
+                    FoundFile::load(&gio::File::for_uri(&uri), &filter, &mut progress);
+                loaded.0.extend(files);
+                loaded.1.extend(rejected);
+            }
+            loaded
+        });
+
+        // The channel closes once loading is done.
+        while let Ok(p) = receiver.recv().await {
+            progress(p);
+        }
+        let (files, rejected) = loading.await.unwrap_or_default();
+        (files.into_iter().map(Self::from_found).collect(), rejected)
     }
 
     pub fn empty() -> Self {
@@ -140,50 +519,82 @@ impl InputFile {
         self.imp().pixbuf.borrow()
     }
 
+    pub fn info(&self) -> Ref<'_, ImageInfo> {
+        self.imp().info.borrow()
+    }
+
+    pub fn set_info(&self, info: ImageInfo) {
+        self.imp().info.replace(info);
+    }
+
     pub fn frames(&self) -> usize {
-        self.imp().frames.get()
+        self.info().frame_count()
     }
 
     pub fn width(&self) -> Option<usize> {
-        self.imp().width.get()
+        self.dimensions().map(|(w, _)| w)
     }
 
     pub fn height(&self) -> Option<usize> {
-        self.imp().height.get()
+        self.dimensions().map(|(_, h)| h)
     }
 
     pub fn dimensions(&self) -> Option<(usize, usize)> {
-        let (w, h) = (self.width(), self.height());
-        w.and_then(|w| h.map(|h| (w, h)))
+        self.info().dimensions()
     }
 
-    pub fn set_frames(&self, f: usize) {
-        self.imp().frames.replace(f);
+    pub fn has_alpha(&self) -> bool {
+        self.info().is_transparent(self.kind())
     }
 
-    pub fn set_width(&self, f: usize) {
-        self.imp().width.replace(Some(f));
+    pub fn area(&self) -> Option<usize> {
+        self.dimensions().map(|(w, h)| w * h)
     }
 
-    pub fn set_height(&self, f: usize) {
-        self.imp().height.replace(Some(f));
+    pub fn set_pixbuf(&self, p: Texture) {
+        self.imp().pixbuf.replace(Some(p));
     }
 
-    pub fn area(&self) -> Option<usize> {
-        let (w, h) = (self.width(), self.height());
-        w.and_then(|w| h.map(|h| w * h))
+    pub fn path(&self) -> PathBuf {
+        self.imp().path.borrow().clone()
     }
 
-    pub fn set_pixbuf(&self, p: Texture) {
-        self.imp().pixbuf.replace(Some(p));
+    pub fn root(&self) -> Option<PathBuf> {
+        self.imp().root.borrow().clone()
+    }
+
+    pub fn relative_path(&self) -> Option<PathBuf> {
+        self.imp().relative_path.borrow().clone()
+    }
+
+    /// Folder holding the file relative to its root, `None` when it is
+    /// directly in it.
+    pub fn relative_dir(&self) -> Option<PathBuf> {
+        self.relative_path()?
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(Path::to_owned)
+    }
+
+    /// Whether the file is an extracted or downloaded copy, deleted once it
+    /// is no longer used.
+    pub fn is_temporary(&self) -> bool {
+        self.imp().extraction_dir.borrow().is_some()
     }
 
-    pub fn path(&self) -> String {
-        self.imp().path.borrow().to_string()
+    /// Where the file comes from, pointing inside its archive for extracted
+    /// ones and to its URI for downloaded ones.
+    pub fn display_path(&self) -> String {
+        let path = match (self.root(), self.relative_path()) {
+            (Some(root), Some(relative_path)) => root.join(relative_path),
+            (Some(root), None) => root,
+            _ => self.path(),
+        };
+        path.to_string_lossy().into_owned()
     }
 
     pub fn exists(&self) -> bool {
-        std::path::Path::new(&self.path()).exists()
+        self.imp().path.borrow().exists()
     }
 
     pub fn is_behind_sandbox(&self) -> bool {
@@ -1,48 +1,133 @@
-use crate::{color::Color, filetypes::FileType, window::ResizeFilter};
+use crate::backend::ConversionBackend;
+use crate::capabilities::magick_capabilities;
+use crate::image_info::{IDENTIFY_FORMAT, ImageInfo};
+use crate::metadata::MetadataPolicy;
+use crate::scheduler::Step;
+use crate::{color::Color, filetypes::FileType};
 use gettextrs::gettext;
-use itertools::Itertools;
+use log::debug;
 use shared_child::SharedChild;
+use std::ffi::{OsStr, OsString};
 use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-pub async fn count_frames(path: String) -> Result<(usize, Option<(usize, usize)>), ()> {
-    let command = tokio::process::Command::new("magick")
-        .stdout(std::process::Stdio::piped())
+/// The argument naming the file at `path` for `magick`, read or written as
+/// `kind` whatever its name. Only the given frame is read if any.
+///
+/// Without the explicit coder, names like `msl:script.xml`, `label:@secret`
+/// or `-write out.png` would be taken for a coder or an option rather than a
+/// file.
+pub fn file_argument(kind: FileType, path: &Path, frame: Option<usize>) -> OsString {
+    let mut argument = OsString::new();
+    if kind != FileType::Unknown {
+        argument.push(kind.as_coder());
+        argument.push(":");
+    }
+    if path.is_relative() {
+        argument.push("./");
+    }
+    argument.push(path);
+    if let Some(frame) = frame {
+        argument.push(format!("[{frame}]"));
+    }
+    argument
+}
+
+/// Like [`file_argument`], for the file `magick` writes. A `%` in its name is
+/// escaped, as it would otherwise start a scene number like `%d`.
+pub fn output_argument(kind: FileType, path: &Path) -> OsString {
+    let mut escaped = Vec::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte == b'%' {
+            escaped.push(b'%');
+        }
+        escaped.push(byte);
+    }
+    file_argument(kind, Path::new(OsStr::from_bytes(&escaped)), None)
+}
+
+/// Converts with the `magick` command, supporting every format.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageMagick;
+
+impl ConversionBackend for ImageMagick {
+    fn name(&self) -> &'static str {
+        "ImageMagick"
+    }
+
+    fn can_probe(&self, kind: FileType) -> bool {
+        self.can_decode(kind)
+    }
+
+    fn can_decode(&self, kind: FileType) -> bool {
+        kind.is_input() && magick_capabilities().can_read(kind)
+    }
+
+    fn can_encode(&self, kind: FileType) -> bool {
+        kind.is_output() && magick_capabilities().can_write(kind)
+    }
+
+    fn probe(&self, path: &Path, kind: FileType) -> Result<ImageInfo, ()> {
+        identify(path, kind)
+    }
+
+    fn convert(&self, job: &MagickConvertJob) -> Step {
+        Step::Command(job.get_command())
+    }
+}
+
+/// Describes every frame of the image at `path` with `magick identify`.
+fn identify(path: &Path, kind: FileType) -> Result<ImageInfo, ()> {
+    let output = Command::new("magick")
+        .stdout(Stdio::piped())
         .arg("identify")
-        .arg(path)
+        .arg("-format")
+        .arg(IDENTIFY_FORMAT)
+        .arg(file_argument(kind, path, None))
         .output()
-        .await;
+        .map_err(|_| ())?;
+    ImageInfo::from_identify(&String::from_utf8_lossy(&output.stdout)).ok_or(())
+}
 
-    match command {
-        Ok(output) => match std::str::from_utf8(&output.stdout) {
-            Ok(output_string) => {
-                let lines = output_string.lines().collect_vec();
-                let count = lines.len();
-                let dims = lines
-                    .first()
-                    .and_then(|line| {
-                        let dimension_match = regex::Regex::new(r" \d+x\d+ ").unwrap().find(line);
-
-                        dimension_match.map(|m| {
-                            let dims = m
-                                .as_str()
-                                .trim()
-                                .split('x')
-                                .map(|n| n.parse::<usize>())
-                                .collect_vec();
-                            match dims[..] {
-                                [Ok(width), Ok(height)] => Some((width, height)),
-                                _ => None,
-                            }
-                        })
-                    })
-                    .flatten();
-                Ok((count, dims))
-            }
-            _ => Err(()),
-        },
-        _ => Err(()),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Default,
+    Point,
+}
+
+#[allow(dead_code)]
+impl ResizeFilter {
+    pub fn as_display_string(&self) -> Option<&str> {
+        match self {
+            ResizeFilter::Default => None,
+            ResizeFilter::Point => Some("Point"),
+        }
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(ResizeFilter::Default),
+            1 => Some(ResizeFilter::Point),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> &str {
+        match self {
+            ResizeFilter::Default => "default",
+            ResizeFilter::Point => "point",
+        }
+    }
+
+    pub fn from_string(filter: &str) -> Option<Self> {
+        match filter {
+            "default" => Some(ResizeFilter::Default),
+            "point" => Some(ResizeFilter::Point),
+            _ => None,
+        }
     }
 }
 
@@ -65,6 +150,42 @@ impl Default for ResizeArgument {
     }
 }
 
+impl ResizeArgument {
+    /// Parses `50%`, `50%x25%` or `1920x1080`. Sizes have to be positive.
+    pub fn from_string(resize: &str) -> Option<Self> {
+        let size = |n: &str| n.parse::<usize>().ok().filter(|n| *n > 0);
+        let resize = resize.trim();
+        if let Some((width, height)) = resize.split_once('x')
+            && let (Some(width), Some(height)) = (width.strip_suffix('%'), height.strip_suffix('%'))
+        {
+            return Some(Self::Percentage {
+                width: size(width)?,
+                height: size(height)?,
+            });
+        }
+        if let Some(percentage) = resize.strip_suffix('%') {
+            let percentage = size(percentage)?;
+            return Some(Self::Percentage {
+                width: percentage,
+                height: percentage,
+            });
+        }
+        let (width, height) = resize.split_once('x')?;
+        Some(Self::ExactPixels {
+            width: size(width)?,
+            height: size(height)?,
+        })
+    }
+
+    pub fn as_string(&self) -> String {
+        match self {
+            Self::Percentage { width, height } if width == height => format!("{width}%"),
+            Self::Percentage { width, height } => format!("{width}%x{height}%"),
+            Self::ExactPixels { width, height } => format!("{width}x{height}"),
+        }
+    }
+}
+
 impl MagickArgument for ResizeFilter {
     fn get_argument(&self) -> Vec<String> {
         match self.as_display_string() {
@@ -101,28 +222,36 @@ where
 
 #[derive(Debug, Clone)]
 pub struct MagickConvertJob {
-    pub input_file: String,
-    pub output_file: String,
+    pub input_file: PathBuf,
+    pub input_type: FileType,
+    /// The only frame or page of the input to convert, all of them when
+    /// `None`.
+    pub input_frame: Option<usize>,
+    pub output_file: PathBuf,
+    pub output_type: FileType,
     pub background: Color,
     pub quality: usize,
     pub first_frame: bool,
     pub filter: Option<ResizeFilter>,
     pub resize_arg: ResizeArgument,
     pub density: Option<usize>,
+    /// Drops the alpha channel of an input without transparent pixels, so
+    /// the output does not get one either.
     pub remove_alpha: bool,
+    pub metadata: MetadataPolicy,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct JobFile {
     pub id: usize,
-    pub desired_name: Option<String>,
+    pub desired_name: Option<PathBuf>,
     pub file_extension: FileType,
 }
 
 static FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 impl JobFile {
-    pub fn new(file_extension: FileType, desired_name: Option<String>) -> Self {
+    pub fn new(file_extension: FileType, desired_name: Option<PathBuf>) -> Self {
         let id = FILE_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
         Self {
             id,
@@ -135,19 +264,19 @@ impl JobFile {
         let id = FILE_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
         Self {
             id,
-            desired_name: Some(format!("{}.png", gettext("Pasted Image"))),
+            desired_name: Some(PathBuf::from(format!("{}.png", gettext("Pasted Image")))),
             file_extension: FileType::Png,
         }
     }
 
-    pub fn as_filename(&self) -> String {
+    pub fn as_filename(&self) -> PathBuf {
         match &self.desired_name {
             Some(desired_name) => desired_name.to_owned(),
-            None => format!(
+            None => PathBuf::from(format!(
                 "TEMPORARY_SWITCHEROO_{}.{}",
                 self.id,
                 self.file_extension.as_extension()
-            ),
+            )),
         }
     }
 }
@@ -156,20 +285,10 @@ impl MagickConvertJob {
     pub fn get_command(&self) -> Command {
         let mut command = Command::new("magick");
 
-        dbg!(self);
-
-        let input_file_ext = self
-            .input_file
-            .rsplit('.')
-            .next()
-            .unwrap_or("")
-            .split("[")
-            .next()
-            .unwrap_or("")
-            .to_lowercase();
+        debug!("{self:?}");
 
-        let (resize_arg, size_arg) = match input_file_ext.as_str() {
-            "svg" => (
+        let (resize_arg, size_arg) = match self.input_type {
+            FileType::Svg => (
                 vec![],
                 match self.resize_arg {
                     ResizeArgument::ExactPixels { width, height } => {
@@ -184,7 +303,7 @@ impl MagickConvertJob {
                     }
                 },
             ),
-            "pdf" => (
+            FileType::Pdf => (
                 self.resize_arg.get_argument(),
                 if let Some(density) = self.density {
                     vec!["-density".to_owned(), density.to_string()]
@@ -195,14 +314,17 @@ impl MagickConvertJob {
             _ => (self.resize_arg.get_argument(), vec![]),
         };
 
-        dbg!(&resize_arg);
-        dbg!(&size_arg);
+        debug!("{resize_arg:?} {size_arg:?}");
 
         if self.first_frame {
             command
                 .args(size_arg)
                 .args(["-background", &self.background.as_hex_string()])
-                .arg(self.input_file.clone())
+                .arg(file_argument(
+                    self.input_type,
+                    &self.input_file,
+                    self.input_frame,
+                ))
                 .arg("-flatten");
 
             if self.remove_alpha {
@@ -210,14 +332,20 @@ impl MagickConvertJob {
             }
 
             command
+                .args(self.metadata.magick_arguments(self.output_type))
                 .args(["-quality".to_string(), self.quality.to_string()])
                 .args(self.filter.get_argument())
                 .args(resize_arg)
-                .arg(self.output_file.clone());
+                .arg(output_argument(self.output_type, &self.output_file));
         } else {
             command
-                .arg(self.input_file.clone())
+                .arg(file_argument(
+                    self.input_type,
+                    &self.input_file,
+                    self.input_frame,
+                ))
                 .arg("-coalesce")
+                .args(self.metadata.magick_arguments(self.output_type))
                 .args(vec![
                     "-fill",
                     &self.background.as_hex_string(),
@@ -227,7 +355,7 @@ impl MagickConvertJob {
                 .args(vec!["-quality".to_string(), self.quality.to_string()])
                 .args(self.filter.get_argument())
                 .args(resize_arg)
-                .arg(self.output_file.clone());
+                .arg(output_argument(self.output_type, &self.output_file));
         }
 
         command.stdout(Stdio::piped()).stderr(Stdio::piped());
@@ -237,9 +365,10 @@ impl MagickConvertJob {
 }
 
 pub fn generate_job(
-    input_path: &str,
+    input_path: &Path,
+    input_frame: Option<usize>,
     input_type: &FileType,
-    output_path: &str,
+    output_path: &Path,
     output_type: &FileType,
     pdf_dpi: usize,
     default_arguments: &MagickConvertJob,
@@ -248,26 +377,34 @@ pub fn generate_job(
     match (input_type, output_type) {
         (Pdf, _) => std::iter::once(MagickConvertJob {
             input_file: input_path.to_owned(),
+            input_type: *input_type,
+            input_frame,
+            output_type: *output_type,
             output_file: output_path.to_owned(),
             density: Some(pdf_dpi),
-            ..*default_arguments
+            ..default_arguments.clone()
         })
         .collect(),
         (input, output) if input.supports_animation() && output.supports_animation() => {
             std::iter::once(MagickConvertJob {
                 input_file: input_path.to_owned(),
+                input_type: *input_type,
+                input_frame,
+                output_type: *output_type,
                 output_file: output_path.to_owned(),
                 first_frame: false,
-                ..*default_arguments
+                ..default_arguments.clone()
             })
             .collect()
         }
-        (input, output) => std::iter::once(MagickConvertJob {
+        _ => std::iter::once(MagickConvertJob {
             input_file: input_path.to_owned(),
+            input_type: *input_type,
+            input_frame,
+            output_type: *output_type,
             output_file: output_path.to_owned(),
             first_frame: true,
-            remove_alpha: !input.supports_alpha() && output.supports_alpha(),
-            ..*default_arguments
+            ..default_arguments.clone()
         })
         .collect(),
     }
@@ -292,3 +429,136 @@ pub fn wait_for_child(child: std::sync::Arc<SharedChild>) -> Result<(), String>
         Err(_) => Err(gettext("Unknown IO error happened")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names that `magick` would take for a coder, an option, a file list,
+    /// a scene number or a frame.
+    const HOSTILE: [&str; 5] = ["msl:x", "-write", "label:@/etc/passwd", "%d", "[0]"];
+
+    fn job(input: &Path, output: &Path) -> MagickConvertJob {
+        MagickConvertJob {
+            input_file: input.to_owned(),
+            input_type: FileType::Png,
+            input_frame: None,
+            output_file: output.to_owned(),
+            output_type: FileType::Webp,
+            background: Color::rgb(255, 255, 255),
+            quality: 90,
+            first_frame: true,
+            filter: None,
+            resize_arg: ResizeArgument::default(),
+            density: None,
+            remove_alpha: false,
+            metadata: MetadataPolicy::default(),
+        }
+    }
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn prefixes_the_coder_and_anchors_relative_paths() {
+        for name in HOSTILE {
+            assert_eq!(
+                file_argument(FileType::Png, Path::new(name), None),
+                OsString::from(format!("png:./{name}"))
+            );
+            assert_eq!(
+                file_argument(FileType::Jpg, Path::new("/tmp").join(name).as_path(), None),
+                OsString::from(format!("jpeg:/tmp/{name}"))
+            );
+        }
+    }
+
+    #[test]
+    fn selects_frames_after_the_name() {
+        assert_eq!(
+            file_argument(FileType::Gif, Path::new("[0]"), Some(2)),
+            OsString::from("gif:./[0][2]")
+        );
+    }
+
+    #[test]
+    fn escapes_scene_numbers_in_outputs() {
+        assert_eq!(
+            output_argument(FileType::Png, Path::new("%d")),
+            OsString::from("png:./%%d")
+        );
+        assert_eq!(
+            output_argument(FileType::Png, Path::new("/tmp/100%.png")),
+            OsString::from("png:/tmp/100%%.png")
+        );
+    }
+
+    #[test]
+    fn passes_hostile_names_as_files() {
+        for name in HOSTILE {
+            for first_frame in [true, false] {
+                let job = MagickConvertJob {
+                    first_frame,
+                    ..job(Path::new(name), Path::new("/out").join(name).as_path())
+                };
+                let args = args(&job.get_command());
+
+                assert!(!args.iter().any(|a| a == name), "{args:?}");
+                assert!(args.contains(&format!("png:./{name}")), "{args:?}");
+                let output = format!("webp:/out/{}", name.replace('%', "%%"));
+                assert_eq!(args.last(), Some(&output));
+            }
+        }
+    }
+
+    #[test]
+    fn parses_resize_arguments() {
+        for (resize, parsed) in [
+            (
+                "50%",
+                ResizeArgument::Percentage {
+                    width: 50,
+                    height: 50,
+                },
+            ),
+            (
+                " 50%x25% ",
+                ResizeArgument::Percentage {
+                    width: 50,
+                    height: 25,
+                },
+            ),
+            (
+                "1920x1080",
+                ResizeArgument::ExactPixels {
+                    width: 1920,
+                    height: 1080,
+                },
+            ),
+        ] {
+            assert_eq!(
+                ResizeArgument::from_string(resize),
+                Some(parsed),
+                "{resize}"
+            );
+            assert_eq!(
+                ResizeArgument::from_string(&parsed.as_string()),
+                Some(parsed)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_empty_and_negative_sizes() {
+        for resize in [
+            "0x0", "0%", "0x100", "100x0", "0%x50%", "-50%", "-1x10", "10x-1", "", "x", "50",
+            "50%x",
+        ] {
+            assert_eq!(ResizeArgument::from_string(resize), None, "{resize}");
+        }
+    }
+}
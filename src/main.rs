@@ -1,13 +1,30 @@
 mod application;
+mod archive;
+mod backend;
+mod capabilities;
+mod cli;
+mod collect;
 #[rustfmt::skip]
 mod config;
 mod color;
+mod conversion;
 mod drag_overlay;
 mod file_chooser;
 mod filetypes;
+mod folder;
+mod image_info;
 mod input_file;
 mod magick;
+mod metadata;
+mod mirror;
+mod native;
+mod preset;
+mod remote;
+mod scheduler;
+mod service;
 mod temp;
+mod template;
+mod watch;
 mod widgets;
 mod window;
 
@@ -19,6 +36,7 @@ use gtk::{gio, glib};
 use tokio::runtime::Runtime;
 
 use self::application::App;
+use self::capabilities::magick_capabilities;
 use self::config::{GETTEXT_PACKAGE, LOCALEDIR, RESOURCES_FILE};
 
 fn runtime() -> &'static Runtime {
@@ -31,8 +49,6 @@ fn runtime() -> &'static Runtime {
     })
 }
 
-const ZIP_BINARY_NAME: &str = "zip";
-
 fn main() -> ExitCode {
     // Initialize logger
     pretty_env_logger::init();
@@ -47,6 +63,10 @@ fn main() -> ExitCode {
     let res = gio::Resource::load(RESOURCES_FILE).expect("Could not load gresource file");
     gio::resources_register(&res);
 
+    // Probing ImageMagick takes a moment, which is better spent before the
+    // window asks for the formats.
+    std::thread::spawn(magick_capabilities);
+
     let app = App::new();
     app.run()
 }
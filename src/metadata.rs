@@ -0,0 +1,891 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use gettextrs::gettext;
+
+use crate::filetypes::FileType;
+
+/// What happens to the EXIF, XMP, IPTC and ICC metadata of the images
+/// converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataPolicy {
+    /// Everything ImageMagick carries over to the output format.
+    #[default]
+    KeepAll,
+    StripAll,
+    /// The EXIF copyright notice and the color profile.
+    CopyrightOnly,
+    /// The EXIF data but where the image was taken and what with, and the
+    /// color profile. XMP and IPTC data, which may hold either, is removed.
+    ExifOnly,
+}
+
+impl MetadataPolicy {
+    pub fn iterator() -> std::slice::Iter<'static, Self> {
+        use MetadataPolicy::*;
+        static POLICIES: [MetadataPolicy; 4] = [KeepAll, StripAll, CopyrightOnly, ExifOnly];
+        POLICIES.iter()
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::iterator().nth(index).copied()
+    }
+
+    pub fn from_string(policy: &str) -> Option<Self> {
+        match policy {
+            "keep" => Some(MetadataPolicy::KeepAll),
+            "strip" => Some(MetadataPolicy::StripAll),
+            "copyright" => Some(MetadataPolicy::CopyrightOnly),
+            "exif" => Some(MetadataPolicy::ExifOnly),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> &'static str {
+        match self {
+            MetadataPolicy::KeepAll => "keep",
+            MetadataPolicy::StripAll => "strip",
+            MetadataPolicy::CopyrightOnly => "copyright",
+            MetadataPolicy::ExifOnly => "exif",
+        }
+    }
+
+    /// Arguments given to ImageMagick after reading an image converted to
+    /// `output`. Images losing their orientation tag are rotated first, so
+    /// they still show the right way up.
+    pub fn magick_arguments(&self, output: FileType) -> Vec<&'static str> {
+        match self {
+            MetadataPolicy::KeepAll => vec![],
+            MetadataPolicy::StripAll => vec!["-auto-orient", "-strip"],
+            // EXIF data is filtered once written, where it can be found.
+            _ if self.filters_exif(output) => vec!["-auto-orient", "+profile", "!exif,!icc,*"],
+            _ => vec!["-auto-orient", "+profile", "!icc,*"],
+        }
+    }
+
+    /// Whether the EXIF data of an `output` image has to go through
+    /// [`filter_file`] after converting.
+    pub fn filters_exif(&self, output: FileType) -> bool {
+        matches!(
+            self,
+            MetadataPolicy::CopyrightOnly | MetadataPolicy::ExifOnly
+        ) && matches!(
+            output,
+            FileType::Jpg | FileType::Jpeg | FileType::Png | FileType::Webp | FileType::Tiff
+        )
+    }
+
+    fn keeps(&self, ifd: Ifd, tag: u16) -> bool {
+        match (self, ifd) {
+            (MetadataPolicy::KeepAll | MetadataPolicy::StripAll, _) => true,
+            (_, Ifd::Gps) => false,
+            (_, Ifd::Main) if DEVICE_TAGS.contains(&tag) || EMBEDDED_TAGS.contains(&tag) => false,
+            (_, Ifd::Main) if tag == GPS_IFD => false,
+            (MetadataPolicy::CopyrightOnly, Ifd::Main) => {
+                tag != EXIF_IFD && !DESCRIPTIVE_TAGS.contains(&tag)
+            }
+            (MetadataPolicy::ExifOnly, Ifd::Exif) => !EXIF_DEVICE_TAGS.contains(&tag),
+            _ => true,
+        }
+    }
+}
+
+/// The kinds of image file directories in TIFF files and EXIF data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ifd {
+    /// The image, or a page of a TIFF file.
+    Main,
+    /// The preview following the image in EXIF data.
+    Thumbnail,
+    Exif,
+    Gps,
+    Interoperability,
+}
+
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const INTEROPERABILITY_IFD: u16 = 0xa005;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+
+/// Make, Model, Software, HostComputer, UniqueCameraModel and
+/// CameraSerialNumber.
+const DEVICE_TAGS: [u16; 6] = [0x010f, 0x0110, 0x0131, 0x013c, 0xc614, 0xc62f];
+/// XMP, IPTC and Photoshop data embedded in TIFF tags, which can hold a
+/// location too.
+const EMBEDDED_TAGS: [u16; 3] = [0x02bc, 0x83bb, 0x8649];
+/// ImageDescription, DateTime, Artist, Rating, RatingPercent and the
+/// Windows title, comment, author, keywords and subject.
+const DESCRIPTIVE_TAGS: [u16; 10] = [
+    0x010e, 0x0132, 0x013b, 0x4746, 0x4749, 0x9c9b, 0x9c9c, 0x9c9d, 0x9c9e, 0x9c9f,
+];
+/// MakerNote, ImageUniqueID, CameraOwnerName, BodySerialNumber,
+/// LensSpecification, LensMake, LensModel and LensSerialNumber.
+const EXIF_DEVICE_TAGS: [u16; 8] = [
+    0x927c, 0xa420, 0xa430, 0xa431, 0xa432, 0xa433, 0xa434, 0xa435,
+];
+
+fn malformed() -> String {
+    gettext("The metadata of the output is damaged")
+}
+
+/// Filters the EXIF data of the `kind` image at `path` in place, following
+/// `policy`.
+pub fn filter_file(path: &Path, kind: FileType, policy: MetadataPolicy) -> Result<(), String> {
+    let mut data = std::fs::read(path).map_err(|e| e.to_string())?;
+    match kind {
+        FileType::Jpg | FileType::Jpeg => filter_jpeg(&mut data, policy)?,
+        FileType::Png => filter_png(&mut data, policy)?,
+        FileType::Webp => filter_webp(&mut data, policy)?,
+        FileType::Tiff => Tiff::new(&mut data)?.filter(policy, false)?,
+        _ => return Ok(()),
+    }
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+/// Filters the `APP1` segments holding EXIF data, which come before the
+/// image data.
+fn filter_jpeg(data: &mut [u8], policy: MetadataPolicy) -> Result<(), String> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return Err(malformed());
+    }
+    let mut position = 2;
+    while position + 4 <= data.len() {
+        if data[position] != 0xff {
+            return Err(malformed());
+        }
+        match data[position + 1] {
+            // Padding.
+            0xff => {
+                position += 1;
+                continue;
+            }
+            // Start of scan and end of image.
+            0xda | 0xd9 => break,
+            // Markers without a length.
+            0x01 | 0xd0..=0xd7 => {
+                position += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([data[position + 2], data[position + 3]]) as usize;
+        let end = position + 2 + length;
+        if length < 2 || end > data.len() {
+            return Err(malformed());
+        }
+        if data[position + 1] == 0xe1 && data[position + 4..end].starts_with(b"Exif\0\0") {
+            Tiff::new(&mut data[position + 10..end])?.filter(policy, true)?;
+        }
+        position = end;
+    }
+    Ok(())
+}
+
+/// Filters the `eXIf` chunk, and the EXIF data ImageMagick may also write
+/// as text in `Raw profile type exif` or `Raw profile type APP1` chunks.
+fn filter_png(data: &mut Vec<u8>, policy: MetadataPolicy) -> Result<(), String> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !data.starts_with(SIGNATURE) {
+        return Err(malformed());
+    }
+    let mut filtered = SIGNATURE.to_vec();
+    let mut position = SIGNATURE.len();
+    while position + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+        let end = position + 12 + length;
+        if end > data.len() {
+            return Err(malformed());
+        }
+        let name: [u8; 4] = data[position + 4..position + 8].try_into().unwrap();
+        let mut body = data[position + 8..position + 8 + length].to_vec();
+        match &name {
+            b"eXIf" => Tiff::new(&mut body)?.filter(policy, true)?,
+            b"tEXt" | b"zTXt" | b"iTXt" => filter_png_text(&name, &mut body, policy)?,
+            _ => {}
+        }
+        filtered.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        filtered.extend_from_slice(&name);
+        filtered.extend_from_slice(&body);
+        filtered
+            .extend_from_slice(&crc32(&filtered[filtered.len() - body.len() - 4..]).to_be_bytes());
+        position = end;
+    }
+    *data = filtered;
+    Ok(())
+}
+
+/// Filters the raw EXIF profile held by the text chunk `name`, if that is
+/// what its keyword says it holds, compressing it again if it was.
+fn filter_png_text(
+    name: &[u8; 4],
+    body: &mut Vec<u8>,
+    policy: MetadataPolicy,
+) -> Result<(), String> {
+    let Some(keyword_end) = body.iter().position(|&b| b == 0) else {
+        return Ok(());
+    };
+    let keyword = &body[..keyword_end];
+    if !keyword.eq_ignore_ascii_case(b"Raw profile type exif")
+        && !keyword.eq_ignore_ascii_case(b"Raw profile type APP1")
+    {
+        return Ok(());
+    }
+
+    // The keyword is followed by the compression method in zTXt chunks, and
+    // by a compression flag and method, a language and a translated keyword
+    // in iTXt chunks.
+    let (text_start, compressed) = match name {
+        b"zTXt" => (keyword_end + 2, true),
+        b"iTXt" => {
+            let compressed = body.get(keyword_end + 1) == Some(&1);
+            let mut text_start = keyword_end + 3;
+            for _ in 0..2 {
+                let field = body.get(text_start..).ok_or_else(malformed)?;
+                text_start += field.iter().position(|&b| b == 0).ok_or_else(malformed)? + 1;
+            }
+            (text_start, compressed)
+        }
+        _ => (keyword_end + 1, false),
+    };
+    let text = body.get(text_start..).ok_or_else(malformed)?;
+
+    let mut text = match compressed {
+        true => {
+            let mut inflated = vec![];
+            ZlibDecoder::new(text)
+                .read_to_end(&mut inflated)
+                .map_err(|_| malformed())?;
+            inflated
+        }
+        false => text.to_vec(),
+    };
+    filter_raw_profile(&mut text, policy)?;
+    if compressed {
+        let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&text).map_err(|e| e.to_string())?;
+        text = encoder.finish().map_err(|e| e.to_string())?;
+    }
+
+    body.truncate(text_start);
+    body.extend_from_slice(&text);
+    Ok(())
+}
+
+/// Filters a raw profile as ImageMagick writes them: its name, its length
+/// and its bytes in hexadecimal, each on their own lines.
+fn filter_raw_profile(text: &mut Vec<u8>, policy: MetadataPolicy) -> Result<(), String> {
+    let mut fields = text
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|f| !f.is_empty());
+    let profile = fields.next().ok_or_else(malformed)?.to_vec();
+    let length = fields
+        .next()
+        .and_then(|l| std::str::from_utf8(l).ok()?.parse::<usize>().ok())
+        .ok_or_else(malformed)?;
+    let hex = fields.flatten().copied().collect::<Vec<_>>();
+    let mut bytes = hex
+        .chunks(2)
+        .take(length)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|bytes| bytes.len() == length)
+        .ok_or_else(malformed)?;
+
+    let start = if bytes.starts_with(b"Exif\0\0") { 6 } else { 0 };
+    Tiff::new(&mut bytes[start..])?.filter(policy, true)?;
+
+    let mut filtered = b"\n".to_vec();
+    filtered.extend_from_slice(&profile);
+    filtered.extend_from_slice(format!("\n{length:8}\n").as_bytes());
+    for line in bytes.chunks(36) {
+        for byte in line {
+            filtered.extend_from_slice(format!("{byte:02x}").as_bytes());
+        }
+        filtered.push(b'\n');
+    }
+    *text = filtered;
+    Ok(())
+}
+
+/// Filters the `EXIF` chunk of the RIFF container.
+fn filter_webp(data: &mut [u8], policy: MetadataPolicy) -> Result<(), String> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err(malformed());
+    }
+    let mut position = 12;
+    while position + 8 <= data.len() {
+        let size =
+            u32::from_le_bytes(data[position + 4..position + 8].try_into().unwrap()) as usize;
+        let end = position + 8 + size;
+        if end > data.len() {
+            return Err(malformed());
+        }
+        if &data[position..position + 4] == b"EXIF" {
+            let chunk = &mut data[position + 8..end];
+            let start = if chunk.starts_with(b"Exif\0\0") { 6 } else { 0 };
+            Tiff::new(&mut chunk[start..])?.filter(policy, true)?;
+        }
+        position = end + size % 2;
+    }
+    Ok(())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// A TIFF structure, either a whole file or EXIF data, edited in place:
+/// entries are removed by moving the following ones up, and the values
+/// they point to are zeroed, so no offset ever changes.
+struct Tiff<'a> {
+    data: &'a mut [u8],
+    big_endian: bool,
+    /// Directories already walked through, which malformed data could
+    /// otherwise point back to forever.
+    visited: HashSet<usize>,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a mut [u8]) -> Result<Self, String> {
+        let big_endian = match data.get(0..2) {
+            Some(b"II") => false,
+            Some(b"MM") => true,
+            _ => return Err(malformed()),
+        };
+        let tiff = Self {
+            data,
+            big_endian,
+            visited: HashSet::new(),
+        };
+        // BigTIFF is not supported.
+        match tiff.u16(2)? {
+            42 => Ok(tiff),
+            _ => Err(malformed()),
+        }
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, String> {
+        let bytes = self.data.get(at..at + 2).ok_or_else(malformed)?;
+        let bytes = [bytes[0], bytes[1]];
+        Ok(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, String> {
+        let bytes: [u8; 4] = self
+            .data
+            .get(at..at + 4)
+            .ok_or_else(malformed)?
+            .try_into()
+            .unwrap();
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn set_u16(&mut self, at: usize, value: u16) {
+        let bytes = match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        self.data[at..at + 2].copy_from_slice(&bytes);
+    }
+
+    fn set_u32(&mut self, at: usize, value: u32) {
+        let bytes = match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        self.data[at..at + 4].copy_from_slice(&bytes);
+    }
+
+    /// Zeroes `range`, ignoring whatever lies beyond the data.
+    fn zero(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.data.len());
+        if range.start < end {
+            self.data[range.start..end].fill(0);
+        }
+    }
+
+    /// Filters every directory of the chain. In EXIF data, the second one is
+    /// the thumbnail, which only survives when keeping most of the
+    /// metadata.
+    fn filter(&mut self, policy: MetadataPolicy, exif: bool) -> Result<(), String> {
+        let mut next_field = 4;
+        let mut first = true;
+        loop {
+            let offset = self.u32(next_field)? as usize;
+            let ifd = match (first, exif) {
+                (false, true) if policy == MetadataPolicy::CopyrightOnly => {
+                    self.set_u32(next_field, 0);
+                    return self.wipe(offset);
+                }
+                (false, true) => Ifd::Thumbnail,
+                _ => Ifd::Main,
+            };
+            if offset == 0 || !self.visited.insert(offset) {
+                return Ok(());
+            }
+            next_field = self.filter_ifd(offset, ifd, policy)?;
+            first = false;
+        }
+    }
+
+    /// Removes the entries of the directory at `offset` that `policy` does
+    /// not keep, returning where the offset of the next one now is.
+    fn filter_ifd(
+        &mut self,
+        offset: usize,
+        ifd: Ifd,
+        policy: MetadataPolicy,
+    ) -> Result<usize, String> {
+        let count = self.u16(offset)? as usize;
+        let entries = offset + 2;
+        let end = entries + 12 * count + 4;
+        if end > self.data.len() {
+            return Err(malformed());
+        }
+
+        let mut kept = 0;
+        for i in 0..count {
+            let entry = entries + 12 * i;
+            let tag = self.u16(entry)?;
+            if policy.keeps(ifd, tag) {
+                if let Some(sub_ifd) = sub_ifd(tag) {
+                    let sub_offset = self.u32(entry + 8)? as usize;
+                    if self.visited.insert(sub_offset) {
+                        self.filter_ifd(sub_offset, sub_ifd, policy)?;
+                    }
+                }
+                self.data
+                    .copy_within(entry..entry + 12, entries + 12 * kept);
+                kept += 1;
+            } else {
+                self.wipe_entry(entry)?;
+            }
+        }
+
+        let next = self.u32(entries + 12 * count)?;
+        self.set_u16(offset, kept as u16);
+        let next_field = entries + 12 * kept;
+        self.set_u32(next_field, next);
+        self.zero(next_field + 4..end);
+        Ok(next_field)
+    }
+
+    /// Zeroes the value of the entry at `entry`, along with any directory it
+    /// points to.
+    fn wipe_entry(&mut self, entry: usize) -> Result<(), String> {
+        let tag = self.u16(entry)?;
+        let value = self.u32(entry + 8)? as usize;
+        if sub_ifd(tag).is_some() {
+            return self.wipe(value);
+        }
+        let size = match self.u16(entry + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 | 13 => 4,
+            5 | 10 | 12 => 8,
+            _ => 0,
+        } * self.u32(entry + 4)? as usize;
+        if size > 4 {
+            self.zero(value..value.saturating_add(size));
+        }
+        Ok(())
+    }
+
+    /// Zeroes the directory at `offset`, the values of its entries and the
+    /// thumbnail it may point to.
+    fn wipe(&mut self, offset: usize) -> Result<(), String> {
+        if offset == 0 || !self.visited.insert(offset) {
+            return Ok(());
+        }
+        let count = self.u16(offset)? as usize;
+        let entries = offset + 2;
+        let mut thumbnail = (None, None);
+        for i in 0..count {
+            let entry = entries + 12 * i;
+            match self.u16(entry)? {
+                THUMBNAIL_OFFSET => thumbnail.0 = Some(self.u32(entry + 8)? as usize),
+                THUMBNAIL_LENGTH => thumbnail.1 = Some(self.u32(entry + 8)? as usize),
+                _ => self.wipe_entry(entry)?,
+            }
+        }
+        if let (Some(start), Some(length)) = thumbnail {
+            self.zero(start..start.saturating_add(length));
+        }
+        self.zero(offset..entries + 12 * count + 4);
+        Ok(())
+    }
+}
+
+fn sub_ifd(tag: u16) -> Option<Ifd> {
+    match tag {
+        EXIF_IFD => Some(Ifd::Exif),
+        GPS_IFD => Some(Ifd::Gps),
+        INTEROPERABILITY_IFD => Some(Ifd::Interoperability),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::magick::{MagickConvertJob, ResizeArgument};
+    use crate::scheduler::Step;
+
+    const MAKE: u16 = 0x010f;
+    const ARTIST: u16 = 0x013b;
+    const COPYRIGHT: u16 = 0x8298;
+    const EXPOSURE_TIME: u16 = 0x829a;
+    const LENS_MODEL: u16 = 0xa434;
+    const GPS_LATITUDE: u16 = 0x0002;
+
+    /// Little-endian EXIF data with a device, a descriptive and a copyright
+    /// tag, an EXIF directory with a device and a camera setting tag, and a
+    /// GPS directory. Values fit in their entries.
+    fn exif() -> Vec<u8> {
+        fn ifd(data: &mut Vec<u8>, entries: &[(u16, u32)]) {
+            data.extend((entries.len() as u16).to_le_bytes());
+            for &(tag, value) in entries {
+                let kind: u16 = if sub_ifd(tag).is_some() { 4 } else { 3 };
+                data.extend(tag.to_le_bytes());
+                data.extend(kind.to_le_bytes());
+                data.extend(1u32.to_le_bytes());
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(0u32.to_le_bytes());
+        }
+
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        ifd(
+            &mut data,
+            &[
+                (MAKE, 1),
+                (ARTIST, 2),
+                (COPYRIGHT, 3),
+                (EXIF_IFD, 74),
+                (GPS_IFD, 104),
+            ],
+        );
+        ifd(&mut data, &[(EXPOSURE_TIME, 4), (LENS_MODEL, 5)]);
+        ifd(&mut data, &[(GPS_LATITUDE, 6)]);
+        data
+    }
+
+    /// The tags of the main directory and of the directories it points to.
+    fn tags(data: &[u8]) -> Vec<u16> {
+        fn walk(data: &[u8], offset: usize, tags: &mut Vec<u16>) {
+            let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+            for i in 0..u16_at(offset) as usize {
+                let entry = offset + 2 + 12 * i;
+                let tag = u16_at(entry);
+                tags.push(tag);
+                if sub_ifd(tag).is_some() {
+                    let sub = u32::from_le_bytes(data[entry + 8..entry + 12].try_into().unwrap());
+                    walk(data, sub as usize, tags);
+                }
+            }
+        }
+
+        let mut tags = vec![];
+        walk(data, 8, &mut tags);
+        tags
+    }
+
+    /// The tags left by each policy editing the EXIF data.
+    fn expected(policy: MetadataPolicy) -> Vec<u16> {
+        match policy {
+            MetadataPolicy::CopyrightOnly => vec![COPYRIGHT],
+            MetadataPolicy::ExifOnly => vec![ARTIST, COPYRIGHT, EXIF_IFD, EXPOSURE_TIME],
+            _ => unreachable!(),
+        }
+    }
+
+    const POLICIES: [MetadataPolicy; 2] = [MetadataPolicy::CopyrightOnly, MetadataPolicy::ExifOnly];
+
+    fn png_chunk(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(name);
+        chunk.extend(body);
+        chunk.extend(crc32(&chunk[4..]).to_be_bytes());
+        chunk
+    }
+
+    fn png_chunks(data: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = vec![];
+        let mut position = 8;
+        while position < data.len() {
+            let length =
+                u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+            let name = data[position + 4..position + 8].try_into().unwrap();
+            let body = &data[position + 8..position + 8 + length];
+            let crc = &data[position + 8 + length..position + 12 + length];
+            assert_eq!(
+                crc,
+                crc32(&data[position + 4..position + 8 + length]).to_be_bytes()
+            );
+            chunks.push((name, body.to_vec()));
+            position += 12 + length;
+        }
+        chunks
+    }
+
+    fn raw_profile(exif: &[u8]) -> Vec<u8> {
+        let mut text = format!("\nexif\n{:8}\n", exif.len() + 6).into_bytes();
+        text.extend(b"457869660000");
+        for byte in exif {
+            text.extend(format!("{byte:02x}").as_bytes());
+        }
+        text.push(b'\n');
+        text
+    }
+
+    fn from_raw_profile(text: &[u8]) -> Vec<u8> {
+        let text = std::str::from_utf8(text).unwrap();
+        let hex = text.split_whitespace().skip(2).collect::<String>();
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+        assert!(bytes.starts_with(b"Exif\0\0"));
+        bytes[6..].to_vec()
+    }
+
+    const FORMATS: [FileType; 4] = [FileType::Jpg, FileType::Png, FileType::Webp, FileType::Tiff];
+
+    fn jpeg() -> Vec<u8> {
+        let mut data = b"\xff\xd8\xff\xe1".to_vec();
+        data.extend((exif().len() as u16 + 8).to_be_bytes());
+        data.extend(b"Exif\0\0");
+        data.extend(exif());
+        data.extend(b"\xff\xda\0\x02\xff\xd9");
+        data
+    }
+
+    /// A PNG file with an `eXIf` chunk, and the EXIF data again in a `tEXt`
+    /// and a `zTXt` raw profile, followed by an unrelated comment.
+    fn png() -> Vec<u8> {
+        let mut compressed = b"Raw profile type APP1\0\0".to_vec();
+        let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&raw_profile(&exif())).unwrap();
+        compressed.extend(encoder.finish().unwrap());
+        let mut text = b"Raw profile type exif\0".to_vec();
+        text.extend(raw_profile(&exif()));
+
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend(png_chunk(b"eXIf", &exif()));
+        data.extend(png_chunk(b"tEXt", &text));
+        data.extend(png_chunk(b"zTXt", &compressed));
+        data.extend(png_chunk(b"tEXt", b"Comment\0kept"));
+        data.extend(png_chunk(b"IEND", b""));
+        data
+    }
+
+    fn webp() -> Vec<u8> {
+        let mut chunks = b"VP8L\x01\0\0\0\0\0".to_vec();
+        chunks.extend(b"EXIF");
+        chunks.extend((exif().len() as u32).to_le_bytes());
+        chunks.extend(exif());
+        let mut data = b"RIFF".to_vec();
+        data.extend((chunks.len() as u32 + 4).to_le_bytes());
+        data.extend(b"WEBP");
+        data.extend(chunks);
+        data
+    }
+
+    /// A file of `kind` holding [`exif`].
+    fn sample(kind: FileType) -> Vec<u8> {
+        match kind {
+            FileType::Jpg => jpeg(),
+            FileType::Png => png(),
+            FileType::Webp => webp(),
+            FileType::Tiff => exif(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The EXIF tags of a file made by [`sample`].
+    fn sample_tags(kind: FileType, data: &[u8]) -> Vec<u16> {
+        match kind {
+            FileType::Jpg => tags(&data[12..]),
+            FileType::Png => tags(&png_chunks(data)[0].1),
+            FileType::Webp => tags(&data[30..]),
+            FileType::Tiff => tags(data),
+            _ => unreachable!(),
+        }
+    }
+
+    fn job(output: &Path, output_type: FileType, metadata: MetadataPolicy) -> MagickConvertJob {
+        MagickConvertJob {
+            input_file: output.with_extension("input"),
+            input_type: FileType::Png,
+            input_frame: None,
+            output_file: output.to_owned(),
+            output_type,
+            background: Color::rgb(255, 255, 255),
+            quality: 90,
+            first_frame: true,
+            filter: None,
+            resize_arg: ResizeArgument::default(),
+            density: None,
+            remove_alpha: false,
+            metadata,
+        }
+    }
+
+    #[test]
+    fn filters_jpeg() {
+        for policy in POLICIES {
+            let mut data = jpeg();
+            filter_jpeg(&mut data, policy).unwrap();
+            assert_eq!(tags(&data[12..]), expected(policy), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn filters_png() {
+        for policy in POLICIES {
+            let mut data = png();
+            filter_png(&mut data, policy).unwrap();
+            let chunks = png_chunks(&data);
+            assert_eq!(chunks.len(), 5);
+            assert_eq!(tags(&chunks[0].1), expected(policy), "{policy:?}");
+
+            let text = chunks[1]
+                .1
+                .strip_prefix(b"Raw profile type exif\0")
+                .unwrap();
+            assert_eq!(
+                tags(&from_raw_profile(text)),
+                expected(policy),
+                "{policy:?}"
+            );
+
+            let compressed = chunks[2]
+                .1
+                .strip_prefix(b"Raw profile type APP1\0\0")
+                .unwrap();
+            let mut text = vec![];
+            ZlibDecoder::new(compressed).read_to_end(&mut text).unwrap();
+            assert_eq!(
+                tags(&from_raw_profile(&text)),
+                expected(policy),
+                "{policy:?}"
+            );
+
+            assert_eq!(chunks[3].1, b"Comment\0kept");
+        }
+    }
+
+    #[test]
+    fn filters_webp() {
+        for policy in POLICIES {
+            let mut data = webp();
+            filter_webp(&mut data, policy).unwrap();
+            assert_eq!(tags(&data[30..]), expected(policy), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn filters_tiff() {
+        for policy in POLICIES {
+            let mut data = exif();
+            Tiff::new(&mut data).unwrap().filter(policy, false).unwrap();
+            assert_eq!(tags(&data), expected(policy), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn wipes_what_is_removed() {
+        let mut data = exif();
+        Tiff::new(&mut data)
+            .unwrap()
+            .filter(MetadataPolicy::CopyrightOnly, false)
+            .unwrap();
+        // Only the copyright entry is left in the main directory, the
+        // others are gone along with the directories they pointed to.
+        assert!(data[8 + 2 + 12..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn passes_magick_arguments_by_policy() {
+        let filtered = ["-auto-orient", "+profile", "!exif,!icc,*"];
+        for kind in FORMATS {
+            for (policy, arguments) in [
+                (MetadataPolicy::KeepAll, &[][..]),
+                (MetadataPolicy::StripAll, &["-auto-orient", "-strip"][..]),
+                (MetadataPolicy::CopyrightOnly, &filtered[..]),
+                (MetadataPolicy::ExifOnly, &filtered[..]),
+            ] {
+                assert_eq!(
+                    policy.magick_arguments(kind),
+                    arguments,
+                    "{policy:?} {kind:?}"
+                );
+            }
+        }
+
+        // Formats whose EXIF data is not filtered lose it to ImageMagick.
+        for policy in POLICIES {
+            assert!(!policy.filters_exif(FileType::Gif));
+            assert_eq!(
+                policy.magick_arguments(FileType::Gif),
+                ["-auto-orient", "+profile", "!icc,*"]
+            );
+        }
+    }
+
+    #[test]
+    fn filters_outputs_after_converting() {
+        let dir = tempfile::tempdir().unwrap();
+        for kind in FORMATS {
+            for &policy in MetadataPolicy::iterator() {
+                let output = dir
+                    .path()
+                    .join(format!("{policy:?}.{}", kind.as_extension()));
+                std::fs::write(&output, sample(kind)).unwrap();
+
+                let mut steps = crate::backend::steps(&[job(&output, kind, policy)]);
+                assert_eq!(
+                    steps.len(),
+                    1 + usize::from(policy.filters_exif(kind)),
+                    "{policy:?} {kind:?}"
+                );
+                if policy.filters_exif(kind) {
+                    let Some(Step::Task(filter)) = steps.pop() else {
+                        panic!("{policy:?} {kind:?} is not filtered by a task");
+                    };
+                    filter().unwrap();
+                }
+
+                let data = std::fs::read(&output).unwrap();
+                match policy {
+                    // ImageMagick keeps or strips everything by itself.
+                    MetadataPolicy::KeepAll | MetadataPolicy::StripAll => {
+                        assert_eq!(data, sample(kind), "{policy:?} {kind:?}")
+                    }
+                    _ => assert_eq!(
+                        sample_tags(kind, &data),
+                        expected(policy),
+                        "{policy:?} {kind:?}"
+                    ),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::collect::write_atomically;
+
+/// Hidden file of the output directory remembering where each output came
+/// from.
+const MANIFEST_NAME: &str = ".switcheroo-mirror";
+
+/// How outputs are found to be up to date when converting the same images
+/// into the same directory again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MirrorMode {
+    /// Every image is converted.
+    #[default]
+    Off,
+    /// Outputs newer than their input are kept.
+    ModifiedTime,
+    /// Outputs are kept until the content of their input changes.
+    ContentHash,
+}
+
+impl MirrorMode {
+    pub fn iterator() -> std::slice::Iter<'static, Self> {
+        use MirrorMode::*;
+        static MODES: [MirrorMode; 3] = [Off, ModifiedTime, ContentHash];
+        MODES.iter()
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::iterator().nth(index).copied()
+    }
+
+    pub fn from_string(mode: &str) -> Option<Self> {
+        match mode {
+            "off" => Some(MirrorMode::Off),
+            "mtime" => Some(MirrorMode::ModifiedTime),
+            "hash" => Some(MirrorMode::ContentHash),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ManifestEntry {
+    source: PathBuf,
+    /// SHA-256 of the source when it was converted, in content hash mode.
+    hash: Option<String>,
+}
+
+/// Outputs previously converted into a directory, by path relative to it.
+/// Paths are stored as raw bytes, as they may not be valid UTF-8.
+#[derive(Debug, Clone, Default)]
+struct Manifest {
+    entries: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+impl Manifest {
+    fn load(destination: &Path) -> Self {
+        let entries = fs::read(destination.join(MANIFEST_NAME))
+            .unwrap_or_default()
+            .split(|b| *b == b'\n')
+            .filter_map(|line| {
+                let mut fields = line.split(|b| *b == b'\t');
+                let (output, source, hash) = (fields.next()?, fields.next()?, fields.next()?);
+                let hash = std::str::from_utf8(hash).ok()?;
+                let output = Path::new(OsStr::from_bytes(output));
+                if !is_plain_relative(output) {
+                    return None;
+                }
+                let entry = ManifestEntry {
+                    source: PathBuf::from(OsStr::from_bytes(source)),
+                    hash: Some(hash.to_owned()).filter(|h| !h.is_empty()),
+                };
+                Some((output.to_owned(), entry))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    fn save(&self, destination: &Path) -> io::Result<()> {
+        write_atomically(&destination.join(MANIFEST_NAME), |file| {
+            for (output, entry) in self.entries.iter() {
+                let hash = entry.hash.as_deref().unwrap_or_default();
+                file.write_all(output.as_os_str().as_bytes())?;
+                file.write_all(b"\t")?;
+                file.write_all(entry.source.as_os_str().as_bytes())?;
+                writeln!(file, "\t{hash}")?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Whether `path` only names files and folders below the directory it is
+/// relative to. Other entries of the manifest are ignored so a tampered one
+/// cannot delete files outside the destination.
+fn is_plain_relative(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Outputs left alone or deleted when mirroring.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorReport {
+    pub up_to_date: Vec<PathBuf>,
+    /// Outputs deleted because their input is gone.
+    pub removed: Vec<PathBuf>,
+}
+
+impl MirrorReport {
+    pub fn is_empty(&self) -> bool {
+        self.up_to_date.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Keeps track of the outputs of a directory across conversions.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    mode: MirrorMode,
+    manifest: Manifest,
+    /// Hashes of the inputs computed so far, by path.
+    hashes: HashMap<PathBuf, Option<String>>,
+}
+
+impl Mirror {
+    pub fn new(mode: MirrorMode, destination: &Path) -> Self {
+        Self {
+            mode,
+            manifest: Manifest::load(destination),
+            hashes: HashMap::new(),
+        }
+    }
+
+    /// Whether `output_name` in `destination` already is the conversion of
+    /// the current `source`.
+    pub fn is_up_to_date(&mut self, source: &Path, output_name: &Path, destination: &Path) -> bool {
+        let output = destination.join(output_name);
+        match self.mode {
+            MirrorMode::Off => false,
+            MirrorMode::ModifiedTime => {
+                let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+                match (modified(source), modified(&output)) {
+                    (Some(source), Some(output)) => output >= source,
+                    _ => false,
+                }
+            }
+            MirrorMode::ContentHash => {
+                let recorded = self
+                    .manifest
+                    .entries
+                    .get(output_name)
+                    .and_then(|e| e.hash.clone());
+                output.exists() && recorded.is_some() && self.hash(source) == recorded
+            }
+        }
+    }
+
+    /// Records `output_name` as converted from `source`.
+    pub fn record(&mut self, source: &Path, output_name: &Path) {
+        let hash = match self.mode {
+            MirrorMode::ContentHash => self.hash(source),
+            _ => None,
+        };
+        let entry = ManifestEntry {
+            source: source.to_owned(),
+            hash,
+        };
+        self.manifest.entries.insert(output_name.to_owned(), entry);
+    }
+
+    /// Deletes the outputs whose input no longer exists, unless they are in
+    /// `planned`, and returns their names.
+    pub fn remove_orphans(&mut self, destination: &Path, planned: &HashSet<&Path>) -> Vec<PathBuf> {
+        let orphans = self
+            .manifest
+            .entries
+            .iter()
+            .filter(|(output, entry)| !planned.contains(output.as_path()) && !entry.source.exists())
+            .map(|(output, _)| output.clone())
+            .collect::<Vec<_>>();
+
+        orphans
+            .into_iter()
+            .filter(|output| {
+                let removed = !matches!(
+                    fs::remove_file(destination.join(output)),
+                    Err(e) if e.kind() != io::ErrorKind::NotFound
+                );
+                if removed {
+                    self.manifest.entries.remove(output);
+                }
+                removed
+            })
+            .collect()
+    }
+
+    pub fn save(&self, destination: &Path) -> io::Result<()> {
+        self.manifest.save(destination)
+    }
+
+    fn hash(&mut self, source: &Path) -> Option<String> {
+        self.hashes
+            .entry(source.to_owned())
+            .or_insert_with(|| {
+                let mut hasher = Sha256::new();
+                io::copy(&mut File::open(source).ok()?, &mut hasher).ok()?;
+                Some(format!("{:x}", hasher.finalize()))
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn keeps_outputs_across_runs() {
+        let destination = tempfile::tempdir().unwrap();
+        let source = destination.path().join("a.webp");
+        write(&source, "image");
+        let name = Path::new(OsStr::from_bytes(b"holiday/\xffa.png"));
+
+        let mut mirror = Mirror::new(MirrorMode::ContentHash, destination.path());
+        mirror.record(&source, name);
+        mirror.save(destination.path()).unwrap();
+
+        let loaded = Mirror::new(MirrorMode::ContentHash, destination.path());
+        assert_eq!(loaded.manifest.entries, mirror.manifest.entries);
+        let entry = &loaded.manifest.entries[name];
+        assert_eq!(entry.source, source);
+        assert_eq!(entry.hash.as_ref().map(String::len), Some(64));
+    }
+
+    #[test]
+    fn keys_outputs_by_their_folder() {
+        let destination = tempfile::tempdir().unwrap();
+        let mut mirror = Mirror::new(MirrorMode::ModifiedTime, destination.path());
+        mirror.record(Path::new("/gone/a.webp"), Path::new("holiday/day 1/a.png"));
+        mirror.save(destination.path()).unwrap();
+
+        let manifest = fs::read_to_string(destination.path().join(MANIFEST_NAME)).unwrap();
+        assert_eq!(manifest, "holiday/day 1/a.png\t/gone/a.webp\t\n");
+    }
+
+    #[test]
+    fn compares_modified_times() {
+        let destination = tempfile::tempdir().unwrap();
+        let source = destination.path().join("a.webp");
+        let name = Path::new("out/a.png");
+        let output = destination.path().join(name);
+        write(&source, "image");
+
+        let mut mirror = Mirror::new(MirrorMode::ModifiedTime, destination.path());
+        assert!(!mirror.is_up_to_date(&source, name, destination.path()));
+
+        write(&output, "converted");
+        let now = SystemTime::now();
+        File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(60))
+            .unwrap();
+        assert!(mirror.is_up_to_date(&source, name, destination.path()));
+
+        File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(now + Duration::from_secs(60))
+            .unwrap();
+        assert!(!mirror.is_up_to_date(&source, name, destination.path()));
+    }
+
+    #[test]
+    fn compares_content_hashes() {
+        let destination = tempfile::tempdir().unwrap();
+        let source = destination.path().join("a.webp");
+        let name = Path::new("a.png");
+        write(&source, "image");
+        write(&destination.path().join(name), "converted");
+
+        let mut mirror = Mirror::new(MirrorMode::ContentHash, destination.path());
+        assert!(!mirror.is_up_to_date(&source, name, destination.path()));
+        mirror.record(&source, name);
+        mirror.save(destination.path()).unwrap();
+
+        let mut mirror = Mirror::new(MirrorMode::ContentHash, destination.path());
+        assert!(mirror.is_up_to_date(&source, name, destination.path()));
+
+        write(&source, "edited image");
+        let mut mirror = Mirror::new(MirrorMode::ContentHash, destination.path());
+        assert!(!mirror.is_up_to_date(&source, name, destination.path()));
+
+        fs::remove_file(destination.path().join(name)).unwrap();
+        let mut mirror = Mirror::new(MirrorMode::ContentHash, destination.path());
+        assert!(!mirror.is_up_to_date(&source, name, destination.path()));
+    }
+
+    #[test]
+    fn removes_outputs_of_missing_inputs() {
+        let root = tempfile::tempdir().unwrap();
+        let destination = root.path().join("out");
+        let kept_source = root.path().join("kept.webp");
+        write(&kept_source, "image");
+        for name in ["gone.png", "day 1/gone.png", "kept.png", "planned.png"] {
+            write(&destination.join(name), "converted");
+        }
+        let outside = root.path().join("outside.png");
+        write(&outside, "not ours");
+
+        let manifest = [
+            ("gone.png", "/missing/gone.webp"),
+            ("day 1/gone.png", "/missing/gone.webp"),
+            ("kept.png", kept_source.to_str().unwrap()),
+            ("planned.png", "/missing/planned.webp"),
+            ("../outside.png", "/missing/outside.webp"),
+            (outside.to_str().unwrap(), "/missing/outside.webp"),
+            ("day 1/../../outside.png", "/missing/outside.webp"),
+            ("./gone.png", "/missing/gone.webp"),
+        ]
+        .map(|(output, source)| format!("{output}\t{source}\t\n"))
+        .concat();
+        write(&destination.join(MANIFEST_NAME), &manifest);
+
+        let mut mirror = Mirror::new(MirrorMode::ModifiedTime, &destination);
+        let planned = HashSet::from([Path::new("planned.png")]);
+        let removed = mirror.remove_orphans(&destination, &planned);
+
+        assert_eq!(
+            removed,
+            [PathBuf::from("day 1/gone.png"), PathBuf::from("gone.png")]
+        );
+        assert!(!destination.join("gone.png").exists());
+        assert!(!destination.join("day 1/gone.png").exists());
+        assert!(destination.join("kept.png").exists());
+        assert!(destination.join("planned.png").exists());
+        assert!(outside.exists());
+        assert_eq!(
+            mirror.manifest.entries.keys().collect::<Vec<_>>(),
+            [Path::new("kept.png"), Path::new("planned.png")]
+        );
+    }
+}
@@ -0,0 +1,447 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use gettextrs::gettext;
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{self, PngEncoder};
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPDecoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageEncoder, ImageError, ImageFormat,
+    ImageReader, ImageResult, Rgba, RgbaImage,
+};
+
+use crate::backend::ConversionBackend;
+use crate::color::Color;
+use crate::filetypes::FileType;
+use crate::image_info::ImageInfo;
+use crate::magick::{MagickConvertJob, ResizeArgument, ResizeFilter};
+use crate::metadata::MetadataPolicy;
+use crate::scheduler::Step;
+
+/// Converts the most common formats in-process with the `image` crate,
+/// sparing a process per step. Only still images, or single frames of
+/// animations, are converted this way, and only when stripping metadata or
+/// keeping all of it, when the output can hold what the input has: EXIF in
+/// PNG and JPEG, and ICC profiles in PNG, JPEG and TIFF.
+///
+/// The quality is applied the way ImageMagick does to JPEG and PNG outputs,
+/// the other formats written here have no quality setting.
+#[derive(Debug, Clone, Copy)]
+pub struct Native;
+
+impl ConversionBackend for Native {
+    fn name(&self) -> &'static str {
+        "image"
+    }
+
+    // Formats with a single frame only, others are counted by ImageMagick.
+    fn can_probe(&self, kind: FileType) -> bool {
+        matches!(
+            kind,
+            FileType::Png | FileType::Jpg | FileType::Jpeg | FileType::Bmp
+        )
+    }
+
+    fn can_decode(&self, kind: FileType) -> bool {
+        image_format(kind).is_some()
+    }
+
+    // WebP would always be lossless and ICO is limited to 256 pixels.
+    fn can_encode(&self, kind: FileType) -> bool {
+        !matches!(kind, FileType::Webp | FileType::Ico) && image_format(kind).is_some()
+    }
+
+    fn supports(&self, job: &MagickConvertJob) -> bool {
+        let frame = job.input_frame.unwrap_or_default();
+        // The metadata is checked last, as it reads the input.
+        self.can_decode(job.input_type)
+            && self.can_encode(job.output_type)
+            && job.first_frame
+            && job.density.is_none()
+            && (frame == 0 || matches!(job.input_type, FileType::Gif | FileType::Webp))
+            && match job.metadata {
+                MetadataPolicy::StripAll => true,
+                MetadataPolicy::KeepAll => read_metadata(&job.input_file, job.input_type)
+                    .is_ok_and(|m| m.fits(job.output_type)),
+                MetadataPolicy::CopyrightOnly | MetadataPolicy::ExifOnly => false,
+            }
+    }
+
+    // The resolution is left out, as the decoders do not read it.
+    fn probe(&self, path: &Path, kind: FileType) -> Result<ImageInfo, ()> {
+        let mut reader = ImageReader::open(path).map_err(|_| ())?;
+        reader.set_format(image_format(kind).ok_or(())?);
+        let mut decoder = reader.into_decoder().map_err(|_| ())?;
+
+        let (width, height) = decoder.dimensions();
+        let color_type = decoder.color_type();
+        let has_icc = decoder.icc_profile().is_ok_and(|p| p.is_some());
+        let orientation = decoder
+            .exif_metadata()
+            .ok()
+            .flatten()
+            .and_then(|exif| Orientation::from_exif_chunk(&exif))
+            .map(Orientation::to_exif);
+        // Only the pixels tell whether the alpha channel is used.
+        let has_alpha = color_type.has_alpha()
+            && DynamicImage::from_decoder(decoder)
+                .is_ok_and(|image| image.into_rgba8().pixels().any(|p| p[3] < 255));
+
+        Ok(ImageInfo {
+            colorspace: Some(
+                if color_type.has_color() {
+                    "sRGB"
+                } else {
+                    "Gray"
+                }
+                .to_owned(),
+            ),
+            depth: Some((color_type.bits_per_pixel() / color_type.channel_count() as u16) as usize),
+            has_alpha,
+            has_icc,
+            orientation,
+            ..ImageInfo::still(width as usize, height as usize)
+        })
+    }
+
+    fn convert(&self, job: &MagickConvertJob) -> Step {
+        let job = job.clone();
+        Step::Task(Box::new(move || {
+            let (image, metadata) =
+                decode(&job).map_err(|e| format!("{}: {e}", job.input_file.display()))?;
+            encode(&process(image, &job), metadata, &job)
+                .map_err(|e| format!("{}: {e}", job.output_file.display()))
+        }))
+    }
+}
+
+fn image_format(kind: FileType) -> Option<ImageFormat> {
+    match kind {
+        FileType::Png => Some(ImageFormat::Png),
+        FileType::Jpg | FileType::Jpeg => Some(ImageFormat::Jpeg),
+        FileType::Gif => Some(ImageFormat::Gif),
+        FileType::Webp => Some(ImageFormat::WebP),
+        FileType::Bmp => Some(ImageFormat::Bmp),
+        FileType::Tiff => Some(ImageFormat::Tiff),
+        FileType::Ico => Some(ImageFormat::Ico),
+        _ => None,
+    }
+}
+
+/// The metadata of an image that the encoders can write.
+#[derive(Debug, Default)]
+struct Metadata {
+    exif: Option<Vec<u8>>,
+    icc: Option<Vec<u8>>,
+    /// Whether the image also has XMP or IPTC metadata, which is never
+    /// written.
+    other: bool,
+}
+
+impl Metadata {
+    fn read(decoder: &mut impl ImageDecoder) -> ImageResult<Self> {
+        Ok(Self {
+            exif: decoder.exif_metadata()?,
+            icc: decoder.icc_profile()?,
+            other: decoder.xmp_metadata()?.is_some() || decoder.iptc_metadata()?.is_some(),
+        })
+    }
+
+    /// Whether all of it is written to `kind` images.
+    fn fits(&self, kind: FileType) -> bool {
+        let exif = matches!(kind, FileType::Png | FileType::Jpg | FileType::Jpeg);
+        let icc = exif || kind == FileType::Tiff;
+        !self.other && (exif || self.exif.is_none()) && (icc || self.icc.is_none())
+    }
+}
+
+fn read_metadata(path: &Path, kind: FileType) -> ImageResult<Metadata> {
+    let mut reader = ImageReader::open(path)?;
+    reader.set_format(
+        image_format(kind).ok_or_else(|| {
+            ImageError::Unsupported(image::error::ImageFormatHint::Unknown.into())
+        })?,
+    );
+    Metadata::read(&mut reader.into_decoder()?)
+}
+
+/// The metadata of `decoder` that `job` keeps.
+fn kept_metadata(
+    decoder: &mut impl ImageDecoder,
+    job: &MagickConvertJob,
+) -> Result<Metadata, String> {
+    match job.metadata {
+        MetadataPolicy::KeepAll => Metadata::read(decoder).map_err(|e| e.to_string()),
+        _ => Ok(Metadata::default()),
+    }
+}
+
+/// Reads the frame of the input `job` converts, along with the metadata to
+/// keep.
+fn decode(job: &MagickConvertJob) -> Result<(DynamicImage, Metadata), String> {
+    let index = job.input_frame.unwrap_or_default();
+    let open = || File::open(&job.input_file).map(BufReader::new);
+    let (frame, metadata) = match job.input_type {
+        FileType::Gif if index > 0 => {
+            let mut decoder =
+                GifDecoder::new(open().map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+            let metadata = kept_metadata(&mut decoder, job)?;
+            (decoder.into_frames().nth(index), metadata)
+        }
+        FileType::Webp if index > 0 => {
+            let mut decoder =
+                WebPDecoder::new(open().map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+            let metadata = kept_metadata(&mut decoder, job)?;
+            (decoder.into_frames().nth(index), metadata)
+        }
+        _ => {
+            let mut reader = ImageReader::new(open().map_err(|e| e.to_string())?);
+            reader.set_format(image_format(job.input_type).ok_or_else(unsupported)?);
+            // Rotated the way ImageMagick does it, as the orientation tag is
+            // not written.
+            let mut decoder = reader.into_decoder().map_err(|e| e.to_string())?;
+            let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+            let mut metadata = kept_metadata(&mut decoder, job)?;
+            if let Some(exif) = metadata.exif.as_mut() {
+                let _ = Orientation::remove_from_exif_chunk(exif);
+            }
+            let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+            image.apply_orientation(orientation);
+            return Ok((image, metadata));
+        }
+    };
+
+    match frame {
+        Some(frame) => Ok((
+            DynamicImage::ImageRgba8(frame.map_err(|e| e.to_string())?.into_buffer()),
+            metadata,
+        )),
+        None => Err(gettext("The image has no frame {}").replace("{}", &index.to_string())),
+    }
+}
+
+fn unsupported() -> String {
+    gettext("Unsupported filetype")
+}
+
+/// Applies the options of `job` the way ImageMagick would: flattening onto
+/// the background, then resizing.
+fn process(image: DynamicImage, job: &MagickConvertJob) -> DynamicImage {
+    let mut image = image.into_rgba8();
+    flatten(&mut image, job.background);
+
+    let (width, height) = image.dimensions();
+    let (new_width, new_height) = match job.resize_arg {
+        ResizeArgument::Percentage {
+            width: w,
+            height: h,
+        } => (
+            (width as usize * w).div_ceil(100).max(1) as u32,
+            (height as usize * h).div_ceil(100).max(1) as u32,
+        ),
+        ResizeArgument::ExactPixels { width, height } => (width as u32, height as u32),
+    };
+    let filter = match job.filter {
+        Some(ResizeFilter::Point) => FilterType::Nearest,
+        _ => FilterType::Lanczos3,
+    };
+    if (new_width, new_height) != (width, height) {
+        image = image::imageops::resize(&image, new_width, new_height, filter);
+    }
+
+    let image = DynamicImage::ImageRgba8(image);
+    if job.remove_alpha || !job.output_type.supports_alpha() {
+        DynamicImage::ImageRgb8(image.into_rgb8())
+    } else {
+        image
+    }
+}
+
+/// Composes `image` over `background`.
+fn flatten(image: &mut RgbaImage, background: Color) {
+    if background.alpha == 0 {
+        return;
+    }
+
+    let Color {
+        red,
+        green,
+        blue,
+        alpha,
+    } = background;
+    let background_alpha = alpha as f32 / 255.0;
+    for Rgba([r, g, b, a]) in image.pixels_mut() {
+        let alpha = *a as f32 / 255.0;
+        let out_alpha = alpha + background_alpha * (1.0 - alpha);
+        let blend = |c: u8, bg: u8| {
+            let c = c as f32 * alpha + bg as f32 * background_alpha * (1.0 - alpha);
+            (c / out_alpha).round().clamp(0.0, 255.0) as u8
+        };
+        (*r, *g, *b) = (blend(*r, red), blend(*g, green), blend(*b, blue));
+        *a = (out_alpha * 255.0).round() as u8;
+    }
+}
+
+fn encode(image: &DynamicImage, metadata: Metadata, job: &MagickConvertJob) -> Result<(), String> {
+    let mut writer = BufWriter::new(File::create(&job.output_file).map_err(|e| e.to_string())?);
+    match job.output_type {
+        FileType::Jpg | FileType::Jpeg => write(
+            JpegEncoder::new_with_quality(&mut writer, job.quality.clamp(1, 100) as u8),
+            &DynamicImage::ImageRgb8(image.to_rgb8()),
+            metadata,
+        ),
+        FileType::Png => {
+            let (compression, filter) = png_quality(job.quality);
+            write(
+                PngEncoder::new_with_quality(&mut writer, compression, filter),
+                image,
+                metadata,
+            )
+        }
+        FileType::Tiff => write(TiffEncoder::new(&mut writer), image, metadata),
+        kind => image.write_to(&mut writer, image_format(kind).ok_or_else(unsupported)?),
+    }
+    .map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())
+}
+
+fn write(
+    mut encoder: impl ImageEncoder,
+    image: &DynamicImage,
+    metadata: Metadata,
+) -> ImageResult<()> {
+    if let Some(icc) = metadata.icc {
+        encoder
+            .set_icc_profile(icc)
+            .map_err(ImageError::Unsupported)?;
+    }
+    if let Some(exif) = metadata.exif {
+        encoder
+            .set_exif_metadata(exif)
+            .map_err(ImageError::Unsupported)?;
+    }
+    image.write_with_encoder(encoder)
+}
+
+/// The zlib compression level and filter ImageMagick takes from the tens and
+/// the units of the quality of PNG images.
+fn png_quality(quality: usize) -> (png::CompressionType, png::FilterType) {
+    let compression = match (quality / 10).min(9) {
+        0 => png::CompressionType::Uncompressed,
+        level => png::CompressionType::Level(level as u8),
+    };
+    let filter = match quality % 10 {
+        0 => png::FilterType::NoFilter,
+        1 => png::FilterType::Sub,
+        2 => png::FilterType::Up,
+        3 => png::FilterType::Avg,
+        4 => png::FilterType::Paeth,
+        _ => png::FilterType::Adaptive,
+    };
+    (compression, filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a 2×1 PNG image with `exif` to `path`.
+    fn write_png(path: &Path, exif: Option<Vec<u8>>) {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::new(2, 1));
+        let metadata = Metadata {
+            exif,
+            ..Metadata::default()
+        };
+        write(
+            PngEncoder::new(File::create(path).unwrap()),
+            &image,
+            metadata,
+        )
+        .unwrap();
+    }
+
+    fn job(input: &Path, output: &Path, output_type: FileType) -> MagickConvertJob {
+        MagickConvertJob {
+            input_file: input.to_owned(),
+            input_type: FileType::Png,
+            input_frame: None,
+            output_file: output.to_owned(),
+            output_type,
+            background: Color::rgb(255, 255, 255),
+            quality: 90,
+            first_frame: true,
+            filter: None,
+            resize_arg: ResizeArgument::default(),
+            density: None,
+            remove_alpha: false,
+            metadata: MetadataPolicy::KeepAll,
+        }
+    }
+
+    /// A little-endian EXIF chunk holding an orientation tag only.
+    fn exif(orientation: u8) -> Vec<u8> {
+        let mut exif = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+        exif.extend([orientation, 0, 0, 0, 0, 0, 0, 0]);
+        exif
+    }
+
+    #[test]
+    fn keeps_metadata_the_output_holds() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.png");
+        let tagged = dir.path().join("tagged.png");
+        write_png(&plain, None);
+        write_png(&tagged, Some(exif(1)));
+
+        let output = dir.path().join("out");
+        assert!(Native.supports(&job(&plain, &output, FileType::Bmp)));
+        assert!(Native.supports(&job(&tagged, &output, FileType::Jpg)));
+        assert!(!Native.supports(&job(&tagged, &output, FileType::Bmp)));
+        assert!(!Native.supports(&MagickConvertJob {
+            metadata: MetadataPolicy::ExifOnly,
+            ..job(&plain, &output, FileType::Jpg)
+        }));
+    }
+
+    #[test]
+    fn writes_exif_without_the_orientation() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("rotated.png");
+        let output = dir.path().join("rotated.jpg");
+        write_png(&input, Some(exif(6)));
+
+        let Step::Task(task) = Native.convert(&job(&input, &output, FileType::Jpg)) else {
+            panic!("not converted in-process");
+        };
+        task().unwrap();
+
+        let metadata = read_metadata(&output, FileType::Jpg).unwrap();
+        let exif = metadata.exif.unwrap();
+        assert_eq!(
+            Orientation::from_exif_chunk(&exif),
+            Some(Orientation::NoTransforms)
+        );
+        assert_eq!(image::image_dimensions(&output).unwrap(), (1, 2));
+    }
+
+    #[test]
+    fn reads_png_quality_like_imagemagick() {
+        assert_eq!(
+            png_quality(75),
+            (png::CompressionType::Level(7), png::FilterType::Adaptive)
+        );
+        assert_eq!(
+            png_quality(3),
+            (png::CompressionType::Uncompressed, png::FilterType::Avg)
+        );
+        assert_eq!(
+            png_quality(100),
+            (png::CompressionType::Level(9), png::FilterType::NoFilter)
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use gettextrs::gettext;
+use gtk::{gio, glib, prelude::*};
+
+use crate::color::Color;
+use crate::conversion::ConversionOptions;
+use crate::filetypes::{CompressionType, FileType};
+use crate::magick::{ResizeArgument, ResizeFilter};
+use crate::metadata::MetadataPolicy;
+
+const PRESETS_KEY: &str = "presets";
+
+/// How a preset is stored in GSettings: name, output format, quality,
+/// resize, resize filter, background, DPI, compression and metadata policy.
+type StoredPreset = (
+    String,
+    String,
+    i32,
+    String,
+    String,
+    String,
+    i32,
+    String,
+    String,
+);
+
+/// A named set of conversion options that can be applied in one go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub name: String,
+    pub output_type: FileType,
+    pub quality: usize,
+    pub resize_arg: ResizeArgument,
+    pub filter: ResizeFilter,
+    pub background: Color,
+    pub dpi: usize,
+    pub compression: CompressionType,
+    pub metadata: MetadataPolicy,
+}
+
+impl Preset {
+    fn from_stored(stored: StoredPreset) -> Option<Self> {
+        let (
+            name,
+            output_type,
+            quality,
+            resize_arg,
+            filter,
+            background,
+            dpi,
+            compression,
+            metadata,
+        ) = stored;
+        Some(Self {
+            name,
+            output_type: FileType::from_string(&output_type).filter(|f| f.is_output())?,
+            quality: quality.clamp(1, 100) as usize,
+            resize_arg: ResizeArgument::from_string(&resize_arg)?,
+            filter: ResizeFilter::from_string(&filter)?,
+            background: Color::from_hex_string(&background)?,
+            dpi: dpi.max(1) as usize,
+            compression: CompressionType::from_string(&compression)?,
+            metadata: MetadataPolicy::from_string(&metadata)?,
+        })
+    }
+
+    fn to_stored(&self) -> StoredPreset {
+        (
+            self.name.clone(),
+            self.output_type.as_extension().to_owned(),
+            self.quality as i32,
+            self.resize_arg.as_string(),
+            self.filter.as_string().to_owned(),
+            self.background.as_hex_string(),
+            self.dpi as i32,
+            self.compression.as_extension().to_owned(),
+            self.metadata.as_string().to_owned(),
+        )
+    }
+
+    /// The options converting images the way this preset describes.
+    pub fn conversion_options(&self) -> ConversionOptions {
+        ConversionOptions {
+            output_type: self.output_type,
+            background: self.background,
+            quality: self.quality,
+            filter: Some(self.filter),
+            resize_arg: self.resize_arg,
+            dpi: self.dpi,
+            metadata: self.metadata,
+        }
+    }
+
+    /// Every saved preset, skipping entries that can no longer be parsed.
+    pub fn load_all(settings: &gio::Settings) -> Vec<Self> {
+        settings
+            .value(PRESETS_KEY)
+            .get::<Vec<StoredPreset>>()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(Self::from_stored)
+            .collect()
+    }
+
+    pub fn save_all(settings: &gio::Settings, presets: &[Self]) -> Result<(), glib::BoolError> {
+        let stored: Vec<StoredPreset> = presets.iter().map(Self::to_stored).collect();
+        settings.set_value(PRESETS_KEY, &stored.to_variant())
+    }
+
+    pub fn find(settings: &gio::Settings, name: &str) -> Option<Self> {
+        Self::load_all(settings)
+            .into_iter()
+            .find(|p| p.name == name)
+    }
+
+    /// Adds `self`, replacing any preset with the same name.
+    pub fn save(&self, settings: &gio::Settings) -> Result<(), glib::BoolError> {
+        let mut presets = Self::load_all(settings);
+        match presets.iter_mut().find(|p| p.name == self.name) {
+            Some(p) => *p = self.clone(),
+            None => presets.push(self.clone()),
+        }
+        Self::save_all(settings, &presets)
+    }
+
+    /// Renames the preset called `name`, failing if another one is already
+    /// called `new_name`.
+    pub fn rename(settings: &gio::Settings, name: &str, new_name: &str) -> Result<(), String> {
+        let mut presets = Self::load_all(settings);
+        if name != new_name && presets.iter().any(|p| p.name == new_name) {
+            return Err(gettext("A preset named “{}” already exists").replace("{}", new_name));
+        }
+        for preset in presets.iter_mut().filter(|p| p.name == name) {
+            preset.name = new_name.to_owned();
+        }
+        Self::save_all(settings, &presets).map_err(|_| gettext("Could not save preset"))
+    }
+
+    pub fn delete(settings: &gio::Settings, name: &str) -> Result<(), glib::BoolError> {
+        let presets = Self::load_all(settings)
+            .into_iter()
+            .filter(|p| p.name != name)
+            .collect::<Vec<_>>();
+        Self::save_all(settings, &presets)
+    }
+}
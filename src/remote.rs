@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+
+use gettextrs::gettext;
+use gtk::{gio, glib, prelude::*};
+use tempfile::TempDir;
+
+use crate::runtime;
+use crate::temp::create_temporary_dir;
+
+/// Name to give the local copy of `file`, which keeps its extension so the
+/// mimetype can still be guessed from it.
+fn local_name(file: &gio::File) -> PathBuf {
+    file.query_info(
+        gio::FILE_ATTRIBUTE_STANDARD_DISPLAY_NAME,
+        gio::FileQueryInfoFlags::NONE,
+        gio::Cancellable::NONE,
+    )
+    .ok()
+    .map(|info| PathBuf::from(info.display_name().as_str()))
+    .or_else(|| file.basename())
+    .filter(|n| n.file_name().is_some_and(|f| f == n.as_os_str()))
+    .unwrap_or_else(|| PathBuf::from("image"))
+}
+
+/// Size of `file` in bytes, if the location it is on knows it.
+pub fn size(file: &gio::File) -> Option<u64> {
+    file.query_info(
+        gio::FILE_ATTRIBUTE_STANDARD_SIZE,
+        gio::FileQueryInfoFlags::NONE,
+        gio::Cancellable::NONE,
+    )
+    .ok()
+    .map(|info| info.size() as u64)
+}
+
+/// Streams `file`, which may be on any location GIO supports, into a new
+/// temporary folder, returning the path of the copy along with the folder
+/// holding it. `progress` is given the number of bytes copied so far.
+///
+/// This blocks until the whole file is copied, so it must not run on the
+/// main thread.
+pub fn download(
+    file: &gio::File,
+    mut progress: impl FnMut(u64),
+) -> Result<(PathBuf, TempDir), glib::Error> {
+    let dir = runtime()
+        .block_on(create_temporary_dir())
+        .map_err(|e| glib::Error::new(gio::IOErrorEnum::Failed, &e.to_string()))?;
+    let path = dir.path().join(local_name(file));
+    file.copy(
+        &gio::File::for_path(&path),
+        gio::FileCopyFlags::OVERWRITE,
+        gio::Cancellable::NONE,
+        Some(&mut |done, _| progress(done.max(0) as u64)),
+    )?;
+    Ok((path, dir))
+}
+
+/// An output file chosen on a location without a local path. It is
+/// converted into a temporary folder first, then uploaded.
+#[derive(Debug)]
+pub struct RemoteOutput {
+    destination: gio::File,
+    local_path: PathBuf,
+    _dir: TempDir,
+}
+
+impl RemoteOutput {
+    pub fn new(destination: gio::File) -> std::io::Result<Self> {
+        let dir = runtime().block_on(create_temporary_dir())?;
+        let local_path = dir.path().join(local_name(&destination));
+        Ok(Self {
+            destination,
+            local_path,
+            _dir: dir,
+        })
+    }
+
+    /// Where the output has to be written before uploading it.
+    pub fn local_path(&self) -> &Path {
+        &self.local_path
+    }
+
+    pub fn uri(&self) -> String {
+        self.destination.uri().into()
+    }
+
+    /// Copies the output to its destination, replacing anything there.
+    pub async fn upload(self) -> Result<(), glib::Error> {
+        gio::spawn_blocking(move || {
+            gio::File::for_path(&self.local_path).copy(
+                &self.destination,
+                gio::FileCopyFlags::OVERWRITE,
+                gio::Cancellable::NONE,
+                None,
+            )
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(glib::Error::new(
+                gio::IOErrorEnum::Failed,
+                &gettext("Could not upload the output"),
+            ))
+        })
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::BinaryHeap;
+use std::num::NonZeroUsize;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+
+use shared_child::SharedChild;
+
+use crate::magick::wait_for_child;
+
+pub type JobId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Default)]
+struct TokenState {
+    canceled: AtomicBool,
+    children: Mutex<Vec<Arc<SharedChild>>>,
+    /// Tokens created by [`CancellationToken::child`], only kept while
+    /// something still holds them.
+    tokens: Mutex<Vec<Weak<TokenState>>>,
+}
+
+/// Cancels a job or a whole batch, killing any process started under it.
+///
+/// Canceling a token also cancels every token created through
+/// [`CancellationToken::child`].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn child(&self) -> Self {
+        let token = Self::new();
+        let mut tokens = self.state.tokens.lock().unwrap();
+        if self.is_canceled() {
+            token.cancel();
+        }
+        // Tokens of finished jobs are dropped here, so long-lived parents
+        // do not grow without bound.
+        tokens.retain(|t| t.strong_count() > 0);
+        tokens.push(Arc::downgrade(&token.state));
+        token
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.state.canceled.load(Ordering::SeqCst)
+    }
+
+    pub fn cancel(&self) {
+        self.state.canceled.store(true, Ordering::SeqCst);
+
+        for child in self.state.children.lock().unwrap().drain(..) {
+            kill_child(&child);
+        }
+
+        let tokens = std::mem::take(&mut *self.state.tokens.lock().unwrap());
+        for state in tokens.iter().filter_map(Weak::upgrade) {
+            CancellationToken { state }.cancel();
+        }
+    }
+
+    /// Tracks `child` so it gets killed on cancellation. Returns `false`, after
+    /// killing it, if the token was already canceled.
+    fn register(&self, child: &Arc<SharedChild>) -> bool {
+        let mut children = self.state.children.lock().unwrap();
+        if self.is_canceled() {
+            kill_child(child);
+            return false;
+        }
+        children.push(child.clone());
+        true
+    }
+
+    fn unregister(&self, child: &Arc<SharedChild>) {
+        self.state
+            .children
+            .lock()
+            .unwrap()
+            .retain(|c| !Arc::ptr_eq(c, child));
+    }
+}
+
+fn kill_child(child: &SharedChild) {
+    if child.kill().is_err() {
+        child.wait().ok();
+    }
+}
+
+/// A single unit of work of a job.
+pub enum Step {
+    /// An external process, killed on cancellation.
+    Command(Command),
+    /// Work done on the worker thread itself. It cannot be interrupted, the
+    /// job only stops once it returns.
+    Task(Box<dyn FnOnce() -> Result<(), String> + Send>),
+    /// A step only chosen once the job runs, on the worker thread, as
+    /// choosing it reads the input.
+    Deferred(Box<dyn FnOnce() -> Step + Send>),
+}
+
+impl std::fmt::Debug for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Command(command) => f.debug_tuple("Command").field(command).finish(),
+            Step::Task(_) => f.debug_tuple("Task").finish_non_exhaustive(),
+            Step::Deferred(_) => f.debug_tuple("Deferred").finish_non_exhaustive(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobEvent {
+    /// One step of the job finished successfully.
+    Progress(JobId),
+    /// The job ran all of its steps, or stopped at the first failing one.
+    /// Canceled jobs never report this.
+    Done(JobId, Result<(), String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    pub id: JobId,
+    token: CancellationToken,
+}
+
+impl JobHandle {
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+}
+
+#[derive(Debug)]
+struct QueuedJob {
+    id: JobId,
+    priority: Priority,
+    steps: Vec<Step>,
+    token: CancellationToken,
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for QueuedJob {}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedJob {
+    // Higher priorities first, then in submission order.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    jobs: Mutex<BinaryHeap<QueuedJob>>,
+    available: Condvar,
+    closed: AtomicBool,
+}
+
+/// Runs submitted jobs on a fixed number of worker threads, so a big batch
+/// never starts more processes or in-process conversions than there are
+/// workers.
+#[derive(Debug)]
+pub struct Scheduler {
+    queue: Arc<Queue>,
+    /// Parent of the tokens of the current batch, replaced once canceled.
+    token: Mutex<CancellationToken>,
+    next_id: AtomicUsize,
+    sender: async_channel::Sender<JobEvent>,
+    receiver: async_channel::Receiver<JobEvent>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler with `workers` threads, or one per CPU if zero.
+    pub fn new(workers: usize) -> Self {
+        let workers = match workers {
+            0 => std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            n => n,
+        };
+
+        let queue = Arc::new(Queue::default());
+        let (sender, receiver) = async_channel::unbounded();
+
+        for _ in 0..workers {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || worker(&queue, &sender));
+        }
+
+        Self {
+            queue,
+            token: Mutex::default(),
+            next_id: AtomicUsize::new(0),
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn events(&self) -> async_channel::Receiver<JobEvent> {
+        self.receiver.clone()
+    }
+
+    pub fn submit(&self, priority: Priority, steps: Vec<Step>) -> JobHandle {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let batch = self.token.lock().unwrap();
+        let token = batch.child();
+
+        self.queue.jobs.lock().unwrap().push(QueuedJob {
+            id,
+            priority,
+            steps,
+            token: token.clone(),
+        });
+        drop(batch);
+        self.queue.available.notify_one();
+
+        JobHandle { id, token }
+    }
+
+    /// Cancels every queued and running job. This is the only way a batch
+    /// should be stopped, jobs submitted afterwards start a new one.
+    pub fn cancel(&self) {
+        let mut batch = self.token.lock().unwrap();
+        std::mem::take(&mut *batch).cancel();
+        self.queue.jobs.lock().unwrap().clear();
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.cancel();
+        self.queue.closed.store(true, Ordering::SeqCst);
+        self.queue.available.notify_all();
+        self.sender.close();
+    }
+}
+
+fn worker(queue: &Queue, sender: &async_channel::Sender<JobEvent>) {
+    loop {
+        let job = {
+            let mut jobs = queue.jobs.lock().unwrap();
+            loop {
+                if queue.closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                if let Some(job) = jobs.pop() {
+                    break job;
+                }
+                jobs = queue.available.wait(jobs).unwrap();
+            }
+        };
+
+        if let Some(event) = run_job(job, sender) {
+            sender.send_blocking(event).ok();
+        }
+    }
+}
+
+fn run_job(job: QueuedJob, sender: &async_channel::Sender<JobEvent>) -> Option<JobEvent> {
+    let QueuedJob {
+        id, steps, token, ..
+    } = job;
+
+    for mut step in steps {
+        if token.is_canceled() {
+            return None;
+        }
+        while let Step::Deferred(choose) = step {
+            step = choose();
+        }
+
+        let result = match step {
+            Step::Command(mut command) => {
+                let child = match SharedChild::spawn(&mut command) {
+                    Ok(child) => Arc::new(child),
+                    Err(e) => {
+                        let program = command.get_program().to_string_lossy();
+                        return Some(JobEvent::Done(id, Err(format!("{program}: {e}"))));
+                    }
+                };
+
+                if !token.register(&child) {
+                    return None;
+                }
+                let result = wait_for_child(child.clone());
+                token.unregister(&child);
+                result
+            }
+            Step::Task(task) => task(),
+            Step::Deferred(_) => unreachable!(),
+        };
+
+        if token.is_canceled() {
+            return None;
+        }
+
+        match result {
+            Ok(()) => {
+                sender.send_blocking(JobEvent::Progress(id)).ok();
+            }
+            Err(e) => return Some(JobEvent::Done(id, Err(e))),
+        }
+    }
+
+    Some(JobEvent::Done(id, Ok(())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task() -> Vec<Step> {
+        vec![Step::Task(Box::new(|| Ok(())))]
+    }
+
+    #[test]
+    fn forgets_finished_child_tokens() {
+        let parent = CancellationToken::new();
+        let kept = parent.child();
+        for _ in 0..100 {
+            drop(parent.child());
+        }
+        assert_eq!(parent.state.tokens.lock().unwrap().len(), 2);
+
+        parent.cancel();
+        assert!(kept.is_canceled());
+        assert!(parent.child().is_canceled());
+    }
+
+    #[test]
+    fn runs_jobs_submitted_after_a_cancel() {
+        let scheduler = Scheduler::new(1);
+        let events = scheduler.events();
+        let canceled = scheduler.submit(Priority::Normal, task());
+        scheduler.cancel();
+        assert!(canceled.token.is_canceled());
+
+        let handle = scheduler.submit(Priority::Normal, task());
+        assert!(!handle.token.is_canceled());
+        let done = loop {
+            match events.recv_blocking().unwrap() {
+                JobEvent::Done(id, result) => break (id, result),
+                JobEvent::Progress(_) => {}
+            }
+        };
+        assert_eq!(done, (handle.id, Ok(())));
+    }
+
+    #[test]
+    fn chooses_deferred_steps_on_the_worker() {
+        let scheduler = Scheduler::new(1);
+        let events = scheduler.events();
+        let caller = std::thread::current().id();
+        let step = Step::Deferred(Box::new(move || {
+            let worker = std::thread::current().id();
+            Step::Task(Box::new(move || match worker == caller {
+                true => Err("chosen on the calling thread".to_owned()),
+                false => Ok(()),
+            }))
+        }));
+
+        let handle = scheduler.submit(Priority::Normal, vec![step]);
+        assert_eq!(
+            events.recv_blocking().unwrap(),
+            JobEvent::Progress(handle.id)
+        );
+        assert_eq!(
+            events.recv_blocking().unwrap(),
+            JobEvent::Done(handle.id, Ok(()))
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use gettextrs::gettext;
+use gtk::{gio, glib, prelude::*};
+use log::{debug, warn};
+
+use crate::backend;
+use crate::cli::{self, CliOptions, PreparedConversion};
+use crate::conversion::PlannedJob;
+use crate::scheduler::{JobEvent, JobHandle, JobId, Priority, Scheduler};
+
+const INTERFACE_NAME: &str = "io.gitlab.adhami3310.Converter.Service";
+const INTERFACE_XML: &str = include_str!("../data/io.gitlab.adhami3310.Converter.Service.xml");
+
+/// Boolean command line options, only looked up by presence.
+const FLAGS: [&str; 4] = ["pixel-art", "hidden", "keep-folders", "delete-orphans"];
+
+/// A conversion queued over D-Bus.
+struct Conversion {
+    options: CliOptions,
+    prepared: PreparedConversion,
+    handles: Vec<JobHandle>,
+    completed: usize,
+    remaining: usize,
+    succeeded: Vec<PlannedJob>,
+    errors: Vec<String>,
+    /// Keeps the application running while converting without a window.
+    _hold: gio::ApplicationHoldGuard,
+}
+
+struct State {
+    application: glib::WeakRef<gio::Application>,
+    connection: gio::DBusConnection,
+    object_path: String,
+    settings: gio::Settings,
+    scheduler: Scheduler,
+    conversions: RefCell<HashMap<u32, Conversion>>,
+    /// Conversions whose inputs are still being loaded.
+    preparing: RefCell<HashSet<u32>>,
+    /// The conversion and plan index of each scheduled job.
+    jobs: RefCell<HashMap<JobId, (u32, usize)>>,
+    next_id: Cell<u32>,
+}
+
+/// Exports the conversion interface documented in
+/// `data/io.gitlab.adhami3310.Converter.Service.xml` for as long as it is
+/// alive.
+pub struct ConversionService {
+    state: Rc<State>,
+    registration: Option<gio::RegistrationId>,
+}
+
+impl std::fmt::Debug for ConversionService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConversionService")
+            .field("object_path", &self.state.object_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ConversionService {
+    pub fn register(
+        application: &impl IsA<gio::Application>,
+        connection: &gio::DBusConnection,
+        object_path: &str,
+        settings: &gio::Settings,
+    ) -> Result<Self, glib::Error> {
+        let interface = gio::DBusNodeInfo::for_xml(INTERFACE_XML)?
+            .lookup_interface(INTERFACE_NAME)
+            .expect("the interface is described in its XML");
+
+        let state = Rc::new(State {
+            application: application.upcast_ref::<gio::Application>().downgrade(),
+            connection: connection.clone(),
+            object_path: object_path.to_owned(),
+            settings: settings.clone(),
+            scheduler: Scheduler::new(settings.int("max-parallel-jobs") as usize),
+            conversions: RefCell::default(),
+            preparing: RefCell::default(),
+            jobs: RefCell::default(),
+            next_id: Cell::new(1),
+        });
+
+        let weak = Rc::downgrade(&state);
+        let registration = connection
+            .register_object(object_path, &interface)
+            .method_call(move |_, _, _, _, method, parameters, invocation| {
+                let Some(state) = weak.upgrade() else {
+                    return;
+                };
+                let result = match method {
+                    "Convert" => state
+                        .convert(&parameters)
+                        .map(|id| Some((id,).to_variant())),
+                    "Cancel" => state.cancel(&parameters).map(|()| None),
+                    _ => Err(gettext("Unknown method {}").replace("{}", method)),
+                };
+                match result {
+                    Ok(value) => invocation.return_value(value.as_ref()),
+                    Err(e) => {
+                        invocation.return_dbus_error("org.freedesktop.DBus.Error.InvalidArgs", &e)
+                    }
+                }
+            })
+            .build()?;
+
+        let events = state.scheduler.events();
+        let weak = Rc::downgrade(&state);
+        glib::spawn_future_local(async move {
+            while let Ok(event) = events.recv().await {
+                let Some(state) = weak.upgrade() else {
+                    break;
+                };
+                state.job_event(event);
+            }
+        });
+
+        Ok(Self {
+            state,
+            registration: Some(registration),
+        })
+    }
+}
+
+impl Drop for ConversionService {
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration.take() {
+            self.state.connection.unregister_object(registration).ok();
+        }
+    }
+}
+
+impl State {
+    fn convert(self: &Rc<Self>, parameters: &glib::Variant) -> Result<u32, String> {
+        let files = parameters
+            .child_value(0)
+            .get::<Vec<String>>()
+            .ok_or_else(|| gettext("Invalid arguments"))?;
+
+        let dict = command_line_options(&parameters.child_value(1))?;
+        let paths = files
+            .iter()
+            .map(|f| absolute_file(f).map(|file| file.path().unwrap_or_else(|| PathBuf::from(f))))
+            .collect::<Result<Vec<_>, _>>()?;
+        if paths.is_empty() {
+            return Err(gettext("No input files given"));
+        }
+        dict.insert_value(glib::OPTION_REMAINING, &paths.to_variant());
+
+        let options = CliOptions::from_options(&dict, &self.settings)?
+            .ok_or_else(|| gettext("Missing output"))?;
+        let hold = self
+            .application
+            .upgrade()
+            .ok_or_else(|| gettext("The application is shutting down"))?
+            .hold();
+
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1).max(1));
+        self.preparing.borrow_mut().insert(id);
+
+        // Loading the inputs can take a while, so the caller gets the id
+        // first and hears about failures through the Finished signal.
+        let weak = Rc::downgrade(self);
+        glib::spawn_future_local(async move {
+            let prepared = cli::prepare(&options).await;
+            let Some(state) = weak.upgrade() else {
+                return;
+            };
+            if !state.preparing.borrow_mut().remove(&id) {
+                // Canceled while preparing.
+                return;
+            }
+            match prepared {
+                Ok(prepared) => state.start(id, options, prepared, hold),
+                Err(e) => state.emit_finished(id, false, &[], &[e]),
+            }
+        });
+
+        Ok(id)
+    }
+
+    fn start(
+        self: &Rc<Self>,
+        id: u32,
+        options: CliOptions,
+        prepared: PreparedConversion,
+        hold: gio::ApplicationHoldGuard,
+    ) {
+        let handles = prepared
+            .plan
+            .jobs
+            .iter()
+            .enumerate()
+            .map(|(i, job)| {
+                let handle = self
+                    .scheduler
+                    .submit(Priority::Normal, backend::steps(&job.commands));
+                self.jobs.borrow_mut().insert(handle.id, (id, i));
+                handle
+            })
+            .collect::<Vec<_>>();
+        debug!("D-Bus conversion {id} runs {} jobs", handles.len());
+
+        let conversion = Conversion {
+            remaining: handles.len(),
+            errors: prepared.rejected.clone(),
+            options,
+            prepared,
+            handles,
+            completed: 0,
+            succeeded: vec![],
+            _hold: hold,
+        };
+        self.conversions.borrow_mut().insert(id, conversion);
+        self.finish_if_done(id);
+    }
+
+    fn cancel(&self, parameters: &glib::Variant) -> Result<(), String> {
+        let (id,) = parameters
+            .get::<(u32,)>()
+            .ok_or_else(|| gettext("Invalid arguments"))?;
+        if !self.preparing.borrow_mut().remove(&id) {
+            let conversion = self.conversions.borrow_mut().remove(&id).ok_or_else(|| {
+                gettext("No conversion with id {}").replace("{}", &id.to_string())
+            })?;
+
+            for handle in conversion.handles.iter() {
+                handle.cancel();
+            }
+            self.jobs.borrow_mut().retain(|_, (c, _)| *c != id);
+        }
+        self.emit_finished(id, false, &[], &[gettext("Canceled")]);
+        Ok(())
+    }
+
+    fn job_event(self: &Rc<Self>, event: JobEvent) {
+        let (JobEvent::Progress(job) | JobEvent::Done(job, _)) = event;
+        let Some((id, index)) = self.jobs.borrow().get(&job).copied() else {
+            return;
+        };
+
+        {
+            let mut conversions = self.conversions.borrow_mut();
+            let Some(conversion) = conversions.get_mut(&id) else {
+                return;
+            };
+            match event {
+                JobEvent::Progress(_) => {
+                    conversion.completed += 1;
+                    let total = conversion.prepared.plan.command_count();
+                    let progress = (id, conversion.completed as u32, total as u32);
+                    self.emit("Progress", progress.to_variant());
+                    return;
+                }
+                JobEvent::Done(_, result) => {
+                    conversion.remaining -= 1;
+                    let planned = &conversion.prepared.plan.jobs[index];
+                    match result {
+                        Ok(()) => conversion.succeeded.push(planned.clone()),
+                        Err(e) => conversion.errors.push(format!(
+                            "{}: {}",
+                            conversion.prepared.inputs[planned.input].path.display(),
+                            e.trim()
+                        )),
+                    }
+                }
+            }
+        }
+
+        self.jobs.borrow_mut().remove(&job);
+        self.finish_if_done(id);
+    }
+
+    fn finish_if_done(self: &Rc<Self>, id: u32) {
+        let mut conversion = {
+            let mut conversions = self.conversions.borrow_mut();
+            if conversions.get(&id).is_none_or(|c| c.remaining > 0) {
+                return;
+            }
+            conversions.remove(&id).unwrap()
+        };
+
+        let outputs = conversion
+            .succeeded
+            .iter()
+            .map(|job| job.output_name.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let succeeded = std::mem::take(&mut conversion.succeeded);
+
+        // Moving or compressing the outputs can take a while. The conversion
+        // is kept until then, along with its temporary folder.
+        let weak = Rc::downgrade(self);
+        glib::spawn_future_local(async move {
+            if !succeeded.is_empty() {
+                let plan = conversion.prepared.succeeded(succeeded);
+                let save_format = conversion.prepared.save_format;
+                let output = conversion.options.output.clone();
+                let level = conversion.options.compression_level;
+                let result = gio::spawn_blocking(move || plan.collect(save_format, &output, level))
+                    .await
+                    .unwrap_or_else(|_| Err(gettext("Unknown IO error happened")));
+                if let Err(e) = result {
+                    conversion.errors.push(e);
+                }
+            }
+
+            let Some(state) = weak.upgrade() else {
+                return;
+            };
+            let success = conversion.errors.is_empty();
+            state.emit_finished(id, success, &outputs, &conversion.errors);
+        });
+    }
+
+    fn emit_finished(&self, id: u32, success: bool, outputs: &[String], errors: &[String]) {
+        let parameters = (id, success, outputs.to_vec(), errors.to_vec());
+        self.emit("Finished", parameters.to_variant());
+    }
+
+    fn emit(&self, signal: &str, parameters: glib::Variant) {
+        if let Err(e) = self.connection.emit_signal(
+            None,
+            &self.object_path,
+            INTERFACE_NAME,
+            signal,
+            Some(&parameters),
+        ) {
+            warn!("Could not emit {signal}: {e}");
+        }
+    }
+}
+
+/// The file `name` refers to, which has to be an absolute path or a URI as
+/// relative paths would be resolved against the folder the service runs in
+/// rather than the caller's.
+fn absolute_file(name: &str) -> Result<gio::File, String> {
+    if glib::Uri::peek_scheme(name).is_some() {
+        Ok(gio::File::for_uri(name))
+    } else if Path::new(name).is_absolute() {
+        Ok(gio::File::for_path(name))
+    } else {
+        Err(gettext("Not an absolute path or URI: {}").replace("{}", name))
+    }
+}
+
+/// Turns the options of a Convert() call into those of the command line.
+fn command_line_options(options: &glib::Variant) -> Result<glib::VariantDict, String> {
+    let dict = glib::VariantDict::new(None);
+    for entry in options.iter() {
+        let Some(name) = entry.child_value(0).str().map(str::to_owned) else {
+            continue;
+        };
+        let value = entry
+            .child_value(1)
+            .as_variant()
+            .unwrap_or(entry.child_value(1));
+
+        match name.as_str() {
+            "watch" | "jobs" => {
+                return Err(gettext("Unsupported option: {}").replace("{}", &name));
+            }
+            // The command line takes file names rather than strings.
+            "output" => {
+                let output = value.str().ok_or_else(|| gettext("Invalid output"))?;
+                let output = absolute_file(output)?
+                    .path()
+                    .ok_or_else(|| gettext("Invalid output"))?;
+                dict.insert_value(&name, &output.to_variant());
+            }
+            flag if FLAGS.contains(&flag) => match value.get::<bool>() {
+                Some(true) => dict.insert_value(&name, &value),
+                Some(false) => {}
+                None => return Err(gettext("Invalid value for {}").replace("{}", &name)),
+            },
+            _ => dict.insert_value(&name, &value),
+        }
+    }
+    Ok(dict)
+}
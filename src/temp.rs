@@ -16,7 +16,3 @@ pub fn get_temp_file_path(dir: &TempDir, identifer: JobFile) -> PathBuf {
     let dir_path = dir.path();
     dir_path.join(identifer.as_filename())
 }
-
-pub fn clean_dir(temp_dir_path: String) {
-    std::fs::remove_dir_all(temp_dir_path).unwrap();
-}
@@ -0,0 +1,279 @@
+use std::ffi::{OsStr, OsString};
+
+use gettextrs::gettext;
+
+/// The template used when none is configured, it keeps the input's name.
+pub const DEFAULT_TEMPLATE: &str = "{stem}.{ext}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Stem,
+    Index,
+    Width,
+    Height,
+    Ext,
+    Format,
+    Date,
+    Counter,
+}
+
+impl Token {
+    fn from_string(name: &str) -> Option<Self> {
+        match name {
+            "stem" => Some(Token::Stem),
+            "index" => Some(Token::Index),
+            "width" => Some(Token::Width),
+            "height" => Some(Token::Height),
+            "ext" => Some(Token::Ext),
+            "format" => Some(Token::Format),
+            "date" => Some(Token::Date),
+            "counter" => Some(Token::Counter),
+            _ => None,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Token::Index | Token::Width | Token::Height | Token::Counter
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    /// A token, and the width numbers are zero-padded to.
+    Token(Token, usize),
+}
+
+/// Everything a template can refer to for a single output file.
+#[derive(Debug, Clone, Default)]
+pub struct NameFields<'a> {
+    pub stem: &'a OsStr,
+    /// Frame or page number, starting at zero.
+    pub index: usize,
+    pub dimensions: Option<(usize, usize)>,
+    pub extension: &'a str,
+    pub date: &'a str,
+    /// Position of the output in the batch, starting at one.
+    pub counter: usize,
+}
+
+/// Output file name pattern such as `{stem}-{width}x{height}-{index:03}.{ext}`.
+///
+/// Numeric tokens accept a `:0N` suffix padding them with zeros, and `{{` and
+/// `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    segments: Vec<Segment>,
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        if template.trim().is_empty() {
+            return Err(gettext("The file name template is empty"));
+        }
+        if template.contains('/') {
+            return Err(gettext("File names cannot contain “/”"));
+        }
+
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut token = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => token.push(c),
+                            None => return Err(gettext("Unclosed “{” in file name template")),
+                        }
+                    }
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Self::parse_token(&token)?);
+                }
+                '}' => return Err(gettext("Unmatched “}” in file name template")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(Self { segments })
+    }
+
+    fn parse_token(token: &str) -> Result<Segment, String> {
+        let unknown = || gettext("Unknown file name token: {}").replace("{}", token);
+
+        let (name, padding) = match token.split_once(':') {
+            Some((name, padding)) => (name, Some(padding)),
+            None => (token, None),
+        };
+        let kind = Token::from_string(name).ok_or_else(unknown)?;
+        let width = match padding {
+            Some(p) if kind.is_numeric() => p.parse().map_err(|_| unknown())?,
+            Some(_) => return Err(unknown()),
+            None => 0,
+        };
+
+        Ok(Segment::Token(kind, width))
+    }
+
+    pub fn has_index(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, Segment::Token(Token::Index, _)))
+    }
+
+    /// The file name for `fields`, keeping the stem as it is even when it is
+    /// not valid UTF-8. Names that would not stand for a file, such as `..`,
+    /// are rejected.
+    pub fn render(&self, fields: &NameFields) -> Result<OsString, String> {
+        let number = |n: Option<usize>, width: usize| match n {
+            Some(n) => format!("{n:0width$}"),
+            None => "0".repeat(width.max(1)),
+        };
+
+        let mut name = OsString::new();
+        for segment in self.segments.iter() {
+            match *segment {
+                Segment::Text(ref text) => name.push(text),
+                Segment::Token(Token::Stem, _) => name.push(fields.stem),
+                Segment::Token(Token::Index, w) => name.push(number(Some(fields.index), w)),
+                Segment::Token(Token::Width, w) => {
+                    name.push(number(fields.dimensions.map(|d| d.0), w))
+                }
+                Segment::Token(Token::Height, w) => {
+                    name.push(number(fields.dimensions.map(|d| d.1), w))
+                }
+                Segment::Token(Token::Ext, _) => name.push(fields.extension),
+                Segment::Token(Token::Format, _) => name.push(fields.extension.to_uppercase()),
+                Segment::Token(Token::Date, _) => name.push(fields.date),
+                Segment::Token(Token::Counter, w) => name.push(number(Some(fields.counter), w)),
+            }
+        }
+
+        if ["", ".", ".."].iter().any(|n| name == *n) {
+            return Err(
+                gettext("The file name template gives the invalid name “{}”")
+                    .replace("{}", &name.to_string_lossy()),
+            );
+        }
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> NameFields<'static> {
+        NameFields {
+            stem: OsStr::new("photo"),
+            index: 7,
+            dimensions: Some((640, 480)),
+            extension: "webp",
+            date: "2024-05-01",
+            counter: 3,
+        }
+    }
+
+    fn render(template: &str, fields: &NameFields) -> String {
+        let name = NameTemplate::parse(template)
+            .unwrap()
+            .render(fields)
+            .unwrap();
+        name.into_string().unwrap()
+    }
+
+    #[test]
+    fn renders_every_token() {
+        assert_eq!(
+            render(
+                "{stem}-{index}-{width}x{height}-{date}-{counter}.{format}.{ext}",
+                &fields()
+            ),
+            "photo-7-640x480-2024-05-01-3.WEBP.webp"
+        );
+        assert_eq!(render("{{{stem}}}.{ext}", &fields()), "{photo}.webp");
+        assert_eq!(
+            NameTemplate::default(),
+            NameTemplate::parse("{stem}.{ext}").unwrap()
+        );
+        assert!(NameTemplate::parse("{stem}-{index}").unwrap().has_index());
+        assert!(!NameTemplate::default().has_index());
+    }
+
+    #[test]
+    fn pads_numbers() {
+        assert_eq!(render("{index:03}", &fields()), "007");
+        assert_eq!(render("{counter:2}-{width:05}", &fields()), "03-00640");
+        assert_eq!(render("{index:01}", &fields()), "7");
+        let unknown = NameFields {
+            dimensions: None,
+            ..fields()
+        };
+        assert_eq!(render("{width}x{height:03}", &unknown), "0x000");
+    }
+
+    #[test]
+    fn rejects_unknown_tokens() {
+        for template in [
+            "{name}.png",
+            "{}",
+            "{stem:03}",
+            "{ext:1}",
+            "{index:x}",
+            "{index:-1}",
+        ] {
+            assert!(NameTemplate::parse(template).is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        for template in ["{stem", "stem}", "{stem}}", "{{stem}", "{index:03"] {
+            assert!(NameTemplate::parse(template).is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_files() {
+        for template in ["", " ", "a/b", "{stem}/{ext}"] {
+            assert!(NameTemplate::parse(template).is_err(), "{template:?}");
+        }
+        for (template, stem) in [
+            ("..", "photo"),
+            (".", "photo"),
+            ("{stem}", ""),
+            ("{stem}.", "."),
+        ] {
+            let fields = NameFields {
+                stem: OsStr::new(stem),
+                ..fields()
+            };
+            let template = NameTemplate::parse(template).unwrap();
+            assert!(template.render(&fields).is_err(), "{template:?} {stem:?}");
+        }
+    }
+}
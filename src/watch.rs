@@ -0,0 +1,366 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use gettextrs::gettext;
+use gtk::{gio, glib, prelude::*};
+use itertools::Itertools;
+use log::debug;
+use tempfile::TempDir;
+
+use crate::backend;
+use crate::conversion::{ConflictResolution, ConversionInput, ConversionPlan, Converter};
+use crate::filetypes::{CompressionType, FileType, OutputType};
+use crate::image_info::ImageInfo;
+use crate::input_file::InputError;
+use crate::runtime;
+use crate::scheduler::{JobEvent, JobId, Priority, Scheduler};
+use crate::temp::create_temporary_dir;
+
+/// How long a file has to go without changing before it gets converted, so
+/// images still being written or copied are not picked up halfway.
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// What happened to an image that landed in a watched folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// The image was converted into these outputs, relative to the output
+    /// folder. None means they all existed and were skipped.
+    Converted {
+        input: PathBuf,
+        outputs: Vec<PathBuf>,
+    },
+    Failed {
+        input: PathBuf,
+        error: String,
+    },
+}
+
+/// Everything needed to convert the images of a watched folder.
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub converter: Converter,
+    /// Applied to outputs already in the destination.
+    pub conflict: ConflictResolution,
+    /// Maximum number of images converted at the same time, zero for one
+    /// per CPU.
+    pub jobs: usize,
+}
+
+/// An image being converted, waiting for the last of its jobs.
+struct Batch {
+    input: PathBuf,
+    plan: ConversionPlan,
+    _dir: TempDir,
+    remaining: usize,
+    error: Option<String>,
+}
+
+struct State {
+    options: WatchOptions,
+    scheduler: Scheduler,
+    monitor: gio::FileMonitor,
+    /// Files waiting to settle, with the timeout converting them.
+    pending: RefCell<HashMap<PathBuf, glib::SourceId>>,
+    batches: RefCell<HashMap<usize, Batch>>,
+    /// The batch each running job belongs to.
+    jobs: RefCell<HashMap<JobId, usize>>,
+    next_batch: Cell<usize>,
+    on_event: Box<dyn Fn(WatchEvent)>,
+}
+
+/// Converts every image landing in a folder for as long as it is alive.
+///
+/// Only files created, changed or moved into the folder after it started are
+/// converted, not the ones already there nor those of its subfolders.
+pub struct FolderWatcher {
+    state: Rc<State>,
+}
+
+impl std::fmt::Debug for FolderWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FolderWatcher")
+            .field("options", &self.state.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FolderWatcher {
+    /// Starts watching, calling `on_event` on the main context once each
+    /// image has been dealt with.
+    pub fn start<F>(options: WatchOptions, on_event: F) -> Result<Self, String>
+    where
+        F: Fn(WatchEvent) + 'static,
+    {
+        let inside = match (
+            options.source.canonicalize(),
+            options.destination.canonicalize(),
+        ) {
+            (Ok(source), Ok(destination)) => destination.starts_with(source),
+            _ => options.destination.starts_with(&options.source),
+        };
+        if inside {
+            return Err(gettext(
+                "The output folder cannot be the watched folder or inside it",
+            ));
+        }
+
+        let monitor = gio::File::for_path(&options.source)
+            .monitor_directory(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+            .map_err(|e| format!("{}: {e}", options.source.display()))?;
+
+        let state = Rc::new(State {
+            scheduler: Scheduler::new(options.jobs),
+            options,
+            monitor,
+            pending: RefCell::default(),
+            batches: RefCell::default(),
+            jobs: RefCell::default(),
+            next_batch: Cell::new(0),
+            on_event: Box::new(on_event),
+        });
+
+        let weak = Rc::downgrade(&state);
+        state
+            .monitor
+            .connect_changed(move |_, file, other_file, event| {
+                use gio::FileMonitorEvent::*;
+
+                let Some(state) = weak.upgrade() else {
+                    return;
+                };
+                match event {
+                    Created | Changed | ChangesDoneHint | AttributeChanged | MovedIn => {
+                        if let Some(path) = file.path() {
+                            state.schedule(path);
+                        }
+                    }
+                    // Downloads and copies often write to a temporary name first.
+                    Renamed => {
+                        if let Some(path) = file.path() {
+                            state.unschedule(&path);
+                        }
+                        if let Some(path) = other_file.and_then(|f| f.path()) {
+                            state.schedule(path);
+                        }
+                    }
+                    Deleted | MovedOut => {
+                        if let Some(path) = file.path() {
+                            state.unschedule(&path);
+                        }
+                    }
+                    _ => {}
+                }
+            });
+
+        let events = state.scheduler.events();
+        let weak = Rc::downgrade(&state);
+        glib::spawn_future_local(async move {
+            while let Ok(event) = events.recv().await {
+                let Some(state) = weak.upgrade() else {
+                    break;
+                };
+                if let JobEvent::Done(id, result) = event {
+                    state.job_done(id, result);
+                }
+            }
+        });
+
+        Ok(Self { state })
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.state.options.source
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.state.options.destination
+    }
+}
+
+impl Drop for FolderWatcher {
+    fn drop(&mut self) {
+        self.state.monitor.cancel();
+        for (_, id) in self.state.pending.borrow_mut().drain() {
+            id.remove();
+        }
+    }
+}
+
+impl State {
+    /// Converts `path` once it stopped changing for [`SETTLE_DELAY`].
+    fn schedule(self: &Rc<Self>, path: PathBuf) {
+        let hidden = path
+            .file_name()
+            .is_none_or(|n| n.to_string_lossy().starts_with('.'));
+        if hidden {
+            return;
+        }
+
+        self.unschedule(&path);
+        let weak = Rc::downgrade(self);
+        let key = path.clone();
+        let id = glib::timeout_add_local_once(SETTLE_DELAY, move || {
+            if let Some(state) = weak.upgrade() {
+                state.pending.borrow_mut().remove(&path);
+                state.convert(path);
+            }
+        });
+        self.pending.borrow_mut().insert(key, id);
+    }
+
+    fn unschedule(&self, path: &Path) {
+        if let Some(id) = self.pending.borrow_mut().remove(path) {
+            id.remove();
+        }
+    }
+
+    fn convert(self: &Rc<Self>, path: PathBuf) {
+        // Anything that is not an image, or is gone already, is left alone.
+        let Some(kind) = input_type(&path) else {
+            debug!("Not converting {}", path.display());
+            return;
+        };
+        if !backend::can_read(kind) {
+            let error = InputError::Unavailable(kind).to_string();
+            (self.on_event)(WatchEvent::Failed { input: path, error });
+            return;
+        }
+
+        let weak = Rc::downgrade(self);
+        glib::spawn_future_local(async move {
+            let frames_path = path.clone();
+            let info =
+                gio::spawn_blocking(move || runtime().block_on(backend::probe(frames_path, kind)))
+                    .await
+                    .ok()
+                    .and_then(Result::ok)
+                    .unwrap_or_default();
+            if let Some(state) = weak.upgrade() {
+                state.submit(path, kind, &info);
+            }
+        });
+    }
+
+    fn submit(&self, input: PathBuf, kind: FileType, info: &ImageInfo) {
+        let dir = match runtime().block_on(create_temporary_dir()) {
+            Ok(dir) => dir,
+            Err(e) => {
+                let error = e.to_string();
+                (self.on_event)(WatchEvent::Failed { input, error });
+                return;
+            }
+        };
+
+        let conversion_input = ConversionInput {
+            path: input.clone(),
+            kind,
+            frames: info.frame_count(),
+            dimensions: info.dimensions(),
+            has_alpha: info.is_transparent(kind),
+            relative_dir: None,
+            temporary: false,
+        };
+        let destination = &self.options.destination;
+        let mut plan = match self.options.converter.plan(&[conversion_input], &dir) {
+            Ok(plan) => plan,
+            Err(error) => {
+                (self.on_event)(WatchEvent::Failed { input, error });
+                return;
+            }
+        };
+        let resolutions = plan
+            .conflicts(destination)
+            .into_iter()
+            .map(|i| (i, self.options.conflict.clone()))
+            .collect_vec();
+        plan.resolve(destination, &resolutions);
+
+        if plan.jobs.is_empty() {
+            let outputs = vec![];
+            (self.on_event)(WatchEvent::Converted { input, outputs });
+            return;
+        }
+
+        let batch = self.next_batch.get();
+        self.next_batch.set(batch + 1);
+        for job in plan.jobs.iter() {
+            let handle = self
+                .scheduler
+                .submit(Priority::Normal, backend::steps(&job.commands));
+            self.jobs.borrow_mut().insert(handle.id, batch);
+        }
+        self.batches.borrow_mut().insert(
+            batch,
+            Batch {
+                input,
+                remaining: plan.jobs.len(),
+                plan,
+                _dir: dir,
+                error: None,
+            },
+        );
+    }
+
+    fn job_done(&self, id: JobId, result: Result<(), String>) {
+        let Some(batch_id) = self.jobs.borrow_mut().remove(&id) else {
+            return;
+        };
+
+        let batch = {
+            let mut batches = self.batches.borrow_mut();
+            let Some(batch) = batches.get_mut(&batch_id) else {
+                return;
+            };
+            batch.remaining -= 1;
+            if let Err(e) = result {
+                batch.error.get_or_insert(e.trim().to_owned());
+            }
+            if batch.remaining > 0 {
+                return;
+            }
+            batches.remove(&batch_id).unwrap()
+        };
+
+        let save_format = OutputType::Compression(CompressionType::Directory);
+        let result = match batch.error {
+            Some(error) => Err(error),
+            None => batch
+                .plan
+                .collect(save_format, &self.options.destination, 0),
+        };
+        let input = batch.input;
+        let event = match result {
+            Ok(()) => WatchEvent::Converted {
+                input,
+                outputs: batch.plan.jobs.into_iter().map(|j| j.output_name).collect(),
+            },
+            Err(error) => WatchEvent::Failed { input, error },
+        };
+        (self.on_event)(event);
+    }
+}
+
+/// The type of the image at `path`, `None` if it is not a supported image.
+fn input_type(path: &Path) -> Option<FileType> {
+    let info = gio::File::for_path(path)
+        .query_info(
+            &format!(
+                "{},{}",
+                gio::FILE_ATTRIBUTE_STANDARD_TYPE,
+                gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE
+            ),
+            gio::FileQueryInfoFlags::NONE,
+            gio::Cancellable::NONE,
+        )
+        .ok()?;
+    if info.file_type() != gio::FileType::Regular {
+        return None;
+    }
+    let mimetype = info.content_type();
+    FileType::detect(path, mimetype.as_ref().map(|m| m.as_str())).filter(|f| f.is_input())
+}
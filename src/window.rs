@@ -1,61 +1,44 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::os::fd::AsFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::atomic::AtomicUsize;
 
+use crate::backend;
 use crate::color::Color;
 use crate::config::APP_ID;
+use crate::conversion::{
+    ConflictPolicy, ConflictReport, ConflictResolution, ConversionInput, ConversionOptions,
+    ConversionPlan, Converter,
+};
 use crate::drag_overlay::DragOverlay;
 use crate::file_chooser::FileChooser;
 use crate::filetypes::{CompressionType, FileType, OutputType};
-use crate::input_file::InputFile;
-use crate::magick::{
-    JobFile, MagickConvertJob, ResizeArgument, count_frames, generate_job, wait_for_child,
-};
-use crate::temp::{clean_dir, create_temporary_dir, get_temp_file_path};
+use crate::folder::FolderFilter;
+use crate::input_file::{ImportProgress, InputFile, RejectedFile};
+use crate::magick::{JobFile, ResizeArgument, ResizeFilter};
+use crate::metadata::MetadataPolicy;
+use crate::mirror::{MirrorMode, MirrorReport};
+use crate::preset::Preset;
+use crate::remote::RemoteOutput;
+use crate::runtime;
+use crate::scheduler::{JobEvent, JobId, Priority, Scheduler, Step};
+use crate::temp::create_temporary_dir;
+use crate::template::NameTemplate;
+use crate::watch::{FolderWatcher, WatchEvent, WatchOptions};
 use crate::widgets::about_window::SwitcherooAbout;
 use crate::widgets::image_rest::ImageRest;
 use crate::widgets::image_thumbnail::ImageThumbnail;
-use crate::{ZIP_BINARY_NAME, runtime};
 use adw::prelude::*;
 use futures::future::join_all;
-use gettextrs::gettext;
+use gettextrs::{gettext, ngettext};
 use glib::{MainContext, clone, idle_add_local_once};
 use gtk::accessible::Property;
 use gtk::gdk::Texture;
 use gtk::{gdk, gio, glib, subclass::prelude::*};
 use itertools::Itertools;
-use shared_child::SharedChild;
-use std::sync::Arc;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ResizeFilter {
-    Default,
-    Point,
-}
-
-enum ArcOrOptionError {
-    Child(Arc<SharedChild>),
-    OptionError(Option<String>),
-}
-
-#[allow(dead_code)]
-impl ResizeFilter {
-    pub fn as_display_string(&self) -> Option<&str> {
-        match self {
-            ResizeFilter::Default => None,
-            ResizeFilter::Point => Some("Point"),
-        }
-    }
-
-    pub fn from_index(index: usize) -> Option<Self> {
-        match index {
-            0 => Some(ResizeFilter::Default),
-            1 => Some(ResizeFilter::Point),
-            _ => None,
-        }
-    }
-}
+use log::debug;
+use tempfile::TempDir;
 
 #[derive(Debug, Clone, Copy)]
 enum ResizeType {
@@ -74,10 +57,7 @@ impl ResizeType {
 }
 
 mod imp {
-    use std::{
-        cell::{Cell, RefCell},
-        sync::atomic::AtomicBool,
-    };
+    use std::cell::{Cell, RefCell};
 
     use crate::config::PKGDATADIR;
 
@@ -110,8 +90,12 @@ mod imp {
         #[template_child]
         pub cancel_button: TemplateChild<gtk::Button>,
         #[template_child]
+        pub loading_status: TemplateChild<adw::StatusPage>,
+        #[template_child]
         pub loading_spinner: TemplateChild<gtk::Spinner>,
         #[template_child]
+        pub loading_status_images: TemplateChild<adw::StatusPage>,
+        #[template_child]
         pub loading_spinner_images: TemplateChild<gtk::Spinner>,
         #[template_child]
         pub image_container: TemplateChild<gtk::FlowBox>,
@@ -122,12 +106,32 @@ mod imp {
         #[template_child]
         pub progress_bar: TemplateChild<gtk::ProgressBar>,
 
+        #[template_child]
+        pub preset_row: TemplateChild<adw::ComboRow>,
         #[template_child]
         pub output_filetype: TemplateChild<adw::ComboRow>,
         #[template_child]
-        pub output_compression: TemplateChild<adw::ActionRow>,
+        pub output_compression: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub name_template: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub conflict_policy: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub mirror_mode: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub delete_orphans: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub delete_orphans_value: TemplateChild<gtk::Switch>,
         #[template_child]
-        pub output_compression_value: TemplateChild<gtk::Switch>,
+        pub keep_folders: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub keep_folders_value: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub continue_on_error: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub continue_on_error_value: TemplateChild<gtk::Switch>,
+        #[template_child]
+        pub compression_level: TemplateChild<adw::SpinRow>,
         #[template_child]
         pub single_pdf: TemplateChild<adw::ActionRow>,
         #[template_child]
@@ -144,6 +148,8 @@ mod imp {
         #[template_child]
         pub resize_filter_row: TemplateChild<adw::ActionRow>,
         #[template_child]
+        pub metadata_policy: TemplateChild<adw::ComboRow>,
+        #[template_child]
         pub resize_amount_row: TemplateChild<adw::ActionRow>,
         #[template_child]
         pub resize_type: TemplateChild<gtk::DropDown>,
@@ -176,13 +182,19 @@ mod imp {
         pub input_file_store: gio::ListStore,
         #[derivative(Default(value = "gio::Settings::new(APP_ID)"))]
         pub settings: gio::Settings,
-        #[derivative(Default(value = "std::sync::Arc::new(AtomicBool::new(true))"))]
-        pub is_canceled: std::sync::Arc<AtomicBool>,
-        pub current_jobs: RefCell<Vec<Arc<SharedChild>>>,
+        pub scheduler: RefCell<Option<Scheduler>>,
+        pub conflict_report: RefCell<ConflictReport>,
         pub image_width: Cell<Option<u32>>,
         pub image_height: Cell<Option<u32>>,
         pub removed: RefCell<HashSet<u32>>,
         pub elements: Cell<usize>,
+        pub watcher: RefCell<Option<FolderWatcher>>,
+        /// One line per image handled by the watcher, newest first.
+        #[derivative(Default(value = "gtk::StringList::new(&[])"))]
+        pub watch_log: gtk::StringList,
+        /// Output chosen on a location without a local path, uploaded once
+        /// converted.
+        pub remote_output: RefCell<Option<RemoteOutput>>,
     }
 
     #[glib::object_subclass]
@@ -226,7 +238,7 @@ mod imp {
                 dbg!("Failed to save window state, {}", &err);
             }
 
-            if !self.is_canceled.load(std::sync::atomic::Ordering::SeqCst) {
+            if self.obj().is_converting() {
                 self.obj().close_dialog();
                 glib::Propagation::Stop
             } else {
@@ -286,6 +298,33 @@ impl AppWindow {
                     }
                 ))
                 .build(),
+            gio::ActionEntry::builder("add-folder")
+                .activate(clone!(
+                    #[weak(rename_to=window)]
+                    self,
+                    move |_, _, _| {
+                        window.add_folder_dialog();
+                    }
+                ))
+                .build(),
+            gio::ActionEntry::builder("folder-filter")
+                .activate(clone!(
+                    #[weak(rename_to=window)]
+                    self,
+                    move |_, _, _| {
+                        window.folder_filter_dialog();
+                    }
+                ))
+                .build(),
+            gio::ActionEntry::builder("watch-folder")
+                .activate(clone!(
+                    #[weak(rename_to=window)]
+                    self,
+                    move |_, _, _| {
+                        window.watch_folder_dialog();
+                    }
+                ))
+                .build(),
             gio::ActionEntry::builder("clear")
                 .activate(clone!(
                     #[weak(rename_to=window)]
@@ -322,6 +361,33 @@ impl AppWindow {
                     }
                 ))
                 .build(),
+            gio::ActionEntry::builder("preset-new")
+                .activate(clone!(
+                    #[weak(rename_to=window)]
+                    self,
+                    move |_, _, _| {
+                        window.preset_new_dialog();
+                    }
+                ))
+                .build(),
+            gio::ActionEntry::builder("preset-rename")
+                .activate(clone!(
+                    #[weak(rename_to=window)]
+                    self,
+                    move |_, _, _| {
+                        window.preset_rename_dialog();
+                    }
+                ))
+                .build(),
+            gio::ActionEntry::builder("preset-delete")
+                .activate(clone!(
+                    #[weak(rename_to=window)]
+                    self,
+                    move |_, _, _| {
+                        window.preset_delete_dialog();
+                    }
+                ))
+                .build(),
         ]);
     }
 
@@ -363,6 +429,24 @@ impl AppWindow {
                 this.convert_cancel();
             }
         ));
+        imp.name_template.connect_changed(clone!(
+            #[weak(rename_to=this)]
+            self,
+            move |row| match this.get_name_template_argument() {
+                Ok(_) => row.remove_css_class("error"),
+                Err(_) => row.add_css_class("error"),
+            }
+        ));
+        imp.preset_row.connect_selected_notify(clone!(
+            #[weak(rename_to=this)]
+            self,
+            move |_| {
+                if let Some(preset) = this.selected_preset() {
+                    this.apply_preset(&preset);
+                }
+                this.update_preset_actions();
+            }
+        ));
         imp.output_filetype.connect_selected_notify(clone!(
             #[weak(rename_to=this)]
             self,
@@ -372,6 +456,20 @@ impl AppWindow {
                 this.update_resize();
             }
         ));
+        imp.output_compression.connect_selected_notify(clone!(
+            #[weak(rename_to=this)]
+            self,
+            move |_| {
+                this.update_compression_options();
+            }
+        ));
+        imp.mirror_mode.connect_selected_notify(clone!(
+            #[weak(rename_to=this)]
+            self,
+            move |_| {
+                this.update_compression_options();
+            }
+        ));
         imp.single_pdf_value.connect_state_notify(clone!(
             #[weak(rename_to=this)]
             self,
@@ -519,6 +617,7 @@ impl AppWindow {
             )]);
         });
         self.load_options();
+        self.update_preset_options(None);
     }
 
     fn setup_drop_target(&self) {
@@ -539,8 +638,7 @@ impl AppWindow {
                         return false;
                     }
 
-                    let input_files = file_list.files().iter().map(InputFile::new).collect_vec();
-                    win.open_files(input_files);
+                    win.import_files(file_list.files());
                     return true;
                 }
 
@@ -576,19 +674,7 @@ impl AppWindow {
                 self,
                 move |_, response_id| {
                     if response_id == "stop" {
-                        this.imp()
-                            .is_canceled
-                            .store(true, std::sync::atomic::Ordering::SeqCst);
-                        let mut current_jobs = this.imp().current_jobs.borrow_mut();
-                        for x in current_jobs.iter() {
-                            match x.kill() {
-                                Ok(_) => {}
-                                Err(_) => {
-                                    x.wait().ok();
-                                }
-                            }
-                        }
-                        current_jobs.clear();
+                        this.stop_converting();
                         this.close();
                     }
                 }
@@ -597,6 +683,17 @@ impl AppWindow {
         stop_converting_dialog.present(Some(self));
     }
 
+    fn is_converting(&self) -> bool {
+        self.imp().scheduler.borrow().is_some()
+    }
+
+    /// Kills every running job of the current conversion, if any.
+    fn stop_converting(&self) {
+        if let Some(scheduler) = self.imp().scheduler.take() {
+            scheduler.cancel();
+        }
+    }
+
     fn set_convert_progress(&self, done: usize, total: usize) {
         let msg = format!("{done}/{total}");
         self.imp().progress_bar.set_text(Some(&msg));
@@ -610,6 +707,413 @@ impl AppWindow {
         self.imp().progress_bar.set_text(Some(&msg));
     }
 
+    /// Which files are imported from folders, as configured by the user.
+    pub fn folder_filter(&self) -> FolderFilter {
+        FolderFilter::load(&self.imp().settings)
+    }
+
+    /// Loads the images `files` hold without blocking the window, showing
+    /// how many were found so far.
+    pub async fn load_inputs(&self, files: Vec<gio::File>) -> (Vec<InputFile>, Vec<RejectedFile>) {
+        let progress = clone!(
+            #[weak(rename_to=this)]
+            self,
+            move |progress: ImportProgress| {
+                let description = match progress {
+                    ImportProgress::Found(found) => {
+                        ngettext("{} image found", "{} images found", found as u32)
+                            .replace("{}", &found.to_string())
+                    }
+                    ImportProgress::Downloading(done, Some(total)) => {
+                        gettext("Downloading {done} of {total}")
+                            .replace("{done}", &glib::format_size(done))
+                            .replace("{total}", &glib::format_size(total))
+                    }
+                    ImportProgress::Downloading(done, None) => {
+                        gettext("Downloading {}").replace("{}", &glib::format_size(done))
+                    }
+                };
+                this.imp()
+                    .loading_status
+                    .set_description(Some(&description));
+                this.imp()
+                    .loading_status_images
+                    .set_description(Some(&description));
+            }
+        );
+        let loaded = InputFile::load_all(files, self.folder_filter(), progress).await;
+        self.imp().loading_status.set_description(None);
+        self.imp().loading_status_images.set_description(None);
+        loaded
+    }
+
+    /// Uploads the next conversion saved to `output`'s local path to it.
+    pub fn set_remote_output(&self, output: Option<RemoteOutput>) {
+        self.imp().remote_output.replace(output);
+    }
+
+    fn folder_filter_dialog(&self) {
+        let filter = self.folder_filter();
+
+        let dialog = adw::AlertDialog::new(
+            Some(&gettext("Folder Import")),
+            Some(&gettext(
+                "Separate patterns with commas, such as “*.png, photos/**”. Patterns without “/” match file and folder names.",
+            )),
+        );
+
+        let include = adw::EntryRow::builder()
+            .title(gettext("Only Import"))
+            .text(filter.include.join(", "))
+            .build();
+        let exclude = adw::EntryRow::builder()
+            .title(gettext("Skip"))
+            .text(filter.exclude.join(", "))
+            .build();
+        let hidden = adw::SwitchRow::builder()
+            .title(gettext("Import Hidden Files"))
+            .active(filter.include_hidden)
+            .build();
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+        list.add_css_class("boxed-list");
+        list.append(&include);
+        list.append(&exclude);
+        list.append(&hidden);
+        dialog.set_extra_child(Some(&list));
+
+        dialog.add_response("cancel", &gettext("_Cancel"));
+        dialog.add_response("save", &gettext("_Save"));
+        dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+        dialog.connect_response(
+            None,
+            clone!(
+                #[weak(rename_to=this)]
+                self,
+                #[weak]
+                include,
+                #[weak]
+                exclude,
+                #[weak]
+                hidden,
+                move |_, response_id| {
+                    if response_id != "save" {
+                        return;
+                    }
+                    let filter = FolderFilter {
+                        include: FolderFilter::parse_patterns(&include.text()),
+                        exclude: FolderFilter::parse_patterns(&exclude.text()),
+                        include_hidden: hidden.is_active(),
+                    };
+                    if filter.save(&this.imp().settings).is_err() {
+                        this.show_toast(&gettext("Could not save folder import settings"));
+                    }
+                }
+            ),
+        );
+        dialog.present(Some(self));
+    }
+
+    fn watch_folder_dialog(&self) {
+        let imp = self.imp();
+        let watching = imp.watcher.borrow().is_some();
+
+        let dialog = adw::AlertDialog::new(
+            Some(&gettext("Watch Folder")),
+            Some(&gettext(
+                "Images added to the watched folder are converted with the chosen preset while this window is open.",
+            )),
+        );
+
+        let folder_row = |title: &str, key: &'static str| {
+            let row = adw::ActionRow::builder()
+                .title(title)
+                .subtitle(
+                    self.watch_folder(key)
+                        .map(|f| f.parse_name().to_string())
+                        .unwrap_or_default(),
+                )
+                .sensitive(!watching)
+                .build();
+            let button = gtk::Button::builder()
+                .icon_name("folder-open-symbolic")
+                .tooltip_text(gettext("Choose Folder"))
+                .valign(gtk::Align::Center)
+                .build();
+            button.add_css_class("flat");
+            button.connect_clicked(clone!(
+                #[weak(rename_to=this)]
+                self,
+                #[weak]
+                row,
+                move |_| {
+                    glib::spawn_future_local(async move {
+                        let dialog = gtk::FileDialog::builder().build();
+                        let Ok(folder) = dialog.select_folder_future(Some(&this)).await else {
+                            return;
+                        };
+                        // Stored as a URI, which holds any path.
+                        if folder.path().is_some() {
+                            this.imp().settings.set_string(key, &folder.uri()).ok();
+                            row.set_subtitle(&folder.parse_name());
+                        }
+                    });
+                }
+            ));
+            row.add_suffix(&button);
+            row.set_activatable_widget(Some(&button));
+            row
+        };
+        let source = folder_row(&gettext("Watched Folder"), "watch-source");
+        let destination = folder_row(&gettext("Save To"), "watch-destination");
+
+        let presets = Preset::load_all(&imp.settings);
+        let names = gtk::StringList::new(&[]);
+        for preset in presets.iter() {
+            names.append(&preset.name);
+        }
+        let preset = adw::ComboRow::builder()
+            .title(gettext("Preset"))
+            .model(&names)
+            .sensitive(!watching && !presets.is_empty())
+            .build();
+        let saved = imp.settings.string("watch-preset");
+        if let Some(index) = presets.iter().position(|p| p.name == saved) {
+            preset.set_selected(index as u32);
+        }
+        if presets.is_empty() {
+            preset.set_subtitle(&gettext("Save the current options as a preset first"));
+        }
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+        list.add_css_class("boxed-list");
+        list.append(&source);
+        list.append(&destination);
+        list.append(&preset);
+
+        let content = gtk::Box::new(gtk::Orientation::Vertical, 12);
+        content.append(&list);
+        if imp.watch_log.n_items() > 0 {
+            let log = gtk::ListBox::new();
+            log.set_selection_mode(gtk::SelectionMode::None);
+            log.add_css_class("boxed-list");
+            log.bind_model(Some(&imp.watch_log), |item| {
+                let line = item
+                    .downcast_ref::<gtk::StringObject>()
+                    .map(|s| s.string())
+                    .unwrap_or_default();
+                adw::ActionRow::builder()
+                    .title(line.as_str())
+                    .title_lines(2)
+                    .use_markup(false)
+                    .build()
+                    .upcast()
+            });
+            content.append(
+                &gtk::ScrolledWindow::builder()
+                    .child(&log)
+                    .hscrollbar_policy(gtk::PolicyType::Never)
+                    .max_content_height(240)
+                    .propagate_natural_height(true)
+                    .build(),
+            );
+        }
+        dialog.set_extra_child(Some(&content));
+
+        dialog.add_response("close", &gettext("_Close"));
+        if watching {
+            dialog.add_response("stop", &gettext("_Stop Watching"));
+            dialog.set_response_appearance("stop", adw::ResponseAppearance::Destructive);
+        } else {
+            dialog.add_response("start", &gettext("_Start Watching"));
+            dialog.set_response_appearance("start", adw::ResponseAppearance::Suggested);
+            dialog.set_response_enabled("start", !presets.is_empty());
+        }
+        dialog.connect_response(
+            None,
+            clone!(
+                #[weak(rename_to=this)]
+                self,
+                #[weak]
+                preset,
+                move |_, response_id| {
+                    match response_id {
+                        "stop" => {
+                            this.imp().watcher.take();
+                            this.show_toast(&gettext("Stopped watching"));
+                        }
+                        "start" => {
+                            let name = preset
+                                .selected_item()
+                                .and_downcast::<gtk::StringObject>()
+                                .map(|s| s.string())
+                                .unwrap_or_default();
+                            this.imp().settings.set_string("watch-preset", &name).ok();
+                            match Preset::find(&this.imp().settings, &name) {
+                                Some(preset) => {
+                                    let folder = |key: &str| {
+                                        this.watch_folder(key)
+                                            .and_then(|f| f.path())
+                                            .unwrap_or_default()
+                                    };
+                                    this.start_watching(
+                                        folder("watch-source"),
+                                        folder("watch-destination"),
+                                        &preset,
+                                    )
+                                }
+                                None => this.show_toast(&gettext("No preset chosen")),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            ),
+        );
+        dialog.present(Some(self));
+    }
+
+    /// The folder saved under the settings `key`.
+    fn watch_folder(&self, key: &str) -> Option<gio::File> {
+        let uri = self.imp().settings.string(key);
+        (!uri.is_empty()).then(|| gio::File::for_uri(&uri))
+    }
+
+    fn start_watching(&self, source: PathBuf, destination: PathBuf, preset: &Preset) {
+        let imp = self.imp();
+        if source.as_os_str().is_empty() || destination.as_os_str().is_empty() {
+            self.show_toast(&gettext("Choose a folder to watch and one to save to"));
+            return;
+        }
+
+        let template = match self.get_name_template_argument() {
+            Ok(template) => template,
+            Err(e) => {
+                self.show_toast(&e);
+                return;
+            }
+        };
+
+        let policy =
+            ConflictPolicy::from_index(imp.conflict_policy.selected() as usize).unwrap_or_default();
+        let options = WatchOptions {
+            source,
+            destination,
+            converter: Converter::new(preset.conversion_options(), template, false),
+            // Nobody is around to answer, so files are never replaced unasked.
+            conflict: policy.resolution().unwrap_or_default(),
+            jobs: imp.settings.int("max-parallel-jobs") as usize,
+        };
+        let watcher = FolderWatcher::start(
+            options,
+            clone!(
+                #[weak(rename_to=this)]
+                self,
+                move |event| this.log_watch_event(event)
+            ),
+        );
+        match watcher {
+            Ok(watcher) => {
+                let folder = watcher.source().to_string_lossy().into_owned();
+                imp.watcher.replace(Some(watcher));
+                self.show_toast(&gettext("Watching {}").replace("{}", &folder));
+            }
+            Err(e) => self.show_toast(&e),
+        }
+    }
+
+    fn log_watch_event(&self, event: WatchEvent) {
+        let time = glib::DateTime::now_local()
+            .and_then(|d| d.format("%H:%M:%S"))
+            .map(String::from)
+            .unwrap_or_default();
+        let line = match &event {
+            WatchEvent::Converted { input, outputs } if outputs.is_empty() => format!(
+                "{}: {}",
+                input.display(),
+                gettext("already exists, skipped")
+            ),
+            WatchEvent::Converted { input, outputs } => {
+                format!(
+                    "{} → {}",
+                    input.display(),
+                    outputs.iter().map(|o| o.display()).join(", ")
+                )
+            }
+            WatchEvent::Failed { input, error } => {
+                let name = input.file_name().unwrap_or_default().to_string_lossy();
+                self.show_toast(&gettext("Could not convert {}").replace("{}", &name));
+                format!("{}: {error}", input.display())
+            }
+        };
+        self.imp()
+            .watch_log
+            .splice(0, 0, &[format!("[{time}] {line}").as_str()]);
+    }
+
+    /// Lists the files that could not be imported and why.
+    pub fn show_rejected_files(&self, rejected: Vec<RejectedFile>) {
+        let dialog = adw::AlertDialog::new(
+            Some(&gettext("Some Files Could Not Be Opened")),
+            Some(
+                &ngettext(
+                    "{} file was not imported.",
+                    "{} files were not imported.",
+                    rejected.len() as u32,
+                )
+                .replace("{}", &rejected.len().to_string()),
+            ),
+        );
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+        list.add_css_class("boxed-list");
+        for file in rejected.iter() {
+            let name = Path::new(&file.path)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| file.path.clone());
+            let row = adw::ActionRow::builder()
+                .title(glib::markup_escape_text(&name))
+                .subtitle(glib::markup_escape_text(&file.error.to_string()))
+                .tooltip_text(&file.path)
+                .build();
+            list.append(&row);
+        }
+
+        let sw = gtk::ScrolledWindow::new();
+        sw.set_max_content_height(400);
+        sw.set_propagate_natural_height(true);
+        sw.set_child(Some(&list));
+        dialog.set_extra_child(Some(&sw));
+
+        let report = rejected
+            .iter()
+            .map(|f| format!("{}: {}", f.path, f.error))
+            .join("\n");
+
+        dialog.add_responses(&[
+            ("ok", &gettext("_Close")),
+            ("copy", &gettext("_Copy to Clipboard")),
+        ]);
+        dialog.connect_response(
+            None,
+            clone!(
+                #[weak(rename_to=this)]
+                self,
+                move |_, response_id| {
+                    if response_id == "copy" {
+                        this.clipboard().set_text(&report);
+                        this.show_toast(&gettext("Error copied to clipboard"));
+                    }
+                }
+            ),
+        );
+        dialog.present(Some(self));
+    }
+
     pub fn load_clipboard(&self) {
         let clipboard = self.clipboard();
         if clipboard.formats().contain_mime_type("image/png") {
@@ -621,9 +1125,10 @@ impl AppWindow {
                     if let Ok(Some(t)) = t {
                         let interim = JobFile::from_clipboard();
                         t.save_to_png(interim.as_filename()).ok();
-                        let file =
-                            InputFile::new(&gio::File::for_path(interim.as_filename())).unwrap();
-                        this.open_success(vec![file]);
+                        match InputFile::new(&gio::File::for_path(interim.as_filename())) {
+                            Ok(file) => this.open_success(vec![file]),
+                            Err(e) => this.show_toast(&e.to_string()),
+                        }
                     }
                 }
             ));
@@ -635,12 +1140,11 @@ impl AppWindow {
                 #[weak(rename_to=this)]
                 self,
                 async move {
-                    let t = clipboard.read_text_future().await.unwrap().unwrap();
-                    let files = t
-                        .lines()
-                        .flat_map(|p| InputFile::new(&gio::File::for_path(p)))
-                        .collect();
-                    this.open_success(files);
+                    let Ok(Some(t)) = clipboard.read_text_future().await else {
+                        this.show_toast(&gettext("Could not read the pasted files"));
+                        return;
+                    };
+                    this.import_files(t.lines().map(gio::File::for_path).collect_vec());
                 }
             ));
         }
@@ -672,14 +1176,14 @@ impl AppWindow {
 
     fn load_frames(&self) {
         let files = self.files();
-        let file_paths = files.iter().map(|f| f.path()).collect_vec();
+        let file_paths = files.iter().map(|f| (f.path(), f.kind())).collect_vec();
 
         let (sender, receiver) = async_channel::bounded(1);
 
         std::thread::spawn(move || {
             let jobs = file_paths
                 .into_iter()
-                .map(|f| async move { count_frames(f).await.unwrap_or((1, None)) })
+                .map(|(f, kind)| async move { backend::probe(f, kind).await.unwrap_or_default() })
                 .collect_vec();
 
             let res = runtime().block_on(join_all(jobs));
@@ -693,19 +1197,8 @@ impl AppWindow {
             async move {
                 if let Ok(image_info) = receiver.recv().await {
                     let real_files = files.clone();
-                    for (f, (frame, dims)) in real_files.iter().zip(image_info.iter()) {
-                        f.set_frames(*frame);
-                        let dims = *dims;
-                        idle_add_local_once(clone!(
-                            #[weak(rename_to=ff)]
-                            f,
-                            move || {
-                                if let Some((width, height)) = dims {
-                                    ff.set_width(width);
-                                    ff.set_height(height);
-                                }
-                            }
-                        ));
+                    for (f, info) in real_files.iter().zip(image_info) {
+                        f.set_info(info);
                         glib::MainContext::default().iteration(true);
                     }
                     idle_add_local_once(clone!(
@@ -839,12 +1332,31 @@ impl AppWindow {
         }
     }
 
+    /// Formats the output can be converted to with what is installed.
+    fn available_outputs() -> impl Iterator<Item = FileType> {
+        FileType::output_formats()
+            .copied()
+            .filter(|&f| backend::can_write(f))
+    }
+
+    fn select_output(&self, output: FileType) {
+        if let Some(index) = Self::available_outputs().position(|f| f == output) {
+            self.imp().output_filetype.set_selected(index as u32);
+        }
+    }
+
+    fn select_compression(&self, compression: CompressionType) {
+        if let Some(index) = CompressionType::possible_output(false).position(|&c| c == compression)
+        {
+            self.imp().output_compression.set_selected(index as u32);
+        }
+    }
+
     fn selected_compression(&self) -> Option<CompressionType> {
         match self.imp().output_compression.is_visible() {
-            true => match self.imp().output_compression_value.is_active() {
-                true => Some(CompressionType::Zip),
-                false => Some(CompressionType::Directory),
-            },
+            true => CompressionType::possible_output(false)
+                .nth(self.imp().output_compression.selected() as usize)
+                .copied(),
             false => None,
         }
     }
@@ -931,237 +1443,359 @@ impl AppWindow {
         ));
     }
 
-    fn convert_start(&self, save_format: OutputType, path: String) {
-        use FileType::*;
+    fn convert_start(&self, save_format: OutputType, path: PathBuf) {
+        self.convert_files(self.active_files(), save_format, path);
+    }
 
-        self.imp().convert_button.set_sensitive(false);
-        self.imp().progress_bar.set_text(Some(&gettext("Loading…")));
-        self.imp().progress_bar.set_fraction(0.0);
-        self.imp()
-            .is_canceled
-            .store(false, std::sync::atomic::Ordering::SeqCst);
-        self.imp().current_jobs.replace(vec![]);
+    /// Converts `files` again into the same folder, or asks for a new
+    /// destination when the previous one was a single file or archive.
+    fn retry_failed(&self, files: Vec<InputFile>, save_format: OutputType, path: &Path) {
+        if save_format == OutputType::Compression(CompressionType::Directory) {
+            self.convert_files(files, save_format, path.to_owned());
+            return;
+        }
+
+        let default_folder = path.parent().map(Path::to_owned).unwrap_or_default();
+        let default_name = format!("images.{}", save_format.as_extension());
+        FileChooser::choose_output_file_wrapper(
+            self,
+            default_name,
+            save_format,
+            default_folder,
+            move |this: &AppWindow, save_format, path| {
+                this.convert_files(files.clone(), save_format, path)
+            },
+            AppWindow::save_error,
+        );
+    }
+
+    fn convert_files(&self, files: Vec<InputFile>, save_format: OutputType, path: PathBuf) {
+        self.imp().conflict_report.take();
+        self.imp().convert_button.set_sensitive(false);
+        self.imp().progress_bar.set_text(Some(&gettext("Loading…")));
+        self.imp().progress_bar.set_fraction(0.0);
         self.save_options().ok();
         self.save_selected_output().ok();
         self.save_selected_compression().ok();
 
-        let output_type = self.selected_output().unwrap();
-
-        let files = self.active_files();
-
-        let dir = runtime().block_on(create_temporary_dir()).unwrap();
-
-        let job_input = files
-            .into_iter()
-            .map(|f| {
-                let stem = Path::new(&f.path())
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_owned();
-                (f, stem)
-            })
-            .sorted_by_key(|(_, s)| s.to_owned())
-            .scan(HashSet::new(), |s, (f, stem)| {
-                let stem = if s.contains(&stem) {
-                    let mut i = 1;
-                    while s.contains(&format!("{stem}_{i}")) {
-                        i += 1;
-                    }
-                    format!("{stem}_{i}")
-                } else {
-                    stem
-                };
-                s.insert(stem.clone());
-                Some((f, stem))
-            })
-            .flat_map(|(f, output_stem)| {
-                let (path, input_filetype, frames) = (f.path(), f.kind(), f.frames());
-                match (input_filetype, output_type, frames) {
-                    (_, _, 0) => unreachable!("an image cannot have zero frames"),
-                    (Pdf, _, c) => (0..c)
-                        .map(|f| {
-                            (
-                                format!("{path}[{f}]"),
-                                input_filetype,
-                                format!("{output_stem}[{f}].{}", output_type.as_extension()),
-                            )
-                        })
-                        .collect_vec(),
-                    (_, _, 1) => vec![(
-                        format!("{path}[0]"),
-                        input_filetype,
-                        format!("{output_stem}.{}", output_type.as_extension()),
-                    )],
-                    (input, output, _)
-                        if input.supports_animation() && output.supports_animation() =>
-                    {
-                        vec![(
-                            path,
-                            input_filetype,
-                            format!("{output_stem}.{}", output_type.as_extension()),
-                        )]
-                    }
-                    (input, _, count) if input.supports_animation() => (0..count)
-                        .map(|f| {
-                            (
-                                format!("{path}[{f}]"),
-                                input_filetype,
-                                format!("{output_stem}[{f}].{}", output_type.as_extension()),
-                            )
-                        })
-                        .collect_vec(),
-                    _ => vec![(
-                        format!("{path}[0]"),
-                        input_filetype,
-                        format!("{output_stem}.{}", output_type.as_extension()),
-                    )],
-                }
+        let inputs = files
+            .iter()
+            .map(|f| ConversionInput {
+                path: f.path(),
+                kind: f.kind(),
+                frames: f.frames(),
+                dimensions: f.dimensions(),
+                has_alpha: f.has_alpha(),
+                relative_dir: f.relative_dir(),
+                temporary: f.is_temporary(),
             })
             .collect_vec();
 
-        dbg!(&job_input);
+        let Some(output_type) = self.selected_output() else {
+            self.imp().convert_button.set_sensitive(true);
+            return;
+        };
+        let template = match self.get_name_template_argument() {
+            Ok(template) => template,
+            Err(e) => {
+                self.imp().convert_button.set_sensitive(true);
+                self.show_toast(&e);
+                return;
+            }
+        };
+        let converter = Converter::new(
+            ConversionOptions {
+                output_type,
+                background: self.get_bgcolor_argument(),
+                quality: self.get_quality_argument(),
+                filter: self.get_filter_argument(),
+                resize_arg: self.get_resize_argument(),
+                dpi: self.get_dpi_argument(),
+                metadata: self.get_metadata_argument(),
+            },
+            template,
+            self.imp().keep_folders.is_visible() && self.imp().keep_folders_value.is_active(),
+        );
 
-        let output_files = job_input
-            .iter()
-            .map(|(_, _, o)| {
-                get_temp_file_path(&dir, JobFile::new(output_type, Some(o.to_string())))
-                    .to_str()
-                    .unwrap()
-                    .to_owned()
-            })
-            .collect_vec();
+        let plan = runtime()
+            .block_on(create_temporary_dir())
+            .map_err(|e| e.to_string())
+            .and_then(|dir| Ok((converter.plan(&inputs, &dir)?, dir)));
+        let (mut plan, temp_dir) = match plan {
+            Ok((plan, dir)) => (plan, Rc::new(dir)),
+            Err(e) => {
+                self.imp().convert_button.set_sensitive(true);
+                self.show_error(e);
+                return;
+            }
+        };
 
-        dbg!(&output_files);
+        debug!("{plan:?}");
 
-        let magick_arguments = MagickConvertJob {
-            input_file: "".to_string(),
-            output_file: "".to_string(),
-            background: self.get_bgcolor_argument(),
-            quality: self.get_quality_argument(),
-            filter: self.get_filter_argument(),
-            resize_arg: self.get_resize_argument(),
-            density: None,
-            first_frame: false,
-            remove_alpha: false,
+        let mirror_mode = match save_format {
+            OutputType::Compression(CompressionType::Directory) => self.selected_mirror_mode(),
+            _ => MirrorMode::Off,
         };
+        let delete_orphans = self.imp().delete_orphans_value.is_active();
+        if mirror_mode == MirrorMode::Off {
+            self.resolve_conflicts(files, plan, save_format, path, temp_dir, mirror_mode);
+            return;
+        }
 
-        let magick_jobs = job_input
-            .into_iter()
-            .map(|(f, ft, os)| {
-                generate_job(
-                    &f,
-                    &ft,
-                    get_temp_file_path(&dir, JobFile::new(output_type, Some(os)))
-                        .to_str()
-                        .unwrap(),
-                    &output_type,
-                    self.get_dpi_argument(),
-                    &magick_arguments,
-                )
-            })
-            .collect_vec();
+        // Hashing the inputs and deleting orphans may take a while.
+        glib::spawn_future_local(clone!(
+            #[weak(rename_to=this)]
+            self,
+            async move {
+                let destination = path.clone();
+                let result = gio::spawn_blocking(move || {
+                    let report = plan.mirror(&destination, mirror_mode, delete_orphans);
+                    report.map(|report| (plan, report))
+                })
+                .await
+                .unwrap_or_else(|_| Err(gettext("Unknown IO error happened")));
+                match result {
+                    Ok((plan, report)) => {
+                        this.show_mirror_report(&report);
+                        this.resolve_conflicts(
+                            files,
+                            plan,
+                            save_format,
+                            path,
+                            temp_dir,
+                            mirror_mode,
+                        );
+                    }
+                    Err(e) => {
+                        this.convert_clean(temp_dir);
+                        this.show_toast(&e);
+                    }
+                }
+            }
+        ));
+    }
 
-        let (sender, receiver) = async_channel::bounded(1);
+    /// Settles the outputs that already exist in the destination folder
+    /// according to the conflict policy, then runs `plan`.
+    fn resolve_conflicts(
+        &self,
+        files: Vec<InputFile>,
+        mut plan: ConversionPlan,
+        save_format: OutputType,
+        path: PathBuf,
+        temp_dir: Rc<TempDir>,
+        mirror_mode: MirrorMode,
+    ) {
+        let conflicts = match save_format {
+            OutputType::Compression(CompressionType::Directory)
+                if mirror_mode == MirrorMode::Off =>
+            {
+                plan.conflicts(Path::new(&path))
+            }
+            _ => vec![],
+        };
+        if conflicts.is_empty() {
+            self.run_plan(files, plan, save_format, path, temp_dir);
+            return;
+        }
 
-        let count = magick_jobs.iter().map(|mjs| mjs.len()).sum();
+        let policy = ConflictPolicy::from_index(self.imp().conflict_policy.selected() as usize)
+            .unwrap_or_default();
+        if let Some(resolution) = policy.resolution() {
+            let resolutions = conflicts
+                .into_iter()
+                .map(|i| (i, resolution.clone()))
+                .collect_vec();
+            let report = plan.resolve(Path::new(&path), &resolutions);
+            self.imp().conflict_report.replace(report);
+            self.run_plan(files, plan, save_format, path, temp_dir);
+            return;
+        }
 
-        let completed = std::sync::Arc::new(AtomicUsize::new(0));
+        glib::spawn_future_local(clone!(
+            #[weak(rename_to=this)]
+            self,
+            async move {
+                let names = conflicts
+                    .iter()
+                    .map(|i| plan.jobs[*i].output_name.display().to_string())
+                    .collect_vec();
+                let Some(chosen) = this.ask_conflicts(names).await else {
+                    this.convert_clean(temp_dir);
+                    return;
+                };
+                let resolutions = conflicts.into_iter().zip(chosen).collect_vec();
+                let report = plan.resolve(Path::new(&path), &resolutions);
+                this.imp().conflict_report.replace(report);
+                this.run_plan(files, plan, save_format, path, temp_dir);
+            }
+        ));
+    }
 
-        let stop_flag = self.imp().is_canceled.clone();
-        let stop_flag_s = stop_flag.clone();
+    fn selected_mirror_mode(&self) -> MirrorMode {
+        MirrorMode::from_index(self.imp().mirror_mode.selected() as usize).unwrap_or_default()
+    }
 
-        std::thread::spawn(move || {
-            let stop_flag = stop_flag_s.clone();
+    fn show_mirror_report(&self, report: &MirrorReport) {
+        if report.is_empty() {
+            return;
+        }
 
-            let jobs = magick_jobs
-                .into_iter()
-                .map(|mjs| {
-                    let stop_flag = stop_flag.clone();
-                    let sender = sender.clone();
-                    async move {
-                        for mut mj_command in mjs.into_iter().map(|mj| mj.get_command()) {
-                            if stop_flag.load(std::sync::atomic::Ordering::SeqCst) {
-                                return;
-                            }
+        let up_to_date = report.up_to_date.len();
+        let removed = report.removed.len();
+        let title = match (up_to_date, removed) {
+            (up_to_date, 0) => ngettext(
+                "{} image already up to date",
+                "{} images already up to date",
+                up_to_date as u32,
+            )
+            .replace("{}", &up_to_date.to_string()),
+            (0, removed) => ngettext(
+                "{} output of a deleted image removed",
+                "{} outputs of deleted images removed",
+                removed as u32,
+            )
+            .replace("{}", &removed.to_string()),
+            (up_to_date, removed) => gettext("{up_to_date} images up to date, {removed} removed")
+                .replace("{up_to_date}", &up_to_date.to_string())
+                .replace("{removed}", &removed.to_string()),
+        };
+        self.show_toast(&title);
+    }
 
-                            let mj_command_str =
-                                mj_command.get_program().to_str().unwrap().to_string();
-
-                            let shared_child = match SharedChild::spawn(&mut mj_command) {
-                                std::io::Result::Ok(shared_child) => shared_child,
-                                std::io::Result::Err(e) => {
-                                    sender
-                                        .send_blocking(ArcOrOptionError::OptionError(Some(
-                                            mj_command_str + ": " + &e.to_string(),
-                                        )))
-                                        .expect("Concurrency Issues");
-                                    return;
-                                }
-                            };
+    /// Asks what to do with each of the existing files in `names`, or returns
+    /// `None` if the user canceled the conversion.
+    async fn ask_conflicts(&self, names: Vec<String>) -> Option<Vec<ConflictResolution>> {
+        let mut resolutions = vec![];
+        let mut for_all = None;
 
-                            if stop_flag.load(std::sync::atomic::Ordering::SeqCst) {
-                                return;
-                            }
-                            let child_arc = std::sync::Arc::new(shared_child);
-                            sender
-                                .send_blocking(ArcOrOptionError::Child(child_arc.clone()))
-                                .expect("Concurrency Issues");
-                            let output = wait_for_child(child_arc.clone()).err();
-                            if stop_flag.load(std::sync::atomic::Ordering::SeqCst) {
-                                return;
-                            }
+        for (i, name) in names.iter().enumerate() {
+            if let Some(resolution) = &for_all {
+                resolutions.push(resolution.clone());
+                continue;
+            }
 
-                            sender
-                                .send_blocking(ArcOrOptionError::OptionError(output))
-                                .expect("Concurrency Issues");
-                        }
-                    }
-                })
-                .collect_vec();
+            let dialog = adw::AlertDialog::new(
+                Some(&gettext("Replace “{}”?").replace("{}", name)),
+                Some(&gettext(
+                    "A file with the same name already exists in the destination folder.",
+                )),
+            );
+            dialog.add_responses(&[
+                ("cancel", &gettext("_Cancel")),
+                ("skip", &gettext("_Skip")),
+                ("rename", &gettext("_Keep Both")),
+                ("overwrite", &gettext("_Replace")),
+            ]);
+            dialog.set_response_appearance("overwrite", adw::ResponseAppearance::Destructive);
+            dialog.set_default_response(Some("rename"));
+            dialog.set_close_response("cancel");
+
+            let remaining = names.len() - i - 1;
+            let apply_to_all = gtk::CheckButton::with_label(
+                &ngettext(
+                    "Do the same for the next conflict",
+                    "Do the same for the next {} conflicts",
+                    remaining as u32,
+                )
+                .replace("{}", &remaining.to_string()),
+            );
+            apply_to_all.set_halign(gtk::Align::Center);
+            if remaining > 0 {
+                dialog.set_extra_child(Some(&apply_to_all));
+            }
 
-            runtime().block_on(join_all(jobs));
-        });
+            let resolution = match dialog.choose_future(Some(self)).await.as_str() {
+                "skip" => ConflictResolution::Skip,
+                "rename" => ConflictResolution::Rename,
+                "overwrite" => ConflictResolution::Overwrite,
+                _ => return None,
+            };
+            if apply_to_all.is_active() {
+                for_all = Some(resolution.clone());
+            }
+            resolutions.push(resolution);
+        }
+
+        Some(resolutions)
+    }
 
-        let dir_path = dir.path().to_str().unwrap().to_string();
+    fn run_plan(
+        &self,
+        files: Vec<InputFile>,
+        plan: ConversionPlan,
+        save_format: OutputType,
+        path: PathBuf,
+        temp_dir: Rc<TempDir>,
+    ) {
+        if plan.jobs.is_empty() {
+            self.convert_clean(temp_dir);
+            self.show_conflict_report();
+            return;
+        }
 
-        std::mem::forget(dir);
+        let count = plan.command_count();
+        let mut completed = 0;
+        let mut remaining = plan.jobs.len();
+        let continue_on_error = self.imp().continue_on_error_value.is_active();
+        let mut failed_jobs = HashSet::new();
+        let mut failures: Vec<(InputFile, String)> = vec![];
 
-        let stop_flag_r = stop_flag;
+        let scheduler = Scheduler::new(self.imp().settings.int("max-parallel-jobs") as usize);
+        let jobs: HashMap<JobId, usize> = plan
+            .jobs
+            .iter()
+            .enumerate()
+            .map(|(i, job)| {
+                let handle = scheduler.submit(Priority::Normal, backend::steps(&job.commands));
+                (handle.id, i)
+            })
+            .collect();
+        let events = scheduler.events();
+        self.imp().scheduler.replace(Some(scheduler));
 
         glib::spawn_future_local(clone!(
             #[weak(rename_to=this)]
             self,
             async move {
-                while let Ok(e) = receiver.recv().await {
-                    match e {
-                        ArcOrOptionError::Child(c) => {
-                            if stop_flag_r.load(std::sync::atomic::Ordering::SeqCst) {
-                                match c.kill() {
-                                    Ok(_) => {}
-                                    Err(_) => {
-                                        c.wait().ok();
-                                    }
-                                }
-                            } else {
-                                this.imp().current_jobs.borrow_mut().push(c);
-                            }
+                while let Ok(event) = events.recv().await {
+                    // The conversion was stopped, ignore what is left over.
+                    if events.is_closed() {
+                        break;
+                    }
+                    match event {
+                        JobEvent::Progress(_) => {
+                            completed += 1;
+                            this.set_convert_progress(completed, count);
                         }
-                        ArcOrOptionError::OptionError(e) => {
-                            if let Some(e) = e {
-                                this.convert_failed(e, dir_path.clone());
-                                break;
+                        JobEvent::Done(id, result) => {
+                            remaining -= 1;
+                            if let Err(e) = result {
+                                if !continue_on_error {
+                                    this.convert_failed(e, temp_dir.clone());
+                                    break;
+                                }
+                                let job = jobs[&id];
+                                failed_jobs.insert(job);
+                                failures.push((files[plan.jobs[job].input].clone(), e));
                             }
-                            let c = completed.clone();
-                            let x = c.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                            this.set_convert_progress(x + 1, count);
-                            if x + 1 == count {
+                            if remaining == 0 {
+                                let succeeded = ConversionPlan {
+                                    jobs: plan
+                                        .jobs
+                                        .iter()
+                                        .enumerate()
+                                        .filter(|(i, _)| !failed_jobs.contains(i))
+                                        .map(|(_, job)| job.clone())
+                                        .collect(),
+                                    mirror: plan.mirror.clone(),
+                                };
                                 this.move_output(
                                     save_format,
                                     path.clone(),
-                                    output_files.clone(),
-                                    dir_path.clone(),
+                                    succeeded,
+                                    failures.clone(),
+                                    temp_dir.clone(),
                                 );
                                 break;
                             }
@@ -1177,7 +1811,9 @@ impl AppWindow {
 
 pub trait FileOperations {
     fn add_dialog(&self);
-    fn open_files(&self, files: Vec<Option<InputFile>>);
+    fn add_folder_dialog(&self);
+    fn open_files(&self, files: Vec<InputFile>, rejected: Vec<RejectedFile>);
+    fn import_files(&self, files: Vec<gio::File>);
     fn save_error(&self, error: Option<&str>);
     fn save_files(&self);
     fn open_load(&self);
@@ -1212,23 +1848,56 @@ trait ConvertArguments {
     fn get_dpi_argument(&self) -> usize;
     fn get_bgcolor_argument(&self) -> Color;
     fn get_filter_argument(&self) -> Option<ResizeFilter>;
+    fn get_metadata_argument(&self) -> MetadataPolicy;
     fn get_resize_argument(&self) -> ResizeArgument;
+    fn get_name_template_argument(&self) -> Result<NameTemplate, String>;
 }
 trait ConvertOperations {
-    fn convert_start_wrapper(&self, save_format: OutputType, path: String);
+    fn convert_start_wrapper(&self, save_format: OutputType, path: PathBuf);
     fn move_output(
         &self,
         save_format: OutputType,
-        path: String,
-        output_files: Vec<String>,
-        dir_path: String,
+        path: PathBuf,
+        plan: ConversionPlan,
+        failures: Vec<(InputFile, String)>,
+        temp_dir: Rc<TempDir>,
+    );
+    fn convert_failed(&self, error_message: String, temp_dir: Rc<TempDir>);
+    /// Shows why the conversion could not be done, and goes back to the
+    /// options.
+    fn show_error(&self, error_message: String);
+    fn convert_success(
+        &self,
+        temp_dir: Rc<TempDir>,
+        path: PathBuf,
+        save_format: OutputType,
+        failures: Vec<(InputFile, String)>,
     );
-    fn convert_failed(&self, error_message: String, temp_dir_path: String);
-    fn convert_success(&self, temp_dir_path: String, path: String, save_format: OutputType);
-    fn convert_clean(&self, temp_dir_path: String);
+    fn show_failure_report(
+        &self,
+        failures: Vec<(InputFile, String)>,
+        save_format: OutputType,
+        path: PathBuf,
+    );
+    fn show_conflict_report(&self);
+    fn convert_clean(&self, temp_dir: Rc<TempDir>);
     fn convert_cancel(&self);
 }
 
+trait PresetOperations {
+    fn update_preset_options(&self, selected: Option<&str>);
+    fn update_preset_actions(&self);
+    fn selected_preset(&self) -> Option<Preset>;
+    fn current_preset(&self, name: String) -> Preset;
+    fn apply_preset(&self, preset: &Preset);
+    fn preset_name_dialog<F>(&self, heading: &str, name: &str, callback: F)
+    where
+        F: Fn(&AppWindow, String) + 'static;
+    fn preset_new_dialog(&self);
+    fn preset_rename_dialog(&self);
+    fn preset_delete_dialog(&self);
+}
+
 trait SettingsStore {
     fn save_window_size(&self) -> Result<(), glib::BoolError>;
     fn load_window_size(&self);
@@ -1241,220 +1910,99 @@ trait SettingsStore {
 }
 
 impl ConvertOperations for AppWindow {
-    fn convert_start_wrapper(&self, save_format: OutputType, path: String) {
+    fn convert_start_wrapper(&self, save_format: OutputType, path: PathBuf) {
         self.convert_start(save_format, path);
     }
 
     fn move_output(
         &self,
         save_format: OutputType,
-        path: String,
-        output_files: Vec<String>,
-        dir_path: String,
+        path: PathBuf,
+        plan: ConversionPlan,
+        failures: Vec<(InputFile, String)>,
+        temp_dir: Rc<TempDir>,
     ) {
-        let stop_flag = self.imp().is_canceled.clone();
-        if stop_flag.load(std::sync::atomic::Ordering::SeqCst) {
+        if plan.jobs.is_empty() {
+            self.convert_clean(temp_dir);
+            self.imp().scheduler.take();
+            self.switch_to_stack_convert();
+            self.show_failure_report(failures, save_format, path);
             return;
         }
 
-        let path_r = path.clone();
-
         self.set_collecting_progress();
-        let receiver = match save_format {
-            OutputType::File(FileType::Pdf) if output_files.len() > 1 => {
-                let (sender, receiver) = async_channel::bounded(1);
-
-                std::thread::spawn(move || {
-                    let shared_child: SharedChild = match SharedChild::spawn(
-                        std::process::Command::new("magick")
-                            .stdout(std::process::Stdio::piped())
-                            .stderr(std::process::Stdio::piped())
-                            .args(output_files)
-                            .arg(path),
-                    ) {
-                        Ok(shared_child) => shared_child,
-                        Err(e) => {
-                            sender
-                                .send_blocking(ArcOrOptionError::OptionError(Some(
-                                    "magick: ".to_string() + &e.to_string(),
-                                )))
-                                .expect("Concurrency Issues");
-                            return;
-                        }
-                    };
-                    let child_arc = std::sync::Arc::new(shared_child);
-
-                    sender
-                        .send_blocking(ArcOrOptionError::Child(child_arc.clone()))
-                        .expect("Concurrency Issues");
-
-                    sender
-                        .send_blocking(ArcOrOptionError::OptionError(
-                            wait_for_child(child_arc).err(),
-                        ))
-                        .expect("Concurrency Issues");
-                });
-
-                receiver
-            }
-            OutputType::File(_) => {
-                let file = output_files.first().unwrap().to_owned();
-
-                let (sender, receiver) = async_channel::bounded(1);
-
-                std::thread::spawn(move || {
-                    let shared_child: SharedChild = SharedChild::spawn(
-                        std::process::Command::new("mv")
-                            .stdout(std::process::Stdio::piped())
-                            .stderr(std::process::Stdio::piped())
-                            .arg(file)
-                            .arg(path),
-                    )
-                    .unwrap();
-                    let child_arc = std::sync::Arc::new(shared_child);
-
-                    sender
-                        .send_blocking(ArcOrOptionError::Child(child_arc.clone()))
-                        .expect("Concurrency Issues");
-
-                    sender
-                        .send_blocking(ArcOrOptionError::OptionError(
-                            wait_for_child(child_arc).err(),
-                        ))
-                        .expect("Concurrency Issues");
-                });
-
-                receiver
-            }
-            OutputType::Compression(CompressionType::Directory) => {
-                let (sender, receiver) = async_channel::bounded(1);
-
-                std::thread::spawn(move || {
-                    let shared_child: SharedChild = SharedChild::spawn(
-                        std::process::Command::new("mv")
-                            .stdout(std::process::Stdio::piped())
-                            .stderr(std::process::Stdio::piped())
-                            .args(output_files)
-                            .arg(path),
-                    )
-                    .unwrap();
-                    let child_arc = std::sync::Arc::new(shared_child);
-
-                    sender
-                        .send_blocking(ArcOrOptionError::Child(child_arc.clone()))
-                        .expect("Concurrency Issues");
-
-                    sender
-                        .send_blocking(ArcOrOptionError::OptionError(
-                            wait_for_child(child_arc).err(),
-                        ))
-                        .expect("Concurrency Issues");
-                });
 
-                receiver
-            }
-            _ => {
-                let (sender, receiver) = async_channel::bounded(1);
-
-                std::thread::spawn(move || {
-                    let shared_child = match SharedChild::spawn(
-                        std::process::Command::new(ZIP_BINARY_NAME)
-                            .arg("-jFSm0")
-                            .arg(path)
-                            .args(output_files)
-                            .stdout(std::process::Stdio::piped())
-                            .stderr(std::process::Stdio::piped()),
-                    ) {
-                        Ok(shared_child) => shared_child,
-                        Err(e) => {
-                            sender
-                                .send_blocking(ArcOrOptionError::OptionError(Some(
-                                    ZIP_BINARY_NAME.to_string() + ": " + &e.to_string(),
-                                )))
-                                .expect("Concurrency Issues");
-                            return;
-                        }
-                    };
-
-                    let child_arc = std::sync::Arc::new(shared_child);
-
-                    sender
-                        .send_blocking(ArcOrOptionError::Child(child_arc.clone()))
-                        .expect("Concurrency Issues");
-
-                    sender
-                        .send_blocking(ArcOrOptionError::OptionError(
-                            wait_for_child(child_arc).err(),
-                        ))
-                        .expect("Concurrency Issues");
-                });
+        let Some(command) = plan.merge_command(save_format, &path) else {
+            let compression_level = self.imp().compression_level.value() as u32;
+            glib::spawn_future_local(clone!(
+                #[weak(rename_to=this)]
+                self,
+                async move {
+                    let destination = path.clone();
+                    let result = gio::spawn_blocking(move || {
+                        plan.collect(save_format, Path::new(&destination), compression_level)
+                    })
+                    .await
+                    .unwrap_or_else(|_| Err(gettext("Unknown IO error happened")));
+
+                    // The conversion was stopped while collecting.
+                    if !this.is_converting() {
+                        return;
+                    }
+                    match result {
+                        Err(e) => this.convert_failed(e, temp_dir),
+                        Ok(()) => this.convert_success(temp_dir, path, save_format, failures),
+                    }
+                }
+            ));
+            return;
+        };
 
-                receiver
-            }
+        let (handle, events) = match self.imp().scheduler.borrow().as_ref() {
+            Some(scheduler) => (
+                scheduler.submit(Priority::High, vec![Step::Command(command)]),
+                scheduler.events(),
+            ),
+            None => return,
         };
 
         glib::spawn_future_local(clone!(
             #[weak(rename_to=this)]
             self,
             async move {
-                while let Ok(x) = receiver.recv().await {
-                    match x {
-                        ArcOrOptionError::Child(c) => {
-                            if this
-                                .imp()
-                                .is_canceled
-                                .load(std::sync::atomic::Ordering::SeqCst)
-                            {
-                                match c.kill() {
-                                    Ok(_) => {}
-                                    Err(_) => {
-                                        c.wait().ok();
-                                    }
-                                }
-                            } else {
-                                this.imp().current_jobs.borrow_mut().push(c);
-                            }
-                        }
-                        ArcOrOptionError::OptionError(x) => {
-                            match x {
-                                Some(e) => this.convert_failed(e, dir_path.clone()),
-                                None => this.convert_success(
-                                    dir_path.clone(),
-                                    path_r.clone(),
-                                    save_format,
-                                ),
-                            }
-                            break;
+                while let Ok(event) = events.recv().await {
+                    if events.is_closed() {
+                        break;
+                    }
+                    if let JobEvent::Done(id, result) = event
+                        && id == handle.id
+                    {
+                        match result {
+                            Err(e) => this.convert_failed(e, temp_dir.clone()),
+                            Ok(()) => this.convert_success(
+                                temp_dir.clone(),
+                                path.clone(),
+                                save_format,
+                                failures.clone(),
+                            ),
                         }
-                    };
+                        break;
+                    }
                 }
             }
         ));
     }
 
-    fn convert_failed(&self, error_message: String, temp_dir_path: String) {
-        self.convert_clean(temp_dir_path);
-        if self
-            .imp()
-            .is_canceled
-            .load(std::sync::atomic::Ordering::SeqCst)
-        {
+    fn convert_failed(&self, error_message: String, temp_dir: Rc<TempDir>) {
+        self.convert_clean(temp_dir);
+        if !self.is_converting() {
             return;
         }
-        let mut current_jobs = self.imp().current_jobs.borrow_mut();
-        self.imp()
-            .is_canceled
-            .store(true, std::sync::atomic::Ordering::SeqCst);
-        for x in current_jobs.iter() {
-            match x.kill() {
-                Ok(_) => {}
-                Err(_) => {
-                    x.wait().ok();
-                }
-            }
-        }
-        current_jobs.clear();
+        self.stop_converting();
+        self.show_error(error_message);
+    }
 
+    fn show_error(&self, error_message: String) {
         let dialog = adw::AlertDialog::new(Some(&gettext("Error While Processing")), None);
 
         let sw = gtk::ScrolledWindow::new();
@@ -1499,39 +2047,239 @@ impl ConvertOperations for AppWindow {
         self.switch_to_stack_convert();
     }
 
-    fn convert_success(&self, temp_dir_path: String, path: String, save_format: OutputType) {
-        self.convert_clean(temp_dir_path);
-        self.imp()
-            .is_canceled
-            .store(true, std::sync::atomic::Ordering::SeqCst);
+    fn convert_success(
+        &self,
+        temp_dir: Rc<TempDir>,
+        path: PathBuf,
+        save_format: OutputType,
+        failures: Vec<(InputFile, String)>,
+    ) {
+        let remote_output = self
+            .imp()
+            .remote_output
+            .take()
+            .filter(|o| o.local_path() == Path::new(&path));
+        if let Some(output) = remote_output {
+            self.set_collecting_progress();
+            glib::spawn_future_local(clone!(
+                #[weak(rename_to=this)]
+                self,
+                async move {
+                    let uri = output.uri();
+                    let result = output.upload().await;
+                    // The conversion was stopped while uploading.
+                    if !this.is_converting() {
+                        return;
+                    }
+                    match result {
+                        Ok(()) => this.convert_success(
+                            temp_dir,
+                            PathBuf::from(uri),
+                            save_format,
+                            failures,
+                        ),
+                        Err(e) => this.convert_failed(e.to_string(), temp_dir),
+                    }
+                }
+            ));
+            return;
+        }
+
+        self.convert_clean(temp_dir);
+        self.imp().scheduler.take();
+        if !failures.is_empty() {
+            self.show_failure_report(failures, save_format, path.clone());
+        }
         let toast = adw::Toast::new(&gettext("Image converted"));
         toast.set_button_label(Some(&gettext("Open")));
-        toast.connect_button_clicked(move |_| {
-            let p = path.clone();
-            runtime().spawn(async move {
-                match save_format {
-                    OutputType::Compression(CompressionType::Directory) => {
-                        ashpd::desktop::open_uri::OpenDirectoryRequest::default()
-                            .send(&std::fs::File::open(&p).unwrap().as_fd())
-                            .await
-                            .ok();
+        toast.connect_button_clicked(clone!(
+            #[weak(rename_to=this)]
+            self,
+            move |_| {
+                // Uploaded outputs have no local file to hand over.
+                if gio::File::for_commandline_arg(&path).path().is_none() {
+                    gtk::UriLauncher::new(&path.to_string_lossy()).launch(
+                        Some(&this),
+                        gio::Cancellable::NONE,
+                        |_| {},
+                    );
+                    return;
+                }
+                let file = match std::fs::File::open(&path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        this.show_toast(&format!("{}: {e}", path.display()));
+                        return;
                     }
-                    _ => {
-                        ashpd::desktop::open_uri::OpenFileRequest::default()
-                            .ask(true)
-                            .send_file(&std::fs::File::open(&p).unwrap().as_fd())
-                            .await
-                            .ok();
+                };
+                runtime().spawn(async move {
+                    match save_format {
+                        OutputType::Compression(CompressionType::Directory) => {
+                            ashpd::desktop::open_uri::OpenDirectoryRequest::default()
+                                .send(&file.as_fd())
+                                .await
+                                .ok();
+                        }
+                        _ => {
+                            ashpd::desktop::open_uri::OpenFileRequest::default()
+                                .ask(true)
+                                .send_file(&file.as_fd())
+                                .await
+                                .ok();
+                        }
                     }
-                }
-            });
-        });
+                });
+            }
+        ));
         self.imp().toast_overlay.add_toast(toast);
+        self.show_conflict_report();
         self.switch_to_stack_convert();
     }
 
-    fn convert_clean(&self, temp_dir_path: String) {
-        clean_dir(temp_dir_path);
+    fn show_conflict_report(&self) {
+        let report = self.imp().conflict_report.take();
+        if report.is_empty() {
+            return;
+        }
+
+        let title = match (report.skipped.len(), report.renamed.len()) {
+            (skipped, 0) => ngettext(
+                "{} existing file skipped",
+                "{} existing files skipped",
+                skipped as u32,
+            )
+            .replace("{}", &skipped.to_string()),
+            (0, renamed) => ngettext(
+                "{} file renamed to keep an existing one",
+                "{} files renamed to keep existing ones",
+                renamed as u32,
+            )
+            .replace("{}", &renamed.to_string()),
+            (skipped, renamed) => gettext("{skipped} files skipped, {renamed} renamed")
+                .replace("{skipped}", &skipped.to_string())
+                .replace("{renamed}", &renamed.to_string()),
+        };
+
+        let details = report
+            .skipped
+            .iter()
+            .map(|name| gettext("{} was skipped").replace("{}", &name.display().to_string()))
+            .chain(report.renamed.iter().map(|(name, new_name)| {
+                gettext("{name} was saved as {new_name}")
+                    .replace("{name}", &name.display().to_string())
+                    .replace("{new_name}", &new_name.display().to_string())
+            }))
+            .join("\n");
+
+        let toast = adw::Toast::new(&title);
+        toast.set_button_label(Some(&gettext("Details")));
+        toast.connect_button_clicked(clone!(
+            #[weak(rename_to=this)]
+            self,
+            move |_| {
+                let dialog =
+                    adw::AlertDialog::new(Some(&gettext("Existing Files")), Some(&details));
+                dialog.add_response("ok", &gettext("_Close"));
+                dialog.present(Some(&this));
+            }
+        ));
+        self.imp().toast_overlay.add_toast(toast);
+    }
+
+    fn show_failure_report(
+        &self,
+        failures: Vec<(InputFile, String)>,
+        save_format: OutputType,
+        path: PathBuf,
+    ) {
+        let failed_files = failures
+            .iter()
+            .map(|(f, _)| f.clone())
+            .unique_by(|f| f.path())
+            .collect_vec();
+
+        let dialog = adw::AlertDialog::new(
+            Some(&gettext("Some Images Could Not Be Converted")),
+            Some(
+                &ngettext(
+                    "{} image failed to convert.",
+                    "{} images failed to convert.",
+                    failed_files.len() as u32,
+                )
+                .replace("{}", &failed_files.len().to_string()),
+            ),
+        );
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+        list.add_css_class("boxed-list");
+        for (file, error) in failures.iter() {
+            let name = file
+                .path()
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| file.display_path());
+            let row = adw::ExpanderRow::builder()
+                .title(glib::markup_escape_text(&name))
+                .subtitle(glib::markup_escape_text(&file.display_path()))
+                .build();
+
+            let text = gtk::Label::new(Some(error.trim()));
+            text.set_margin_top(12);
+            text.set_margin_bottom(12);
+            text.set_margin_start(12);
+            text.set_margin_end(12);
+            text.set_xalign(0.0);
+            text.set_selectable(true);
+            text.add_css_class("monospace");
+            text.set_wrap(true);
+            text.set_wrap_mode(gtk::pango::WrapMode::WordChar);
+            row.add_row(&text);
+            list.append(&row);
+        }
+
+        let sw = gtk::ScrolledWindow::new();
+        sw.set_min_content_height(200);
+        sw.set_max_content_height(400);
+        sw.set_propagate_natural_height(true);
+        sw.set_child(Some(&list));
+        dialog.set_extra_child(Some(&sw));
+
+        let report = failures
+            .iter()
+            .map(|(f, e)| format!("{}\n{}", f.display_path(), e.trim()))
+            .join("\n\n");
+
+        dialog.add_responses(&[
+            ("ok", &gettext("_Close")),
+            ("copy", &gettext("_Copy to Clipboard")),
+            ("retry", &gettext("_Retry Failed")),
+        ]);
+        dialog.set_response_appearance("retry", adw::ResponseAppearance::Suggested);
+        dialog.connect_response(
+            None,
+            clone!(
+                #[weak(rename_to=this)]
+                self,
+                move |d, response_id| {
+                    match response_id {
+                        "copy" => {
+                            this.clipboard().set_text(&report);
+                            this.show_toast(&gettext("Error copied to clipboard"));
+                        }
+                        "retry" => this.retry_failed(failed_files.clone(), save_format, &path),
+                        _ => {}
+                    }
+                    d.close();
+                }
+            ),
+        );
+        dialog.present(Some(self));
+    }
+
+    fn convert_clean(&self, temp_dir: Rc<TempDir>) {
+        // The directory is deleted once no job holds on to it anymore.
+        drop(temp_dir);
         self.imp().convert_button.set_sensitive(true);
     }
 
@@ -1553,19 +2301,7 @@ impl ConvertOperations for AppWindow {
                 self,
                 move |_, response_id| {
                     if response_id == "stop" {
-                        this.imp()
-                            .is_canceled
-                            .store(true, std::sync::atomic::Ordering::SeqCst);
-                        let mut current_jobs = this.imp().current_jobs.borrow_mut();
-                        for x in current_jobs.iter() {
-                            match x.kill() {
-                                Ok(_) => {}
-                                Err(_) => {
-                                    x.wait().ok();
-                                }
-                            }
-                        }
-                        current_jobs.clear();
+                        this.stop_converting();
                         this.switch_to_stack_convert();
                         this.show_toast(&gettext("Converting Cancelled"));
                     }
@@ -1578,6 +2314,10 @@ impl ConvertOperations for AppWindow {
 }
 
 impl ConvertArguments for AppWindow {
+    fn get_name_template_argument(&self) -> Result<NameTemplate, String> {
+        NameTemplate::parse(&self.imp().name_template.text())
+    }
+
     fn get_quality_argument(&self) -> usize {
         self.imp().quality.value() as usize
     }
@@ -1597,6 +2337,11 @@ impl ConvertArguments for AppWindow {
         }
     }
 
+    fn get_metadata_argument(&self) -> MetadataPolicy {
+        MetadataPolicy::from_index(self.imp().metadata_policy.selected() as usize)
+            .unwrap_or_default()
+    }
+
     fn get_resize_argument(&self) -> ResizeArgument {
         let imp = self.imp();
 
@@ -1649,14 +2394,24 @@ impl WindowUI for AppWindow {
             .unwrap_or(self.load_selected_output());
 
         let new_options = gtk::StringList::new(&[]);
-        let new_list = FileType::output_formats().collect_vec();
-        for ft in new_list.iter() {
+        for ft in Self::available_outputs() {
             new_options.append(&ft.as_display_string());
         }
         self.imp().output_filetype.set_model(Some(&new_options));
-        if let Some(index) = new_list.into_iter().position(|p| p == &previous_option) {
-            self.imp().output_filetype.set_selected(index as u32);
-        }
+        self.select_output(previous_option);
+
+        let unavailable = FileType::output_formats()
+            .filter(|&&f| !backend::can_write(f))
+            .map(|f| f.as_display_string())
+            .join(", ");
+        self.imp()
+            .output_filetype
+            .set_subtitle(&if unavailable.is_empty() {
+                String::new()
+            } else {
+                gettext("Unavailable with the installed ImageMagick: {}")
+                    .replace("{}", &unavailable)
+            });
         self.update_compression_options();
     }
 
@@ -1665,6 +2420,7 @@ impl WindowUI for AppWindow {
         let multiple_files = files.len() > 1;
         let multiple_frames = multiple_files || files.iter().map(|i| i.frames()).sum::<usize>() > 1;
         let output_option = self.selected_output().unwrap();
+        self.imp().continue_on_error.set_visible(multiple_files);
         if multiple_files || multiple_frames && !output_option.supports_animation() {
             let previous_option = self
                 .selected_compression()
@@ -1678,14 +2434,39 @@ impl WindowUI for AppWindow {
             self.imp()
                 .output_compression
                 .set_visible(!pdf_selected || !single_pdf_enabled);
+            self.imp()
+                .name_template
+                .set_visible(!pdf_selected || !single_pdf_enabled);
+            self.imp().keep_folders.set_visible(
+                (!pdf_selected || !single_pdf_enabled)
+                    && files.iter().any(|f| f.relative_dir().is_some()),
+            );
 
-            match previous_option {
-                CompressionType::Zip => self.imp().output_compression_value.set_active(true),
-                _ => self.imp().output_compression_value.set_active(false),
-            }
+            self.select_compression(previous_option);
+
+            let directory = self.selected_compression() == Some(CompressionType::Directory);
+            let mirroring = self.selected_mirror_mode() != MirrorMode::Off;
+            self.imp().mirror_mode.set_visible(directory);
+            self.imp()
+                .delete_orphans
+                .set_visible(directory && mirroring);
+            // Mirroring replaces the outputs that changed without asking.
+            self.imp()
+                .conflict_policy
+                .set_visible(directory && !mirroring);
+            self.imp().compression_level.set_visible(
+                self.selected_compression()
+                    .is_some_and(|c| c.is_compression()),
+            );
         } else {
             self.imp().output_compression.set_visible(false);
             self.imp().single_pdf.set_visible(false);
+            self.imp().name_template.set_visible(false);
+            self.imp().keep_folders.set_visible(false);
+            self.imp().conflict_policy.set_visible(false);
+            self.imp().mirror_mode.set_visible(false);
+            self.imp().delete_orphans.set_visible(false);
+            self.imp().compression_level.set_visible(false);
         }
     }
 
@@ -1694,9 +2475,7 @@ impl WindowUI for AppWindow {
 
         let input_files = self.active_files();
         let input_filetypes: Vec<FileType> = input_files.iter().map(|inf| inf.kind()).collect();
-        let Some(output_filetype) =
-            FileType::output_formats().nth(imp.output_filetype.selected() as usize)
-        else {
+        let Some(output_filetype) = self.selected_output() else {
             return;
         };
 
@@ -1708,10 +2487,8 @@ impl WindowUI for AppWindow {
             imp.quality_row.set_visible(true);
         }
 
-        if input_filetypes
-            .iter()
-            .any(|input_file| input_file.supports_alpha())
-        {
+        // Opaque images are left as they are, whatever the background.
+        if input_files.iter().any(|input_file| input_file.has_alpha()) {
             imp.bgcolor_row.set_visible(true);
 
             if output_filetype.supports_alpha() {
@@ -1869,6 +2646,10 @@ impl WindowUI for AppWindow {
 
             let image_thumbnail =
                 ImageThumbnail::new(f.pixbuf().as_ref(), &caption, w as u32, h as u32);
+            let summary = f.info().summary();
+            if !summary.is_empty() {
+                image_thumbnail.set_tooltip_text(Some(&summary));
+            }
 
             let image_flow_box_child = gtk::FlowBoxChild::new();
             image_flow_box_child.set_child(Some(&image_thumbnail));
@@ -1920,6 +2701,10 @@ impl WindowUI for AppWindow {
 
                     let image_thumbnail =
                         ImageThumbnail::new(f.pixbuf().as_ref(), &caption, w as u32, h as u32);
+                    let summary = f.info().summary();
+                    if !summary.is_empty() {
+                        image_thumbnail.set_tooltip_text(Some(&summary));
+                    }
 
                     let image_flow_box_child = gtk::FlowBoxChild::new();
                     image_flow_box_child.set_child(Some(&image_thumbnail));
@@ -2033,6 +2818,205 @@ impl StackNavigation for AppWindow {
     }
 }
 
+impl PresetOperations for AppWindow {
+    fn update_preset_options(&self, selected: Option<&str>) {
+        let imp = self.imp();
+
+        let presets = Preset::load_all(&imp.settings);
+        let names = gtk::StringList::new(&[&gettext("Custom")]);
+        for preset in presets.iter() {
+            names.append(&preset.name);
+        }
+        imp.preset_row.set_model(Some(&names));
+
+        if let Some(index) = selected.and_then(|s| presets.iter().position(|p| p.name == s)) {
+            imp.preset_row.set_selected(index as u32 + 1);
+        }
+        self.update_preset_actions();
+    }
+
+    fn update_preset_actions(&self) {
+        let selected = self.selected_preset().is_some();
+        for name in ["preset-rename", "preset-delete"] {
+            if let Some(action) = self.lookup_action(name).and_downcast::<gio::SimpleAction>() {
+                action.set_enabled(selected);
+            }
+        }
+    }
+
+    fn selected_preset(&self) -> Option<Preset> {
+        let imp = self.imp();
+
+        // The first entry stands for the options as they are.
+        if imp.preset_row.selected() == 0 {
+            return None;
+        }
+        let name = imp
+            .preset_row
+            .selected_item()
+            .and_downcast::<gtk::StringObject>()?
+            .string();
+        Preset::find(&imp.settings, &name)
+    }
+
+    fn current_preset(&self, name: String) -> Preset {
+        let compression = CompressionType::possible_output(false)
+            .nth(self.imp().output_compression.selected() as usize)
+            .copied()
+            .unwrap_or(CompressionType::Directory);
+
+        Preset {
+            name,
+            output_type: self.selected_output().unwrap(),
+            quality: self.get_quality_argument(),
+            resize_arg: self.get_resize_argument(),
+            filter: self.get_filter_argument().unwrap_or(ResizeFilter::Default),
+            background: self.get_bgcolor_argument(),
+            dpi: self.get_dpi_argument(),
+            compression,
+            metadata: self.get_metadata_argument(),
+        }
+    }
+
+    fn apply_preset(&self, preset: &Preset) {
+        let imp = self.imp();
+
+        // Changing the output resets the background, so it goes first.
+        self.select_output(preset.output_type);
+
+        imp.quality.set_value(preset.quality as f64);
+        imp.dpi_value.set_text(&preset.dpi.to_string());
+        imp.bgcolor.set_rgba(&preset.background.into());
+        self.select_compression(preset.compression);
+        if let Some(index) = MetadataPolicy::iterator().position(|&p| p == preset.metadata) {
+            imp.metadata_policy.set_selected(index as u32);
+        }
+
+        match preset.filter {
+            ResizeFilter::Default => imp.resize_filter_default.set_active(true),
+            ResizeFilter::Point => imp.resize_filter_pixel.set_active(true),
+        }
+
+        match preset.resize_arg {
+            ResizeArgument::Percentage { width, height } => {
+                if width != height {
+                    imp.link_axis.set_active(false);
+                    imp.link_axis.set_icon_name("chain-link-loose-symbolic");
+                }
+                imp.resize_scale_height_value.set_text(&height.to_string());
+                imp.resize_scale_width_value.set_text(&width.to_string());
+                imp.resize_type.set_selected(0);
+            }
+            ResizeArgument::ExactPixels { width, height } => {
+                imp.link_axis.set_active(false);
+                imp.link_axis.set_icon_name("chain-link-loose-symbolic");
+                imp.resize_width_value.set_text(&width.to_string());
+                imp.resize_height_value.set_text(&height.to_string());
+                imp.resize_type.set_selected(1);
+            }
+        }
+    }
+
+    fn preset_name_dialog<F>(&self, heading: &str, name: &str, callback: F)
+    where
+        F: Fn(&AppWindow, String) + 'static,
+    {
+        let dialog = adw::AlertDialog::new(Some(heading), None);
+
+        let entry = gtk::Entry::new();
+        entry.set_text(name);
+        entry.set_activates_default(true);
+        entry.set_placeholder_text(Some(&gettext("Preset Name")));
+        dialog.set_extra_child(Some(&entry));
+
+        dialog.add_response("cancel", &gettext("_Cancel"));
+        dialog.add_response("save", &gettext("_Save"));
+        dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("save"));
+        dialog.set_response_enabled("save", !name.trim().is_empty());
+
+        entry.connect_changed(clone!(
+            #[weak]
+            dialog,
+            move |e| {
+                dialog.set_response_enabled("save", !e.text().trim().is_empty());
+            }
+        ));
+        dialog.connect_response(
+            None,
+            clone!(
+                #[weak(rename_to=this)]
+                self,
+                #[weak]
+                entry,
+                move |_, response_id| {
+                    let name = entry.text().trim().to_owned();
+                    if response_id == "save" && !name.is_empty() {
+                        callback(&this, name);
+                    }
+                }
+            ),
+        );
+        dialog.present(Some(self));
+        entry.grab_focus();
+    }
+
+    fn preset_new_dialog(&self) {
+        self.preset_name_dialog(&gettext("New Preset"), "", |this, name| {
+            let preset = this.current_preset(name.clone());
+            match preset.save(&this.imp().settings) {
+                Ok(()) => this.update_preset_options(Some(&name)),
+                Err(_) => this.show_toast(&gettext("Could not save preset")),
+            }
+        });
+    }
+
+    fn preset_rename_dialog(&self) {
+        let Some(preset) = self.selected_preset() else {
+            return;
+        };
+        self.preset_name_dialog(
+            &gettext("Rename Preset"),
+            &preset.name,
+            move |this, name| match Preset::rename(&this.imp().settings, &preset.name, &name) {
+                Ok(()) => this.update_preset_options(Some(&name)),
+                Err(e) => this.show_toast(&e),
+            },
+        );
+    }
+
+    fn preset_delete_dialog(&self) {
+        let Some(preset) = self.selected_preset() else {
+            return;
+        };
+
+        let dialog = adw::AlertDialog::new(
+            Some(&gettext("Delete Preset?")),
+            Some(&gettext("“{}” will be removed permanently.").replace("{}", &preset.name)),
+        );
+        dialog.add_response("cancel", &gettext("_Cancel"));
+        dialog.add_response("delete", &gettext("_Delete"));
+        dialog.set_response_appearance("delete", adw::ResponseAppearance::Destructive);
+        dialog.connect_response(
+            None,
+            clone!(
+                #[weak(rename_to=this)]
+                self,
+                move |_, response_id| {
+                    if response_id != "delete" {
+                        return;
+                    }
+                    match Preset::delete(&this.imp().settings, &preset.name) {
+                        Ok(()) => this.update_preset_options(None),
+                        Err(_) => this.show_toast(&gettext("Could not save preset")),
+                    }
+                }
+            ),
+        );
+        dialog.present(Some(self));
+    }
+}
+
 impl SettingsStore for AppWindow {
     fn save_window_size(&self) -> Result<(), glib::BoolError> {
         let imp = self.imp();
@@ -2069,6 +3053,28 @@ impl SettingsStore for AppWindow {
             .set_int("quality", imp.quality.value() as i32)?;
         imp.settings
             .set_int("dpi", imp.dpi_value.text().parse().unwrap())?;
+        imp.settings
+            .set_boolean("continue-on-error", imp.continue_on_error_value.is_active())?;
+        imp.settings
+            .set_boolean("keep-folders", imp.keep_folders_value.is_active())?;
+        imp.settings
+            .set_enum("conflict-policy", imp.conflict_policy.selected() as i32)?;
+        imp.settings
+            .set_enum("mirror-mode", imp.mirror_mode.selected() as i32)?;
+        imp.settings
+            .set_enum("metadata-policy", imp.metadata_policy.selected() as i32)?;
+        imp.settings.set_boolean(
+            "mirror-delete-orphans",
+            imp.delete_orphans_value.is_active(),
+        )?;
+        imp.settings.set_int(
+            "archive-compression-level",
+            imp.compression_level.value() as i32,
+        )?;
+        if self.get_name_template_argument().is_ok() {
+            imp.settings
+                .set_string("filename-template", &imp.name_template.text())?;
+        }
 
         Ok(())
     }
@@ -2078,6 +3084,22 @@ impl SettingsStore for AppWindow {
 
         imp.quality.set_value(imp.settings.int("quality") as f64);
         imp.dpi_value.set_text(&imp.settings.int("dpi").to_string());
+        imp.continue_on_error_value
+            .set_active(imp.settings.boolean("continue-on-error"));
+        imp.keep_folders_value
+            .set_active(imp.settings.boolean("keep-folders"));
+        imp.name_template
+            .set_text(&imp.settings.string("filename-template"));
+        imp.conflict_policy
+            .set_selected(imp.settings.enum_("conflict-policy") as u32);
+        imp.mirror_mode
+            .set_selected(imp.settings.enum_("mirror-mode") as u32);
+        imp.metadata_policy
+            .set_selected(imp.settings.enum_("metadata-policy") as u32);
+        imp.delete_orphans_value
+            .set_active(imp.settings.boolean("mirror-delete-orphans"));
+        imp.compression_level
+            .set_value(imp.settings.int("archive-compression-level") as f64);
     }
 
     fn save_selected_output(&self) -> Result<(), glib::BoolError> {
@@ -2124,15 +3146,40 @@ impl SettingsStore for AppWindow {
 }
 
 impl FileOperations for AppWindow {
-    fn open_files(&self, files: Vec<Option<InputFile>>) {
-        let files = files.into_iter().flatten().collect_vec();
-        if files.is_empty() {
+    fn open_files(&self, files: Vec<InputFile>, rejected: Vec<RejectedFile>) {
+        if !rejected.is_empty() {
+            self.show_rejected_files(rejected);
+        } else if files.is_empty() {
             self.show_toast(&gettext("Unsupported filetype"));
+        }
+        if files.is_empty() {
             return;
         }
         self.add_success_wrapper(files);
     }
 
+    fn import_files(&self, files: Vec<gio::File>) {
+        self.open_load();
+        glib::spawn_future_local(clone!(
+            #[weak(rename_to=this)]
+            self,
+            async move {
+                let (files, rejected) = this.load_inputs(files).await;
+                if files.is_empty() {
+                    this.switch_back_from_loading();
+                    this.imp()
+                        .all_images_stack
+                        .set_visible_child_name("all_images");
+                    match this.files().is_empty() {
+                        true => this.switch_to_stack_welcome(),
+                        false => this.switch_to_stack_convert(),
+                    }
+                }
+                this.open_files(files, rejected);
+            }
+        ));
+    }
+
     fn save_error(&self, error: Option<&str>) {
         if let Some(s) = error {
             self.show_toast(s);
@@ -2140,12 +3187,18 @@ impl FileOperations for AppWindow {
     }
 
     fn save_files(&self) {
+        if let Err(e) = self.get_name_template_argument() {
+            self.show_toast(&e);
+            return;
+        }
+
         let files = self.active_files();
         let multiple_files = files.len() > 1;
         let multiple_frames = multiple_files || files.iter().map(|i| i.frames()).sum::<usize>() > 1;
-        let output_option = self.selected_output().unwrap();
+        let Some(output_option) = self.selected_output() else {
+            return;
+        };
         let first_file_path = files.first().unwrap().path();
-        let first_file_path = std::path::Path::new(&first_file_path);
         let (save_format, default_name) =
             if multiple_files || multiple_frames && !output_option.supports_animation() {
                 if matches!(output_option, FileType::Pdf) && self.imp().single_pdf_value.state() {
@@ -2157,22 +3210,24 @@ impl FileOperations for AppWindow {
                     )
                 }
             } else {
+                // Only a suggestion, the chosen name is used as it is.
                 let file_stem = first_file_path
                     .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_owned();
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned();
 
                 (OutputType::File(output_option), file_stem)
             };
 
-        let default_folder = first_file_path
+        // Extracted files live in a temporary folder, suggest the archive's.
+        let first_file_root = files.first().unwrap().root();
+        let default_folder = first_file_root
+            .as_deref()
+            .unwrap_or(&first_file_path)
             .parent()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_owned();
+            .map(Path::to_owned)
+            .unwrap_or_default();
 
         if save_format != OutputType::Compression(CompressionType::Directory) {
             FileChooser::choose_output_file_wrapper(
@@ -2203,6 +3258,15 @@ impl FileOperations for AppWindow {
         );
     }
 
+    fn add_folder_dialog(&self) {
+        FileChooser::open_folder_wrapper(
+            self,
+            AppWindow::open_load,
+            AppWindow::add_success_wrapper,
+            AppWindow::open_error,
+        );
+    }
+
     fn open_error(&self, error: Option<&str>) {
         if error.is_some() {
             self.switch_to_stack_invalid_image();